@@ -59,6 +59,123 @@ pacman_cmd=pacman
 sudo_cmd=sudo
 # mirror_base: when mirror=github, base URL for repos
 # mirror_base=https://github.com/archlinux-aur
+# reuse_temp: keep cloned/built trees in cache/temp between runs (true | false).
+# Saves re-cloning, but the tree grows until cleaned (turbo -Scc) and existing
+# clones are not re-pulled, so delete a package's dir to pick up a newer PKGBUILD.
+# reuse_temp=false
+# per_run_temp: clone and build under cache/temp/<pid>-<timestamp> instead of
+# cache/temp itself, so several turbo runs at once don't wipe each other's trees.
+# The subdir is removed when the run ends; one left with failed builds or -Sw
+# downloads is removed by the next turbo run, unless reuse_temp is set.
+# turbo -Scc still wipes all of cache/temp.
+# per_run_temp=false
+# clone_depth: git clone --depth for AUR repos; 0 clones the full history
+# (needed to check out older commits of a PKGBUILD)
+# clone_depth=1
+# max_retries: how often to retry fetching sources (makepkg --verifysource) after
+# a download error; a checksum or signature mismatch is never retried
+# max_retries=2
+# edit: open the file manager before building: ask | always | never
+# (--edit / --noedit override it per run)
+# edit=ask
+# fm_wait: how to tell you're done in the file manager: auto | exit | prompt
+# exit waits for it to close (nnn, lf, ranger); prompt launches it and waits for
+# Enter, for GUI file managers that return immediately; auto picks by name
+# fm_wait=auto
+# diffprog: viewer for PKGBUILD changes since the last build (delta, difft, ...)
+# falls back to git diff --no-index when unset or not installed
+# diffprog=delta
+# source_rewrite: redirect dead upstream URLs in PKGBUILD source=() arrays.
+# Off unless set; it edits the PKGBUILD before building. May be repeated.
+# source_rewrite=https://downloads\.sourceforge\.net/ => https://netcologne.dl.sourceforge.net/
+# sync_db_max_age_hours: warn before building AUR packages when the sync DBs are
+# older than this; require_fresh_db=true refuses instead (or pass --require-fresh-db)
+# sync_db_max_age_hours=24
+# require_fresh_db=false
+# timeout: seconds allowed for network requests and git clones (--timeout overrides)
+# timeout=60
+# keep_failed_builds: at the end of a run, remove only the trees that built fine
+# and keep failed ones (sources, logs) in cache/temp for inspection; they are
+# still wiped at the start of the next run unless reuse_temp=true
+# keep_failed_builds=false
+# repo_priority: for -S targets found in several sync repos, install from the
+# first repo listed here that has them (otherwise pacman's first match, or a
+# prompt with --choose-repo)
+# repo_priority=custom,core,extra
+# news_check: before -Syu, show Arch Linux news published since the last
+# acknowledged item and ask before upgrading (turbo --news shows it on demand)
+# news_check=false
+# strict_config: fail on unknown keys in this file instead of ignoring them
+# (catches typos like file_maneger=lf); --strict-config does the same per run
+# strict_config=false
+# default_selection: what the -Syu update menu picks when you just press Enter:
+# all (update everything) | none (nothing is updated unless you choose it)
+# default_selection=all
+# color_theme: output colors: default | light (for light terminal backgrounds) |
+# monochrome (bold/italic only) | high-contrast (--color-theme per run)
+# color_theme=default
+# build_nice: run makepkg with nice -n N (0-19) and ionice -c 3 so AUR builds
+# don't starve interactive work; unset builds at normal priority (--nice N per run)
+# build_nice=10
+# group.NAME: an informal group of AUR packages that -Syu --ignoregroup NAME skips
+# (repo groups are passed through to pacman as-is)
+# group.heavy=chromium-wayland-vaapi electron-bin
+# pin.PKGBASE: build that AUR package from this commit, tag or branch of its git
+# repo instead of the latest; pinned packages are always cloned in full
+# pin.foo-bin=3f2c1a9
+# build_user: when turbo runs as root (containers, CI), build as this user instead;
+# makepkg refuses to run as root. The build tree is chowned to it, so build_dir must
+# be reachable by it (e.g. /tmp), and it needs passwordless sudo for makepkg -s.
+# build_user=builder
+# sudoflags: extra arguments for every sudo call, split on whitespace
+# (e.g. -A to use an askpass helper); --sudoflags overrides it per run
+# sudoflags=-A
+# min_free_mb: free space (MiB) the build dir needs before building; below it turbo
+# asks first (and aborts under --noconfirm). Unset, turbo only warns below 2048 MiB.
+# 0 disables the check.
+# min_free_mb=2048
+# regen_srcinfo: regenerate .SRCINFO after the edit step (true | false). It is
+# already skipped for checkouts whose PKGBUILD is unchanged; false always trusts
+# the committed .SRCINFO (--no-regen-srcinfo per run)
+# regen_srcinfo=true
+# output_dir: where -P writes needupdate.json (default: ~/<root_dir_name>;
+# --output-dir per run)
+# output_dir=~/turbo
+# clone_dir: keep AUR git clones here between runs (default: unset, clones are
+# thrown away with the temp tree). Each run pulls them and builds from a copy,
+# so their history can be inspected with git log. --clone-dir per run.
+# clone_dir=~/aur
+# post_install_hook: executable run after an install where every package built
+# and installed, with the installed package names as arguments. A failing hook
+# only warns.
+# post_install_hook=~/.local/bin/turbo-post-install
+# pre_build_hook: executable run once before the build loop, with the build
+# dir as first argument and the pkgbases about to be built after it (e.g. to
+# prime a source mirror). A non-zero exit stops the run unless
+# pre_build_hook_abort=false, which only warns.
+# pre_build_hook=~/.local/bin/turbo-pre-build
+# pre_build_hook_abort=true
+# verbose: extra diagnostics, such as fields the AUR RPC returns that turbo does
+# not know about (AURWRAP_VERBOSE=1 per run)
+# verbose=false
+# prefer_source: AUR sources to try in order, falling back to the next one for
+# packages the earlier ones lack or fail to clone (official = aur.archlinux.org,
+# github = the GitHub mirror). Unset, only the mirror setting above is used.
+# prefer_source=github,official
+# build_dir: clone and build somewhere else, e.g. a tmpfs like /tmp or /dev/shm.
+# turbo works in a "turbo" subdirectory of it; cached packages stay in cache/.
+# build_dir=/tmp
+# include_debug: also install the -debug packages makepkg splits out (true | false)
+# include_debug=false
+# github_token: sent as "Authorization: Bearer <token>" to a private GitHub mirror
+# (AURWRAP_GITHUB_TOKEN overrides it). Extra headers can be added per line:
+# github_token=
+# mirror_header=X-Proxy-Auth: secret
+# use_keyring: read github_token from the system keyring (Secret Service, e.g.
+# GNOME Keyring or KWallet) instead, falling back to the above when it is
+# unavailable. Store it with:
+#   secret-tool store --label='turbo GitHub token' service aurwrap username github_token
+# use_keyring=false
 EOF
 
   echo "Created default conf at ${CONF_FILE}"
@@ -1,29 +1,38 @@
 use crate::build::AurSource;
-use crate::config::Config;
+use crate::config::{Config, Secret};
+use crate::style::{command, dim, warn_icon, warning};
 use anyhow::{anyhow, Context, Result};
 use petgraph::algo::toposort;
 use petgraph::graph::DiGraph;
 use petgraph::graph::NodeIndex;
 use rayon::prelude::*;
-use reqwest::blocking::Client;
-use reqwest::StatusCode;
-use serde::Deserialize;
-use std::collections::{HashMap, HashSet, VecDeque};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const GITHUB_SRCINFO_TIMEOUT_SECS: u64 = 45;
+/// Cap on GitHub API branch lookups per run; unauthenticated clients get 60/hour.
+const GITHUB_BRANCH_LOOKUP_LIMIT: usize = 10;
+static GITHUB_BRANCH_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
 const GITHUB_SRCINFO_MAX_RETRIES: usize = 3;
 const GITHUB_SRCINFO_RETRY_DELAY_SECS: u64 = 2;
 
 #[derive(Debug, Deserialize)]
 pub struct AurMeta {
-    #[serde(rename = "resultcount")]
+    #[allow(dead_code)]
+    #[serde(rename = "resultcount", default)]
     pub resultcount: u32,
     pub results: Vec<AurInfo>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AurInfo {
     #[serde(rename = "Name")]
     pub name: String,
@@ -31,14 +40,123 @@ pub struct AurInfo {
     pub pkgbase: String,
     #[serde(rename = "Version")]
     pub version: String,
-    #[serde(rename = "Depends")]
+    #[serde(rename = "Description", default, deserialize_with = "lenient")]
+    pub description: Option<String>,
+    #[serde(rename = "URL", default, deserialize_with = "lenient")]
+    pub url: Option<String>, // upstream project page
+    #[serde(rename = "Depends", default, deserialize_with = "lenient")]
     pub depends: Option<Vec<String>>,
-    #[serde(rename = "MakeDepends")]
+    #[serde(rename = "MakeDepends", default, deserialize_with = "lenient")]
     pub makedepends: Option<Vec<String>>,
-    #[serde(rename = "CheckDepends")]
+    #[serde(rename = "CheckDepends", default, deserialize_with = "lenient")]
     pub checkdepends: Option<Vec<String>>,
+    #[serde(rename = "Replaces", default, deserialize_with = "lenient")]
+    pub replaces: Option<Vec<String>>,
+    #[serde(rename = "OutOfDate", default, deserialize_with = "lenient")]
+    pub out_of_date: Option<i64>, // unix time it was flagged; not in .SRCINFO
 }
 
+/// An optional RPC field of an unexpected type reads as absent instead of failing
+/// the whole response.
+fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// RPC result fields `AurInfo` reads or knowingly ignores; anything else is new.
+const KNOWN_RPC_FIELDS: &[&str] = &[
+    "ID",
+    "Name",
+    "PackageBaseID",
+    "PackageBase",
+    "Version",
+    "Description",
+    "URL",
+    "NumVotes",
+    "Popularity",
+    "OutOfDate",
+    "Maintainer",
+    "Submitter",
+    "FirstSubmitted",
+    "LastModified",
+    "URLPath",
+    "Depends",
+    "MakeDepends",
+    "CheckDepends",
+    "OptDepends",
+    "Conflicts",
+    "Provides",
+    "Replaces",
+    "Groups",
+    "License",
+    "Keywords",
+    "CoMaintainers",
+];
+
+/// Read an RPC response without letting one odd result sink the batch. Results
+/// that do not fit `AurInfo` are skipped with a warning; with `verbose`, fields
+/// this version does not know about are reported too.
+fn parse_rpc_response(body: serde_json::Value, verbose: bool) -> Result<AurMeta> {
+    if body.get("type").and_then(|t| t.as_str()) == Some("error") {
+        let msg = body
+            .get("error")
+            .and_then(|e| e.as_str())
+            .unwrap_or("unknown error");
+        return Err(anyhow!("AUR RPC error: {}", msg));
+    }
+    let Some(raw) = body.get("results").and_then(|r| r.as_array()) else {
+        return Err(anyhow!("Unexpected AUR RPC response: no results array"));
+    };
+    let mut unknown: HashSet<&str> = HashSet::new();
+    let mut results = Vec::with_capacity(raw.len());
+    for entry in raw {
+        if verbose {
+            if let Some(obj) = entry.as_object() {
+                unknown.extend(
+                    obj.keys()
+                        .map(String::as_str)
+                        .filter(|k| !KNOWN_RPC_FIELDS.contains(k)),
+                );
+            }
+        }
+        match AurInfo::deserialize(entry) {
+            Ok(info) => results.push(info),
+            Err(e) => {
+                let name = entry.get("Name").and_then(|n| n.as_str()).unwrap_or("?");
+                eprintln!(
+                    "{} {}",
+                    warn_icon(),
+                    warning().apply_to(format!(
+                        "Skipping unreadable AUR RPC result {}: {}",
+                        name, e
+                    ))
+                );
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        let mut unknown: Vec<&str> = unknown.into_iter().collect();
+        unknown.sort();
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            dim().apply_to(format!(
+                "AUR RPC returned unknown fields: {}",
+                unknown.join(", ")
+            ))
+        );
+    }
+    Ok(AurMeta {
+        resultcount: results.len() as u32,
+        results,
+    })
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct AurRpcResponse {
     #[serde(rename = "type")]
@@ -47,7 +165,148 @@ pub struct AurRpcResponse {
     pub meta: AurMeta,
 }
 
-fn aur_rpc_info(client: &Client, names: &[String]) -> Result<AurMeta> {
+/// Fields the AUR RPC can search by (`--by`).
+pub const SEARCH_FIELDS: &[&str] = &[
+    "name",
+    "name-desc",
+    "maintainer",
+    "depends",
+    "makedepends",
+    "optdepends",
+    "checkdepends",
+    "keywords",
+    "replaces",
+];
+
+/// Search the AUR RPC for `term` in the given field. The first term goes to the
+/// RPC; any further terms narrow the results by name/description locally.
+pub fn aur_search(
+    cfg: &Config,
+    client: &Client,
+    by: &str,
+    terms: &[String],
+) -> Result<Vec<AurInfo>> {
+    let Some(first) = terms.first() else {
+        return Ok(vec![]);
+    };
+    if AurSource::from_cfg(cfg) == AurSource::Github {
+        return Err(anyhow!(
+            "AUR search needs the official AUR RPC; it is not available on the GitHub mirror"
+        ));
+    }
+    let url = format!(
+        "https://aur.archlinux.org/rpc/?v=5&type=search&by={}&arg={}",
+        urlencoding::encode(by),
+        urlencoding::encode(first)
+    );
+    let body = send(client.get(&url))?.error_for_status()?.json()?;
+    let meta = parse_rpc_response(body, cfg.verbose)?;
+    let rest: Vec<String> = terms[1..].iter().map(|t| t.to_lowercase()).collect();
+    let mut results: Vec<AurInfo> = meta
+        .results
+        .into_iter()
+        .filter(|info| {
+            let haystack = format!(
+                "{} {}",
+                info.name,
+                info.description.as_deref().unwrap_or("")
+            )
+            .to_lowercase();
+            rest.iter().all(|t| haystack.contains(t))
+        })
+        .collect();
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(results)
+}
+
+/// AUR packages that list `name` in their depends or makedepends, mapped to the
+/// fields it appears in. Needs the RPC, like `aur_search`.
+pub fn aur_reverse_deps(
+    cfg: &Config,
+    client: &Client,
+    name: &str,
+) -> Result<BTreeMap<String, Vec<&'static str>>> {
+    let mut out: BTreeMap<String, Vec<&'static str>> = BTreeMap::new();
+    for field in ["depends", "makedepends"] {
+        for info in aur_search(cfg, client, field, &[name.to_string()])? {
+            out.entry(info.name).or_default().push(field);
+        }
+    }
+    Ok(out)
+}
+
+/// HTTP client for AUR/mirror requests; `timeout_secs` (--timeout) applies to every request.
+pub fn http_client(cfg: &Config) -> Result<Client> {
+    let mut builder = Client::builder().user_agent("aurwrap/0.1");
+    if let Some(t) = cfg.timeout_secs {
+        builder = builder.timeout(Duration::from_secs(t));
+    }
+    Ok(builder.build()?)
+}
+
+static VERBOSE_HTTP: AtomicBool = AtomicBool::new(false);
+
+/// --verbose-http: log every request `send` makes.
+pub fn set_verbose_http(on: bool) {
+    VERBOSE_HTTP.store(on, Ordering::Relaxed);
+}
+
+/// `request.send()`; under --verbose-http also logs method, URL, status and time
+/// taken. Credentials only travel in headers, which are never printed, but query
+/// values that look like tokens are masked anyway.
+pub fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    if !VERBOSE_HTTP.load(Ordering::Relaxed) {
+        return request.send();
+    }
+    let (client, request) = request.build_split();
+    let request = request?;
+    let line = format!("{} {}", request.method(), redact_url(request.url()));
+    let started = Instant::now();
+    let res = client.execute(request);
+    let outcome = match &res {
+        Ok(resp) => resp.status().to_string(),
+        Err(e) if e.is_timeout() => String::from("timed out"),
+        Err(_) => String::from("failed"),
+    };
+    eprintln!(
+        "  {} {} {}",
+        dim().apply_to("http"),
+        command().apply_to(line),
+        dim().apply_to(format!(
+            "{} in {} ms",
+            outcome,
+            started.elapsed().as_millis()
+        ))
+    );
+    res
+}
+
+fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("redacted"));
+    }
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| {
+                let secret = ["token", "key", "secret", "password"]
+                    .iter()
+                    .any(|s| k.to_lowercase().contains(s));
+                let v = if secret {
+                    String::from("redacted")
+                } else {
+                    v.into_owned()
+                };
+                (k.into_owned(), v)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn aur_rpc_info(cfg: &Config, client: &Client, names: &[String]) -> Result<AurMeta> {
     if names.is_empty() {
         return Ok(AurMeta {
             resultcount: 0,
@@ -59,8 +318,22 @@ fn aur_rpc_info(client: &Client, names: &[String]) -> Result<AurMeta> {
         url.push_str("&arg[]=");
         url.push_str(&urlencoding::encode(n));
     }
-    let meta: AurMeta = client.get(&url).send()?.error_for_status()?.json()?;
-    Ok(meta)
+    let body = send(client.get(&url))?.error_for_status()?.json()?;
+    parse_rpc_response(body, cfg.verbose)
+}
+
+/// Which of `names` the AUR has, from one RPC info query. Only the official RPC
+/// is asked; with the GitHub mirror this would cost API requests, so it is empty.
+pub fn names_in_aur(cfg: &Config, names: &[String]) -> Result<HashSet<String>> {
+    if AurSource::from_cfg(cfg) == AurSource::Github {
+        return Ok(HashSet::new());
+    }
+    let client = http_client(cfg)?;
+    Ok(aur_rpc_info(cfg, &client, names)?
+        .results
+        .into_iter()
+        .map(|info| info.name)
+        .collect())
 }
 
 pub fn aur_info_batch(
@@ -76,29 +349,129 @@ pub fn aur_info_batch(
     Ok(map)
 }
 
-fn strip_version(dep: &str) -> String {
+pub fn strip_version(dep: &str) -> String {
     // foo>=1.2 -> foo
-    dep.split(|c| c == '<' || c == '>' || c == '=')
-        .next()
-        .unwrap_or(dep)
-        .to_string()
+    dep.split(['<', '>', '=']).next().unwrap_or(dep).to_string()
+}
+
+/// Names from `depends=` only: what has to be installed alongside the package.
+pub fn runtime_dep_names(info: &AurInfo) -> Vec<String> {
+    info.depends
+        .iter()
+        .flatten()
+        .map(|s| strip_version(s))
+        .collect()
+}
+
+/// The package's page on the AUR web interface.
+pub fn aur_page_url(name: &str) -> String {
+    format!(
+        "https://aur.archlinux.org/packages/{}",
+        urlencoding::encode(name)
+    )
 }
 
-fn resolve_dep_names(info: &AurInfo) -> Vec<String> {
-    let mut out = vec![];
-    if let Some(v) = &info.depends {
-        out.extend(v.iter().map(|s| strip_version(s)));
+pub fn resolve_dep_names(info: &AurInfo) -> Vec<String> {
+    dep_specs(info).map(strip_version).collect()
+}
+
+/// depends, makedepends and checkdepends as written, version constraints included.
+pub fn dep_specs(info: &AurInfo) -> impl Iterator<Item = &str> {
+    [&info.depends, &info.makedepends, &info.checkdepends]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(String::as_str)
+}
+
+/// For installed packages that are no longer in the AUR, look for AUR packages
+/// that declare `replaces=` on the old name (i.e. the package was renamed).
+/// Returns old name -> replacement. Needs the RPC, so nothing is found on the
+/// GitHub mirror.
+pub fn find_replacements(
+    cfg: &Config,
+    client: &Client,
+    missing: &[String],
+) -> Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+    if AurSource::from_cfg(cfg) == AurSource::Github {
+        return Ok(out);
     }
-    if let Some(v) = &info.makedepends {
-        out.extend(v.iter().map(|s| strip_version(s)));
+    for old in missing {
+        let candidates: Vec<String> =
+            aur_search(cfg, client, "replaces", std::slice::from_ref(old))?
+                .into_iter()
+                .map(|info| info.name)
+                .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+        // Search results don't carry Replaces; confirm with a full info lookup
+        let infos = aur_info_batch(cfg, client, candidates)?;
+        let replacement = infos.values().find(|info| {
+            info.replaces
+                .iter()
+                .flatten()
+                .any(|r| strip_version(r) == *old)
+        });
+        if let Some(info) = replacement {
+            out.insert(old.clone(), info.name.clone());
+        }
     }
-    if let Some(v) = &info.checkdepends {
-        out.extend(v.iter().map(|s| strip_version(s)));
+    Ok(out)
+}
+
+/// AUR packages to build for `roots`, dependencies first. With `skip_installed`,
+/// deps that something installed already satisfies (version constraints
+/// included, per `pacman -T`) are not pulled in; roots always are.
+pub fn resolve_build_order(
+    cfg: &Config,
+    client: &Client,
+    roots: &[String],
+    skip_installed: bool,
+) -> Result<Vec<String>> {
+    let unmet = |specs: Vec<String>| {
+        if skip_installed {
+            unmet_specs(specs, crate::pac::deptest, cfg.verbose)
+        } else {
+            Ok(specs)
+        }
+    };
+    build_order(roots, |chunk| fetch_infos(cfg, client, chunk), unmet)
+}
+
+/// The dep `specs` that `deptest` (normally `pacman -T`) reports as not
+/// satisfied by anything installed.
+fn unmet_specs(
+    specs: Vec<String>,
+    deptest: impl Fn(&[String]) -> Result<Vec<String>>,
+    verbose: bool,
+) -> Result<Vec<String>> {
+    let unmet = deptest(&specs)?;
+    if verbose {
+        let satisfied: Vec<&str> = specs
+            .iter()
+            .filter(|s| !unmet.contains(s))
+            .map(String::as_str)
+            .collect();
+        if !satisfied.is_empty() {
+            println!(
+                "{} {}",
+                dim().apply_to("Already installed, not resolved:"),
+                dim().apply_to(satisfied.join(", "))
+            );
+        }
     }
-    out
+    Ok(unmet)
 }
 
-pub fn resolve_build_order(cfg: &Config, client: &Client, roots: &[String]) -> Result<Vec<String>> {
+/// `resolve_build_order` with the AUR lookup (`fetch`) and the filter of dep
+/// specs still to resolve (`unmet`) passed in.
+fn build_order(
+    roots: &[String],
+    mut fetch: impl FnMut(&[String]) -> Result<Vec<AurInfo>>,
+    mut unmet: impl FnMut(Vec<String>) -> Result<Vec<String>>,
+) -> Result<Vec<String>> {
     // BFS fetch AUR info & dependencies, but only keep AUR packages (repo deps handled by pacman)
     let mut to_visit: Vec<String> = roots.to_vec();
     let mut seen: HashSet<String> = HashSet::new();
@@ -112,16 +485,20 @@ pub fn resolve_build_order(cfg: &Config, client: &Client, roots: &[String]) -> R
             continue;
         }
 
-        let fetched = fetch_infos(cfg, client, &chunk)?;
+        let fetched = fetch(&chunk)?;
+        let mut specs: Vec<String> = vec![];
         for info in fetched {
             let name = info.name.clone();
             if !seen.insert(name.clone()) {
                 continue;
             }
-            let deps = resolve_dep_names(&info);
-            to_visit.extend(deps);
+            specs.extend(dep_specs(&info).map(str::to_string));
             infos.insert(name, info);
         }
+        specs.sort();
+        specs.dedup();
+        let specs = unmet(specs)?;
+        to_visit.extend(specs.iter().map(|s| strip_version(s)));
     }
 
     // Build graph among AUR infos only
@@ -157,8 +534,23 @@ pub fn resolve_build_order(cfg: &Config, client: &Client, roots: &[String]) -> R
 }
 
 fn fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result<Vec<AurInfo>> {
+    let (infos, failed) = fetch_infos_reporting(cfg, client, names)?;
+    if let Some((name, err)) = failed.into_iter().next() {
+        return Err(anyhow!("Failed to fetch metadata for {}: {}", name, err));
+    }
+    Ok(infos)
+}
+
+/// Like `fetch_infos`, but names whose metadata could not be fetched (mirror
+/// errors) are returned separately as name -> error instead of failing the
+/// whole lookup, so they aren't mistaken for packages that don't exist.
+fn fetch_infos_reporting(
+    cfg: &Config,
+    client: &Client,
+    names: &[String],
+) -> Result<(Vec<AurInfo>, BTreeMap<String, String>)> {
     if names.is_empty() {
-        return Ok(vec![]);
+        return Ok((vec![], BTreeMap::new()));
     }
     let mut seen = HashSet::new();
     let mut unique = Vec::new();
@@ -167,22 +559,95 @@ fn fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result<Vec<Au
             unique.push(name.clone());
         }
     }
-    match AurSource::from_cfg(cfg) {
-        AurSource::Official => Ok(aur_rpc_info(client, &unique)?.results),
-        AurSource::Github => github_fetch_infos(cfg, client, &unique),
+    let order = AurSource::order(cfg);
+    if let [source] = order[..] {
+        return match source {
+            AurSource::Official => {
+                Ok((aur_rpc_info(cfg, client, &unique)?.results, BTreeMap::new()))
+            }
+            AurSource::Github => github_fetch_infos(cfg, client, &unique),
+        };
+    }
+
+    // prefer_source: ask each source in turn for what the earlier ones lacked
+    let mut infos: Vec<AurInfo> = vec![];
+    let mut failed: BTreeMap<String, String> = BTreeMap::new();
+    let mut remaining = unique;
+    for source in order {
+        if remaining.is_empty() {
+            break;
+        }
+        let res = match source {
+            AurSource::Official => {
+                aur_rpc_info(cfg, client, &remaining).map(|meta| (meta.results, BTreeMap::new()))
+            }
+            AurSource::Github => github_fetch_infos(cfg, client, &remaining),
+        };
+        let (found, errs) = res.unwrap_or_else(|e| {
+            let err = format!("{:#}", e);
+            (
+                vec![],
+                remaining.iter().map(|n| (n.clone(), err.clone())).collect(),
+            )
+        });
+        failed.extend(errs);
+        let mut via = FOUND_VIA.lock().unwrap();
+        for info in &found {
+            via.insert(info.pkgbase.clone(), source);
+        }
+        remaining.retain(|n| !found.iter().any(|info| &info.name == n));
+        infos.extend(found);
     }
+    failed.retain(|name, _| remaining.contains(name));
+    Ok((infos, failed))
+}
+
+static FOUND_VIA: Mutex<BTreeMap<String, AurSource>> = Mutex::new(BTreeMap::new());
+
+/// With `prefer_source`, the source whose metadata lookup found `pkgbase`.
+pub fn found_via(pkgbase: &str) -> Option<AurSource> {
+    FOUND_VIA.lock().unwrap().get(pkgbase).copied()
+}
+
+/// Requested names split into found metadata and names whose lookup failed.
+pub struct InfoLookup {
+    pub infos: HashMap<String, AurInfo>,
+    pub failed: BTreeMap<String, String>, // name -> why its metadata couldn't be fetched
+}
+
+/// `aur_info_batch` that reports per-package fetch failures instead of erroring.
+/// A name in neither map is genuinely not in the AUR.
+pub fn aur_info_lookup(cfg: &Config, client: &Client, names: Vec<String>) -> Result<InfoLookup> {
+    let (infos, failed) = fetch_infos_reporting(cfg, client, &names)?;
+    Ok(InfoLookup {
+        infos: infos
+            .into_iter()
+            .map(|info| (info.name.clone(), info))
+            .collect(),
+        failed,
+    })
 }
 
-fn github_fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result<Vec<AurInfo>> {
+fn github_fetch_infos(
+    cfg: &Config,
+    client: &Client,
+    names: &[String],
+) -> Result<(Vec<AurInfo>, BTreeMap<String, String>)> {
     if names.is_empty() {
-        return Ok(vec![]);
+        return Ok((vec![], BTreeMap::new()));
     }
     let raw_base = github_raw_base(cfg)?;
+    let mirror = MirrorRequest {
+        headers: github_mirror_headers(cfg)?,
+        timeout: Duration::from_secs(cfg.timeout_secs.unwrap_or(GITHUB_SRCINFO_TIMEOUT_SECS)),
+    };
     let mut queue: VecDeque<String> = VecDeque::from(names.to_vec());
     let mut attempts: HashMap<String, u8> = HashMap::new();
     let mut branch_cache: HashMap<String, Vec<AurInfo>> = HashMap::new();
+    let mut branch_errors: HashMap<String, String> = HashMap::new();
     let mut package_to_branch: HashMap<String, String> = HashMap::new();
     let mut results: HashMap<String, AurInfo> = HashMap::new();
+    let mut failed: BTreeMap<String, String> = BTreeMap::new();
 
     while !queue.is_empty() {
         let chunk_len = queue.len().min(100);
@@ -192,28 +657,33 @@ fn github_fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result
             continue;
         }
 
+        // Branches that failed are fetched again on the retry pass
         let mut branches_to_fetch: Vec<String> = chunk
             .iter()
-            .map(|pkg| {
-                package_to_branch
-                    .get(pkg)
-                    .cloned()
-                    .unwrap_or_else(|| pkg.clone())
-            })
+            .map(|pkg| branch_of(&package_to_branch, pkg))
             .filter(|branch| !branch_cache.contains_key(branch))
             .collect();
         branches_to_fetch.sort();
         branches_to_fetch.dedup();
 
         if !branches_to_fetch.is_empty() {
-            let fetched = fetch_branches_parallel(client, &raw_base, &branches_to_fetch)?;
-            for (branch, entries) in fetched {
-                for info in &entries {
-                    package_to_branch
-                        .entry(info.name.clone())
-                        .or_insert(info.pkgbase.clone());
+            for (branch, fetched) in
+                fetch_branches_parallel(client, &mirror, &raw_base, &branches_to_fetch)
+            {
+                match fetched {
+                    Ok(entries) => {
+                        for info in &entries {
+                            package_to_branch
+                                .entry(info.name.clone())
+                                .or_insert(info.pkgbase.clone());
+                        }
+                        branch_errors.remove(&branch);
+                        branch_cache.insert(branch, entries);
+                    }
+                    Err(e) => {
+                        branch_errors.insert(branch, format!("{:#}", e));
+                    }
                 }
-                branch_cache.insert(branch, entries);
             }
         }
 
@@ -221,10 +691,7 @@ fn github_fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result
             if results.contains_key(&pkg) {
                 continue;
             }
-            let branch = package_to_branch
-                .get(&pkg)
-                .cloned()
-                .unwrap_or_else(|| pkg.clone());
+            let branch = branch_of(&package_to_branch, &pkg);
             if let Some(entries) = branch_cache.get(&branch) {
                 if let Some(info) = entries.iter().find(|info| info.name == pkg) {
                     results.insert(pkg.clone(), info.clone());
@@ -236,24 +703,95 @@ fn github_fetch_infos(cfg: &Config, client: &Client, names: &[String]) -> Result
             if *entry == 0 {
                 *entry = 1;
                 queue.push_back(pkg);
+            } else if let Some(err) = branch_errors.get(&branch) {
+                failed.insert(pkg, err.clone());
             }
         }
     }
 
-    Ok(results.into_iter().map(|(_, v)| v).collect())
+    // Split packages live under their pkgbase branch; when the name isn't a branch,
+    // look the pkgbase up in the mirror's branch list before giving up. Repo deps
+    // that aren't installed yet come through here too; they're skipped.
+    for pkg in names {
+        if results.contains_key(pkg)
+            || failed.contains_key(pkg)
+            || crate::pac::in_sync_repos(pkg)
+            || GITHUB_BRANCH_LOOKUPS.fetch_add(1, Ordering::Relaxed) >= GITHUB_BRANCH_LOOKUP_LIMIT
+        {
+            continue;
+        }
+        let candidates = match github_pkgbase_candidates(client, &mirror, &raw_base, pkg) {
+            Ok(c) => c,
+            Err(e) => {
+                failed.insert(pkg.clone(), format!("{:#}", e));
+                continue;
+            }
+        };
+        for branch in candidates {
+            if !branch_cache.contains_key(&branch) {
+                match fetch_branch_srcinfo(client, &mirror, &raw_base, &branch) {
+                    Ok(infos) => {
+                        branch_cache.insert(branch.clone(), infos);
+                    }
+                    Err(e) => {
+                        failed.insert(
+                            pkg.clone(),
+                            format!("Failed to fetch .SRCINFO for {}: {:#}", branch, e),
+                        );
+                        continue;
+                    }
+                }
+            }
+            if let Some(info) = branch_cache[&branch].iter().find(|info| &info.name == pkg) {
+                failed.remove(pkg);
+                results.insert(pkg.clone(), info.clone());
+                break;
+            }
+        }
+    }
+
+    // Every .SRCINFO 404ing usually means the mirror repo itself was renamed/moved:
+    // raw.githubusercontent.com doesn't follow repo redirects, so say so clearly.
+    // Probed once per run: resolution levels made of repo deps find nothing too.
+    if results.is_empty() && failed.is_empty() {
+        let problem = GITHUB_MIRROR_PROBLEM.get_or_init(|| {
+            match github_mirror_moved(client, &mirror, &raw_base) {
+                Ok(None) => None,
+                Ok(Some(moved_to)) => Some(format!(
+                    "GitHub mirror has moved to https://github.com/{}; update mirror_base in your config",
+                    moved_to
+                )),
+                Err(e) => Some(format!("{:#}", e)),
+            }
+        });
+        if let Some(problem) = problem {
+            return Err(anyhow!("{}", problem));
+        }
+    }
+
+    Ok((results.into_values().collect(), failed))
 }
 
+fn branch_of(package_to_branch: &HashMap<String, String>, pkg: &str) -> String {
+    package_to_branch
+        .get(pkg)
+        .cloned()
+        .unwrap_or_else(|| pkg.to_string())
+}
+
+/// Fetch several branches' .SRCINFO at once; each branch keeps its own result.
 fn fetch_branches_parallel(
     client: &Client,
+    mirror: &MirrorRequest,
     raw_base: &str,
     branches: &[String],
-) -> Result<Vec<(String, Vec<AurInfo>)>> {
+) -> Vec<(String, Result<Vec<AurInfo>>)> {
     branches
         .par_iter()
         .map(|branch| {
-            let infos = fetch_branch_srcinfo(client, raw_base, branch)
-                .with_context(|| format!("Failed to fetch .SRCINFO for {}", branch))?;
-            Ok((branch.clone(), infos))
+            let infos = fetch_branch_srcinfo(client, mirror, raw_base, branch)
+                .with_context(|| format!("Failed to fetch .SRCINFO for {}", branch));
+            (branch.clone(), infos)
         })
         .collect()
 }
@@ -285,19 +823,190 @@ fn github_raw_base(cfg: &Config) -> Result<String> {
     ))
 }
 
-fn fetch_branch_srcinfo(client: &Client, raw_base: &str, branch: &str) -> Result<Vec<AurInfo>> {
-    let mut urls = vec![format!("{}/{}/.SRCINFO", raw_base, branch)];
-    // Packages also exist as directories under the main branch; try common defaults.
+/// Branches of the mirror that could be the pkgbase of `pkg`, longest first.
+/// Only branches that are a `-`-separated prefix of the name are considered
+/// (e.g. `linux-headers` -> `linux`), which covers the usual split-package layout.
+fn github_pkgbase_candidates(
+    client: &Client,
+    mirror: &MirrorRequest,
+    raw_base: &str,
+    pkg: &str,
+) -> Result<Vec<String>> {
+    #[derive(Deserialize)]
+    struct RefEntry {
+        #[serde(rename = "ref")]
+        git_ref: String,
+    }
+
+    let Some(repo) = raw_base.strip_prefix("https://raw.githubusercontent.com/") else {
+        return Ok(vec![]);
+    };
+    let Some((prefix, _)) = pkg.split_once('-') else {
+        return Ok(vec![]);
+    };
+    // The AUR mirror has one branch per pkgbase, far too many to page through
+    // `/branches`; `matching-refs` narrows the list to the shared prefix.
+    let resp = send(
+        client
+            .get(format!(
+                "https://api.github.com/repos/{}/git/matching-refs/heads/{}",
+                repo,
+                urlencoding::encode(prefix)
+            ))
+            .headers(mirror.headers.clone())
+            .timeout(mirror.timeout),
+    )
+    .with_context(|| format!("Failed to query the GitHub API for {}", repo))?;
+    let Ok(resp) = resp.error_for_status() else {
+        return Ok(vec![]);
+    };
+    let refs: Vec<RefEntry> = resp.json().unwrap_or_default();
+    let mut branches: Vec<String> = refs
+        .into_iter()
+        .filter_map(|r| r.git_ref.strip_prefix("refs/heads/").map(str::to_string))
+        .filter(|branch| {
+            pkg.strip_prefix(branch.as_str())
+                .is_some_and(|rest| rest.starts_with('-'))
+        })
+        .collect();
+    branches.sort_by_key(|b| std::cmp::Reverse(b.len()));
+    Ok(branches)
+}
+
+/// Why the GitHub mirror is unusable (moved or missing), per the one probe a run makes.
+static GITHUB_MIRROR_PROBLEM: OnceLock<Option<String>> = OnceLock::new();
+
+/// Ask the GitHub API whether the mirror repo behind `raw_base` was renamed.
+/// The API follows repo redirects, so a different `full_name` means it moved.
+fn github_mirror_moved(
+    client: &Client,
+    mirror: &MirrorRequest,
+    raw_base: &str,
+) -> Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct RepoResponse {
+        full_name: String,
+    }
+
+    let Some(repo) = raw_base.strip_prefix("https://raw.githubusercontent.com/") else {
+        return Ok(None);
+    };
+    // Network and API errors: don't turn a best-effort probe into a failure
+    let Ok(resp) = send(
+        client
+            .get(format!("https://api.github.com/repos/{}", repo))
+            .headers(mirror.headers.clone())
+            .timeout(mirror.timeout),
+    ) else {
+        return Ok(None);
+    };
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Err(anyhow!(
+            "GitHub mirror https://github.com/{} does not exist; check mirror_base in your config",
+            repo
+        ));
+    }
+    let Ok(info) = resp
+        .error_for_status()
+        .and_then(|r| r.json::<RepoResponse>())
+    else {
+        return Ok(None);
+    };
+    if info.full_name.eq_ignore_ascii_case(repo) {
+        Ok(None)
+    } else {
+        Ok(Some(info.full_name))
+    }
+}
+
+/// Per-request settings shared by every GitHub mirror call.
+struct MirrorRequest {
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+static KEYRING_GITHUB_TOKEN: OnceLock<Option<Secret>> = OnceLock::new();
+
+/// The `github_token` entry of the system keyring (service `aurwrap`), looked up
+/// once per run. `None` when there is no entry or no reachable keyring.
+fn keyring_github_token() -> Option<Secret> {
+    KEYRING_GITHUB_TOKEN
+        .get_or_init(|| {
+            // The Secret Service client blocks on its own runtime, which must not
+            // nest inside the tokio one main runs on
+            let lookup =
+                thread::spawn(|| keyring::Entry::new("aurwrap", "github_token")?.get_password())
+                    .join();
+            match lookup {
+                Ok(Ok(token)) if !token.trim().is_empty() => Some(Secret::new(token.trim())),
+                Ok(Ok(_)) | Ok(Err(keyring::Error::NoEntry)) => None,
+                Ok(Err(e)) => {
+                    eprintln!(
+                        "{} {}",
+                        warn_icon(),
+                        warning().apply_to(format!(
+                            "Keyring unavailable ({}); using github_token from config/env",
+                            e
+                        ))
+                    );
+                    None
+                }
+                Err(_) => None,
+            }
+        })
+        .clone()
+}
+
+/// Extra headers for GitHub mirror requests (auth token, proxy headers).
+/// Values are marked sensitive so they are redacted from `Debug` output.
+fn github_mirror_headers(cfg: &Config) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let token = cfg
+        .use_keyring
+        .then(keyring_github_token)
+        .flatten()
+        .or_else(|| cfg.github_token.clone());
+    if let Some(token) = &token {
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", token.expose()))
+            .map_err(|_| anyhow!("github_token contains characters not allowed in a header"))?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+    for (name, value) in &cfg.mirror_headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow!("Invalid mirror header name '{}'", name))?;
+        let mut header_value = HeaderValue::from_str(value.expose())
+            .map_err(|_| anyhow!("Invalid value for mirror header '{}'", name))?;
+        header_value.set_sensitive(true);
+        headers.insert(header_name, header_value);
+    }
+    Ok(headers)
+}
+
+/// Where the mirror may keep the .SRCINFO of `branch`: on the branch itself, or
+/// in a directory of that name on a default branch.
+fn srcinfo_urls(raw_base: &str, branch: &str) -> Vec<String> {
+    // Names like `gtk+` must be escaped as path segments
+    let segment = urlencoding::encode(branch);
+    let mut urls = vec![format!("{}/{}/.SRCINFO", raw_base, segment)];
     for default_branch in ["master", "main"] {
         urls.push(format!(
             "{}/{}/{}/.SRCINFO",
-            raw_base, default_branch, branch
+            raw_base, default_branch, segment
         ));
     }
+    urls
+}
 
+fn fetch_branch_srcinfo(
+    client: &Client,
+    mirror: &MirrorRequest,
+    raw_base: &str,
+    branch: &str,
+) -> Result<Vec<AurInfo>> {
     let mut last_err: Option<anyhow::Error> = None;
-    for url in urls {
-        match fetch_srcinfo_from_url(client, &url, branch) {
+    for url in srcinfo_urls(raw_base, branch) {
+        match fetch_srcinfo_from_url(client, mirror, &url, branch) {
             Ok(Some(infos)) => return Ok(infos),
             Ok(None) => continue,
             Err(e) => {
@@ -315,14 +1024,17 @@ fn fetch_branch_srcinfo(client: &Client, raw_base: &str, branch: &str) -> Result
 
 fn fetch_srcinfo_from_url(
     client: &Client,
+    mirror: &MirrorRequest,
     url: &str,
     pkgname: &str,
 ) -> Result<Option<Vec<AurInfo>>> {
     for attempt in 0..GITHUB_SRCINFO_MAX_RETRIES {
-        let resp_result = client
-            .get(url)
-            .timeout(Duration::from_secs(GITHUB_SRCINFO_TIMEOUT_SECS))
-            .send();
+        let resp_result = send(
+            client
+                .get(url)
+                .headers(mirror.headers.clone())
+                .timeout(mirror.timeout),
+        );
 
         match resp_result {
             Ok(resp) => {
@@ -338,6 +1050,7 @@ fn fetch_srcinfo_from_url(
                 let text = resp
                     .text()
                     .with_context(|| format!("Failed to read .SRCINFO for {}", pkgname))?;
+                warn_foreign_arch(pkgname, &text);
                 let parsed = parse_srcinfo(&text)
                     .with_context(|| format!("Failed to parse .SRCINFO for {}", pkgname))?;
                 return Ok(Some(parsed));
@@ -363,11 +1076,38 @@ fn fetch_srcinfo_from_url(
     Ok(None)
 }
 
+/// Warn when a mirrored .SRCINFO lists architectures that exclude this host.
+/// The mirror can lag behind the AUR or carry a branch meant for another arch,
+/// which otherwise only shows up later as a confusing makepkg failure.
+fn warn_foreign_arch(pkgname: &str, srcinfo: &str) {
+    let arches: Vec<&str> = srcinfo
+        .lines()
+        .filter_map(|l| l.trim().split_once('='))
+        .filter(|(k, _)| k.trim() == "arch")
+        .map(|(_, v)| v.trim())
+        .collect();
+    let host = crate::pac::host_arch();
+    if arches.is_empty() || arches.iter().any(|a| *a == "any" || *a == host) {
+        return;
+    }
+    eprintln!(
+        "{} {}",
+        warn_icon(),
+        warning().apply_to(format!(
+            "GitHub mirror .SRCINFO for {} lists arch ({}) but not {}; the mirror may be stale or for another architecture",
+            pkgname,
+            arches.join(" "),
+            host
+        ))
+    );
+}
+
 #[derive(Default, Clone)]
 struct DepFields {
     depends: Vec<String>,
     makedepends: Vec<String>,
     checkdepends: Vec<String>,
+    replaces: Vec<String>,
 }
 
 fn parse_srcinfo(contents: &str) -> Result<Vec<AurInfo>> {
@@ -375,6 +1115,10 @@ fn parse_srcinfo(contents: &str) -> Result<Vec<AurInfo>> {
     let mut pkgver: Option<String> = None;
     let mut pkgrel: Option<String> = None;
     let mut epoch: Option<String> = None;
+    let mut base_desc: Option<String> = None;
+    let mut pkg_desc: HashMap<String, String> = HashMap::new();
+    let mut base_url: Option<String> = None;
+    let mut pkg_url: HashMap<String, String> = HashMap::new();
     let mut base_fields = DepFields::default();
     let mut pkg_fields: HashMap<String, DepFields> = HashMap::new();
     let mut pkg_names: Vec<String> = Vec::new();
@@ -400,11 +1144,21 @@ fn parse_srcinfo(contents: &str) -> Result<Vec<AurInfo>> {
             "pkgrel" => {
                 pkgrel = Some(value.to_string());
             }
-            "epoch" => {
-                if !value.is_empty() {
-                    epoch = Some(value.to_string());
-                }
+            "epoch" if !value.is_empty() => {
+                epoch = Some(value.to_string());
             }
+            "pkgdesc" => match &current_pkg {
+                Some(pkg) => {
+                    pkg_desc.insert(pkg.clone(), value.to_string());
+                }
+                None => base_desc = Some(value.to_string()),
+            },
+            "url" => match &current_pkg {
+                Some(pkg) => {
+                    pkg_url.insert(pkg.clone(), value.to_string());
+                }
+                None => base_url = Some(value.to_string()),
+            },
             "pkgname" => {
                 let name = value.to_string();
                 current_pkg = Some(name.clone());
@@ -447,6 +1201,18 @@ fn parse_srcinfo(contents: &str) -> Result<Vec<AurInfo>> {
                     base_fields.checkdepends.push(entry);
                 }
             }
+            "replaces" => {
+                let entry = value.to_string();
+                if let Some(pkg) = &current_pkg {
+                    pkg_fields
+                        .entry(pkg.clone())
+                        .or_default()
+                        .replaces
+                        .push(entry);
+                } else {
+                    base_fields.replaces.push(entry);
+                }
+            }
             _ => {}
         }
     }
@@ -468,9 +1234,13 @@ fn parse_srcinfo(contents: &str) -> Result<Vec<AurInfo>> {
             name: name.clone(),
             pkgbase: pkgbase.clone(),
             version: version.clone(),
+            description: pkg_desc.remove(&name).or_else(|| base_desc.clone()),
+            url: pkg_url.remove(&name).or_else(|| base_url.clone()),
             depends: vec_to_option(merged.depends),
             makedepends: vec_to_option(merged.makedepends),
             checkdepends: vec_to_option(merged.checkdepends),
+            replaces: vec_to_option(merged.replaces),
+            out_of_date: None,
         });
     }
     Ok(infos)
@@ -481,6 +1251,7 @@ fn merge_fields(base: &DepFields, specific: &DepFields) -> DepFields {
         depends: merge_lists(&base.depends, &specific.depends),
         makedepends: merge_lists(&base.makedepends, &specific.makedepends),
         checkdepends: merge_lists(&base.checkdepends, &specific.checkdepends),
+        replaces: merge_lists(&base.replaces, &specific.replaces),
     }
 }
 
@@ -505,3 +1276,79 @@ fn format_version(epoch: Option<&str>, pkgver: &str, pkgrel: &str) -> String {
         _ => format!("{}-{}", pkgver, pkgrel),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, depends: &[&str]) -> AurInfo {
+        AurInfo {
+            name: name.to_string(),
+            pkgbase: name.to_string(),
+            version: String::from("1.0-1"),
+            description: None,
+            url: None,
+            depends: Some(depends.iter().map(|d| d.to_string()).collect()),
+            makedepends: None,
+            checkdepends: None,
+            replaces: None,
+            out_of_date: None,
+        }
+    }
+
+    /// An AUR "lookup" that knows `aur` and nothing else (repo packages).
+    fn lookup(aur: Vec<AurInfo>) -> impl FnMut(&[String]) -> Result<Vec<AurInfo>> {
+        move |names| {
+            Ok(aur
+                .iter()
+                .filter(|i| names.contains(&i.name))
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn new_aur_dep_of_an_update_is_built_first() {
+        // foo's update now depends on bar (AUR, not installed) and glibc (repo)
+        let aur = vec![info("foo", &["bar>=2", "glibc"]), info("bar", &[])];
+        let order = build_order(&[String::from("foo")], lookup(aur), Ok).unwrap();
+        assert_eq!(order, ["bar", "foo"]);
+    }
+
+    #[test]
+    fn satisfied_aur_makedepend_is_not_built() {
+        let mut foo = info("foo", &[]);
+        foo.makedepends = Some(vec![String::from("bar-git>=1.2")]);
+        let aur = vec![foo, info("bar-git", &[])];
+        // pacman -T: an installed bar-git already satisfies the constraint
+        let deptest = |specs: &[String]| -> Result<Vec<String>> {
+            Ok(specs
+                .iter()
+                .filter(|s| !s.starts_with("bar-git"))
+                .cloned()
+                .collect())
+        };
+        let order = build_order(&[String::from("foo")], lookup(aur), |specs| {
+            unmet_specs(specs, deptest, false)
+        })
+        .unwrap();
+        assert_eq!(order, ["foo"]);
+    }
+
+    #[test]
+    fn srcinfo_urls_escape_plus() {
+        let base = "https://raw.githubusercontent.com/archlinux/aur";
+        assert_eq!(
+            srcinfo_urls(base, "gtk+"),
+            [
+                format!("{}/gtk%2B/.SRCINFO", base),
+                format!("{}/master/gtk%2B/.SRCINFO", base),
+                format!("{}/main/gtk%2B/.SRCINFO", base),
+            ]
+        );
+        assert_eq!(
+            srcinfo_urls(base, "notcurses++")[0],
+            format!("{}/notcurses%2B%2B/.SRCINFO", base)
+        );
+    }
+}
@@ -1,11 +1,19 @@
 use anyhow::{anyhow, Result};
 use duct::cmd;
+use rayon::prelude::*;
+use regex::Regex;
 use std::collections::HashSet;
+use std::ffi::{CString, OsString};
 use std::fs;
-use std::path::Path;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-use crate::config::Config;
+use crate::config::{Config, FmWait, DEFAULT_MIN_FREE_MB};
+use crate::interrupt;
 use crate::style::*;
+use crate::ui::{confirm, wait_for_enter};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AurSource {
@@ -14,6 +22,34 @@ pub enum AurSource {
 }
 
 impl AurSource {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "official" | "aur" => Some(AurSource::Official),
+            "github" | "github-aur" => Some(AurSource::Github),
+            _ => None,
+        }
+    }
+
+    /// Sources to try, most preferred first: `prefer_source` when set, else the
+    /// single `aur_mirror` one.
+    pub fn order(cfg: &Config) -> Vec<Self> {
+        let mut order: Vec<Self> = vec![];
+        for source in cfg.prefer_source.iter().filter_map(|s| Self::parse(s)) {
+            if !order.contains(&source) {
+                order.push(source);
+            }
+        }
+        if order.is_empty() {
+            order.push(Self::from_cfg(cfg));
+        }
+        order
+    }
+
+    /// The source bare package names are fetched and cloned from first.
+    pub fn preferred(cfg: &Config) -> Self {
+        Self::order(cfg)[0]
+    }
+
     pub fn from_cfg(cfg: &Config) -> Self {
         if cfg.aur_mirror.eq_ignore_ascii_case("github")
             || cfg.aur_mirror.eq_ignore_ascii_case("github-aur")
@@ -31,125 +67,670 @@ pub struct AurCloneSpec {
     pub source: AurSource,
 }
 
-fn run_git_command(args: &[&str], timeout_secs: u64) -> Result<bool> {
-    let output = cmd(
-        "timeout",
-        [&format!("{}s", timeout_secs), "git"]
-            .into_iter()
-            .chain(args.iter().cloned()),
+const GIT_CLONE_TIMEOUT_SECS: u64 = 300;
+
+const GIT_CLONE_ATTEMPTS: u32 = 3;
+const GIT_CLONE_RETRY_DELAY_SECS: u64 = 2;
+
+/// How a single git clone attempt ended.
+enum CloneOutcome {
+    Cloned,
+    /// The repo/branch doesn't exist on the remote; retrying won't help
+    Missing,
+    /// `pull --ff-only` refused: the local history diverged from the remote's
+    Diverged,
+    /// Network error, timeout, etc.
+    Failed,
+}
+
+fn run_git_command(args: &[&str], timeout_secs: Option<u64>) -> CloneOutcome {
+    let expr = match timeout_secs {
+        Some(t) => {
+            let mut full = vec![format!("{}s", t), String::from("git")];
+            full.extend(args.iter().map(|a| a.to_string()));
+            cmd("timeout", full)
+        }
+        None => cmd("git", args),
+    };
+    // Shielded from Ctrl-C in a background group, so git must not ask for credentials
+    let expr = expr.env("GIT_TERMINAL_PROMPT", "0");
+    let Ok((success, log)) = tee_output(&expr) else {
+        return CloneOutcome::Failed;
+    };
+    let stderr = String::from_utf8_lossy(&log);
+    if success {
+        CloneOutcome::Cloned
+    } else if stderr.to_lowercase().contains("not found") {
+        CloneOutcome::Missing
+    } else if stderr.contains("Not possible to fast-forward") || stderr.contains("diverg") {
+        CloneOutcome::Diverged
+    } else {
+        CloneOutcome::Failed
+    }
+}
+
+/// Run `expr` shielded, echoing its output (git writes progress to stderr) to our
+/// stderr as it comes and keeping a copy, so a missing repo can be told apart
+/// from a network error. Returns whether it succeeded, and the output.
+fn tee_output(expr: &duct::Expression) -> Result<(bool, Vec<u8>)> {
+    let mut reader = interrupt::shielded(&expr.stderr_to_stdout().unchecked()).reader()?;
+    let _tracked = interrupt::track_children(reader.pids());
+    let mut log: Vec<u8> = vec![];
+    let mut buf = [0u8; 8192];
+    let mut stderr = std::io::stderr();
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        stderr.write_all(&buf[..n])?;
+        log.extend_from_slice(&buf[..n]);
+    }
+    let success = reader.try_wait()?.is_some_and(|out| out.status.success());
+    Ok((success, log))
+}
+
+/// Clone with up to `GIT_CLONE_ATTEMPTS` tries, doubling the delay each time.
+/// A remote that reports the repo/branch as missing fails immediately.
+fn clone_with_retry(
+    args: &[&str],
+    timeout_secs: Option<u64>,
+    target: &Path,
+    what: &str,
+) -> Result<()> {
+    let mut delay = GIT_CLONE_RETRY_DELAY_SECS;
+    for attempt in 1..=GIT_CLONE_ATTEMPTS {
+        // A killed clone can leave a partial checkout behind
+        remove_partial_clone(target)?;
+        match run_git_command(args, timeout_secs) {
+            CloneOutcome::Cloned => return Ok(()),
+            CloneOutcome::Missing => {
+                remove_partial_clone(target)?;
+                return Err(anyhow!("Failed to clone {}: not found on the remote", what));
+            }
+            CloneOutcome::Failed | CloneOutcome::Diverged if attempt < GIT_CLONE_ATTEMPTS => {
+                println!(
+                    "  {} {}",
+                    warn_icon(),
+                    warning().apply_to(format!(
+                        "Clone attempt {}/{} failed; retrying in {}s",
+                        attempt, GIT_CLONE_ATTEMPTS, delay
+                    ))
+                );
+                std::thread::sleep(std::time::Duration::from_secs(delay));
+                delay *= 2;
+            }
+            CloneOutcome::Failed | CloneOutcome::Diverged => {}
+        }
+    }
+    // Never leave a partial checkout where a later step would take it for a clone
+    remove_partial_clone(target)?;
+    Err(anyhow!(
+        "Failed to clone {} after {} attempts",
+        what,
+        GIT_CLONE_ATTEMPTS
+    ))
+}
+
+fn remove_partial_clone(target: &Path) -> Result<()> {
+    if target.exists() {
+        fs::remove_dir_all(target)?;
+    }
+    Ok(())
+}
+
+fn depth_args(depth: u32) -> Vec<String> {
+    if depth == 0 {
+        vec![]
+    } else {
+        vec![String::from("--depth"), depth.to_string()]
+    }
+}
+
+fn print_shallow_note(depth: u32) {
+    if depth > 0 {
+        println!(
+            "  {} {}",
+            dim().apply_to("↳"),
+            dim().apply_to(format!(
+                "Shallow clone (--depth {}): git log only shows recent history; set clone_depth=0 for a full clone",
+                depth
+            ))
+        );
+    }
+}
+
+/// `git` arguments and timeout for cloning `spec` into `target`, `depth` commits deep.
+fn clone_args(
+    cfg: &Config,
+    spec: &AurCloneSpec,
+    target: &Path,
+    depth: u32,
+) -> (Vec<String>, Option<u64>) {
+    let p = &spec.pkgbase;
+    // Progress is only shown on a terminal unless asked for; stderr is a pipe here
+    let mut args = vec![String::from("clone"), String::from("--progress")];
+    args.extend(depth_args(depth));
+    let target = target.to_string_lossy().into_owned();
+    match spec.source {
+        AurSource::Github => {
+            // For GitHub mirror, clone only the specific branch
+            let base = cfg
+                .mirror_base
+                .as_deref()
+                .unwrap_or("https://github.com/archlinux/aur");
+            let url = base.trim_end_matches('/');
+            args.extend(["--single-branch", "--branch", p, url, &target].map(str::to_string));
+            (
+                args,
+                Some(cfg.timeout_secs.unwrap_or(GIT_CLONE_TIMEOUT_SECS)),
+            )
+        }
+        AurSource::Official => {
+            // Standard AUR clone
+            let url = format!("https://aur.archlinux.org/{}.git", urlencoding::encode(p));
+            args.extend([url, target]);
+            (args, cfg.timeout_secs)
+        }
+    }
+}
+
+/// The clone of `spec` under `dest` as a shell command line (for --print).
+pub fn clone_command_line(cfg: &Config, spec: &AurCloneSpec, dest: &Path) -> String {
+    let target = dest.join(&spec.pkgbase);
+    let depth = clone_depth_for(cfg, &spec.pkgbase);
+    let (args, timeout) = clone_args(cfg, spec, &target, depth);
+    let timeout_prefix = timeout
+        .map(|t| format!("timeout {}s ", t))
+        .unwrap_or_default();
+    let args: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+    let mut line = format!("{}git {}", timeout_prefix, args.join(" "));
+    if let Some(git_ref) = cfg.pinned_refs.get(&spec.pkgbase) {
+        line.push_str(&format!(
+            " && git -C {} checkout --quiet {}",
+            shell_quote(&target.to_string_lossy()),
+            shell_quote(git_ref)
+        ));
+    }
+    line
+}
+
+/// `clone_depth`, except that a pinned ref may be anywhere in the history.
+fn clone_depth_for(cfg: &Config, pkgbase: &str) -> u32 {
+    if cfg.pinned_refs.contains_key(pkgbase) {
+        0
+    } else {
+        cfg.clone_depth
+    }
+}
+
+/// Check out the `pin.<pkgbase>` ref, if any, in the clone at `dir`.
+fn checkout_pinned(cfg: &Config, pkgbase: &str, dir: &Path) -> Result<()> {
+    let Some(git_ref) = cfg.pinned_refs.get(pkgbase) else {
+        return Ok(());
+    };
+    let dir_arg = dir.to_string_lossy();
+    // A clone_dir clone made before the pin may still be shallow
+    if dir.join(".git").join("shallow").exists() {
+        let args = ["-C", dir_arg.as_ref(), "fetch", "--unshallow"];
+        let timeout = cfg.timeout_secs.or(Some(GIT_CLONE_TIMEOUT_SECS));
+        if !matches!(run_git_command(&args, timeout), CloneOutcome::Cloned) {
+            return Err(anyhow!(
+                "Failed to fetch the full history of {} for its pinned ref {}",
+                pkgbase,
+                git_ref
+            ));
+        }
+    }
+    println!(
+        "  {} {}",
+        dim().apply_to("↳"),
+        dim().apply_to(format!("Checking out pinned ref {}", git_ref))
+    );
+    let status = cmd(
+        "git",
+        [
+            "-C",
+            dir_arg.as_ref(),
+            "checkout",
+            "--quiet",
+            git_ref.as_str(),
+        ],
     )
     .stderr_to_stdout()
     .unchecked()
-    .run();
+    .run()?;
+    if !status.status.success() {
+        return Err(anyhow!(
+            "Pinned ref {} of {} is not in its git history",
+            git_ref,
+            pkgbase
+        ));
+    }
+    Ok(())
+}
+
+/// Quote `s` for a POSIX shell unless it only has unambiguous characters.
+pub fn shell_quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c));
+    if plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+fn clone_from(cfg: &Config, spec: &AurCloneSpec, target: &Path, depth: u32) -> Result<()> {
+    let p = &spec.pkgbase;
+    let (args, timeout_secs) = clone_args(cfg, spec, target, depth);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let cmd_display = format!(
+        "{}git {} '{}'",
+        timeout_secs
+            .map(|t| format!("timeout {}s ", t))
+            .unwrap_or_default(),
+        args[..args.len() - 1].join(" "),
+        target.display()
+    );
 
-    match output {
-        Ok(output) => Ok(output.status.success()),
-        Err(_) => Ok(false), // Timeout or other error
+    match spec.source {
+        AurSource::Github => println!(
+            "{} {} Cloning {} from GitHub mirror",
+            info_icon(),
+            github_aur_mirror_badge(),
+            package_name().apply_to(p)
+        ),
+        AurSource::Official => println!(
+            "{} {} Cloning {} from AUR",
+            info_icon(),
+            aur_badge(),
+            package_name().apply_to(p)
+        ),
     }
+    println!(
+        "  {} {}",
+        dim().apply_to("↳"),
+        command().apply_to(&cmd_display)
+    );
+    print_shallow_note(depth);
+    let what = match spec.source {
+        AurSource::Github => format!("package {} from GitHub mirror", p),
+        AurSource::Official => format!("{} from AUR", p),
+    };
+    clone_with_retry(&args, timeout_secs, target, &what)
 }
 
 pub fn clone_aur_pkgs(cfg: &Config, pkgs: &[AurCloneSpec], dest: &Path) -> Result<()> {
     fs::create_dir_all(dest)?;
 
     for spec in pkgs {
-        let p = &spec.pkgbase;
-        let target = dest.join(p);
+        let target = dest.join(&spec.pkgbase);
         if target.exists() {
             continue;
         }
+        match &cfg.clone_dir {
+            Some(dir) => {
+                let persistent = dir.join(&spec.pkgbase);
+                update_persistent_clone(cfg, spec, &persistent)?;
+                // Builds and edits happen on a copy; the clone stays pristine for the next pull
+                cmd(
+                    "cp",
+                    [OsString::from("-a"), persistent.into(), (&target).into()],
+                )
+                .run()
+                .map_err(|e| anyhow!("Failed to copy the clone of {}: {}", spec.pkgbase, e))?;
+            }
+            None => clone_with_fallback(cfg, spec, &target, clone_depth_for(cfg, &spec.pkgbase))?,
+        }
+        checkout_pinned(cfg, &spec.pkgbase, &target)?;
+    }
+    Ok(())
+}
 
-        match spec.source {
-            AurSource::Github => {
-                // For GitHub mirror, use shallow clone of the specific branch
-                let base = cfg
-                    .mirror_base
-                    .as_deref()
-                    .unwrap_or("https://github.com/archlinux/aur");
-                let url = base.trim_end_matches('/');
-                let cmd_display = format!(
-                    "timeout 300s git clone --depth 1 --single-branch --branch {} {} '{}'",
-                    p,
-                    url,
-                    target.display()
-                );
-
-                // Clone just the specific branch shallowly
-                println!(
-                    "{} {} Cloning {} from GitHub mirror",
-                    info_icon(),
-                    github_aur_mirror_badge(),
-                    package_name().apply_to(p)
-                );
+/// `clone_dir`: pull the kept clone of `spec`, or clone it there first (in
+/// full, since it is pulled again later). Only a clone that cannot be
+/// fast-forwarded (e.g. the history was rewritten) is replaced; when the pull
+/// fails otherwise (offline, AUR down), the existing clone is built as it is.
+fn update_persistent_clone(cfg: &Config, spec: &AurCloneSpec, dir: &Path) -> Result<()> {
+    if dir.join(".git").exists() {
+        println!(
+            "{} {} Updating {} in {}",
+            info_icon(),
+            aur_badge(),
+            package_name().apply_to(&spec.pkgbase),
+            path().apply_to(dir.display())
+        );
+        let dir_arg = dir.to_string_lossy();
+        let args = ["-C", dir_arg.as_ref(), "pull", "--ff-only"];
+        let timeout = cfg.timeout_secs.or(Some(GIT_CLONE_TIMEOUT_SECS));
+        match run_git_command(&args, timeout) {
+            CloneOutcome::Cloned => return Ok(()),
+            CloneOutcome::Diverged => println!(
+                "  {} {}",
+                warn_icon(),
+                warning().apply_to("The clone cannot be fast-forwarded; cloning again")
+            ),
+            CloneOutcome::Missing | CloneOutcome::Failed => {
                 println!(
                     "  {} {}",
-                    dim().apply_to("↳"),
-                    command().apply_to(&cmd_display)
+                    warn_icon(),
+                    warning().apply_to("git pull failed; building from the existing clone")
                 );
-                let success = run_git_command(
-                    &[
-                        "clone",
-                        "--depth",
-                        "1",
-                        "--single-branch",
-                        "--branch",
-                        p,
-                        url,
-                        target.to_string_lossy().as_ref(),
-                    ],
-                    300, // 5 minute timeout
-                )?;
-
-                if !success {
-                    return Err(anyhow!("Failed to clone package {} from GitHub mirror. The package might not exist or the mirror might be unavailable.", p));
-                }
+                return Ok(());
             }
-            AurSource::Official => {
-                // Standard AUR clone
-                let url = format!("https://aur.archlinux.org/{}.git", p);
-                let cmd_display = format!("git clone {} '{}'", url, target.display());
-                println!(
-                    "{} {} Cloning {} from AUR",
-                    info_icon(),
-                    aur_badge(),
-                    package_name().apply_to(p)
-                );
+        }
+    }
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    clone_with_fallback(cfg, spec, dir, 0)
+}
+
+/// Clone `spec` into `target`, falling back over `prefer_source` when set.
+fn clone_with_fallback(cfg: &Config, spec: &AurCloneSpec, target: &Path, depth: u32) -> Result<()> {
+    // Without prefer_source there is a single source and no fallback
+    let mut sources = vec![spec.source];
+    if !cfg.prefer_source.is_empty() {
+        let order = AurSource::order(cfg);
+        // Bare names carry the first preferred source; their metadata may have
+        // come from a later one, which is then the better first try
+        if spec.source == order[0] {
+            if let Some(found) = crate::aur::found_via(&spec.pkgbase) {
+                sources = vec![found];
+            }
+        }
+        for source in order {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+    }
+    for (i, source) in sources.iter().enumerate() {
+        let attempt = AurCloneSpec {
+            pkgbase: spec.pkgbase.clone(),
+            source: *source,
+        };
+        match clone_from(cfg, &attempt, target, depth) {
+            Ok(()) => break,
+            Err(e) if i + 1 < sources.len() => {
                 println!(
                     "  {} {}",
-                    dim().apply_to("↳"),
-                    command().apply_to(&cmd_display)
+                    warn_icon(),
+                    warning().apply_to(format!("{}; trying the next source", e))
                 );
-                let status = cmd("git", ["clone", &url, target.to_string_lossy().as_ref()])
-                    .stderr_to_stdout()
-                    .run()?;
-
-                if !status.status.success() {
-                    return Err(anyhow!("git clone failed for {}", p));
+                if target.exists() {
+                    fs::remove_dir_all(target)?;
                 }
             }
+            Err(e) => return Err(e),
         }
     }
     Ok(())
 }
 
+/// GUI file managers fork into the background (or hand off to a running
+/// instance), so their exit says nothing about whether editing is finished.
+const GUI_FILE_MANAGERS: &[&str] = &[
+    "nautilus",
+    "dolphin",
+    "thunar",
+    "nemo",
+    "caja",
+    "pcmanfm",
+    "pcmanfm-qt",
+    "spacefm",
+    "krusader",
+    "xdg-open",
+];
+
 pub fn open_file_manager(cfg: &Config, root: &Path) -> Result<()> {
-    // Block until the FM exits
     let fm = &cfg.file_manager;
-    let status = cmd(fm, [root.to_string_lossy().as_ref()])
-        .stderr_to_stdout()
-        .run()?;
+    let mut parts = fm.split_whitespace();
+    let prog = parts.next().unwrap_or(fm.as_str());
+    let args: Vec<String> = parts
+        .map(str::to_string)
+        .chain([root.to_string_lossy().into_owned()])
+        .collect();
+    let prompt_for_enter = match cfg.fm_wait {
+        FmWait::Exit => false,
+        FmWait::Prompt => true,
+        FmWait::Auto => {
+            let name = Path::new(prog)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(prog);
+            GUI_FILE_MANAGERS.contains(&name)
+        }
+    };
+
+    if !prompt_for_enter {
+        // Block until the FM exits
+        let status = cmd(prog, &args).stderr_to_stdout().run()?;
+        if !status.status.success() {
+            return Err(anyhow!("{} exited with failure", fm));
+        }
+        return Ok(());
+    }
+
+    cmd(prog, &args)
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .start()
+        .map_err(|e| anyhow!("Failed to launch {}: {}", fm, e))?;
+    wait_for_enter("Press Enter when you are done editing to start the build...")
+}
+
+/// Run `pre_build_hook`, if configured, as `hook <build_dir> <pkgbase>...`. A
+/// failure is an error unless `pre_build_hook_abort` is off, then it only warns.
+pub fn run_pre_build_hook(cfg: &Config, build_dir: &Path, pkgbases: &[String]) -> Result<()> {
+    let Some(hook) = &cfg.pre_build_hook else {
+        return Ok(());
+    };
+    println!(
+        "{} {}",
+        info_icon(),
+        prompt().apply_to(format!("Running pre_build_hook: {}", hook.display()))
+    );
+    let args = std::iter::once(build_dir.as_os_str().to_owned())
+        .chain(pkgbases.iter().map(OsString::from));
+    let failure = match cmd(hook, args).unchecked().run() {
+        Ok(out) if out.status.success() => return Ok(()),
+        Ok(out) => format!("exited with {}", out.status),
+        Err(e) => e.to_string(),
+    };
+    let msg = format!("pre_build_hook {}: {}", hook.display(), failure);
+    if cfg.pre_build_hook_abort {
+        return Err(anyhow!(msg));
+    }
+    eprintln!("{} {}", warn_icon(), warning().apply_to(msg));
+    Ok(())
+}
+
+/// Run `post_install_hook`, if configured, with the installed package names as
+/// arguments. The transaction is already done, so a failing hook only warns.
+pub fn run_post_install_hook(cfg: &Config, installed: &[String]) {
+    let Some(hook) = &cfg.post_install_hook else {
+        return;
+    };
+    println!(
+        "{} {}",
+        info_icon(),
+        prompt().apply_to(format!("Running post_install_hook: {}", hook.display()))
+    );
+    let failure = match cmd(hook, installed).unchecked().run() {
+        Ok(out) if out.status.success() => return,
+        Ok(out) => format!("exited with {}", out.status),
+        Err(e) => e.to_string(),
+    };
+    eprintln!(
+        "{} {}",
+        warn_icon(),
+        warning().apply_to(format!("post_install_hook {}: {}", hook.display(), failure))
+    );
+}
+
+/// Open `file` in the configured editor and wait for it to exit.
+pub fn open_editor(cfg: &Config, file: &Path) -> Result<()> {
+    let mut parts = cfg.editor.split_whitespace();
+    let prog = parts.next().unwrap_or("nano");
+    let args: Vec<String> = parts
+        .map(str::to_string)
+        .chain([file.to_string_lossy().into_owned()])
+        .collect();
+    let status = cmd(prog, &args).unchecked().run()?;
     if !status.status.success() {
-        return Err(anyhow!("{} exited with failure", fm));
+        return Err(anyhow!("{} exited with failure", cfg.editor));
     }
     Ok(())
 }
 
-pub fn regen_srcinfo(pkgdir: &Path) -> Result<()> {
+/// Hand `url` to `xdg-open` without waiting for the browser.
+pub fn open_in_browser(url: &str) -> Result<()> {
+    cmd("xdg-open", [url])
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .start()
+        .map_err(|e| anyhow!("Failed to launch xdg-open: {}", e))?;
+    Ok(())
+}
+
+/// Keep a copy of the PKGBUILD that was just built so the next clone can be diffed against it.
+pub fn save_built_pkgbuild(cfg: &Config, pkgbase: &str, pkgdir: &Path) -> Result<()> {
+    let dest = cfg.pkgbuild_cache_dir().join(pkgbase);
+    fs::create_dir_all(&dest)?;
+    fs::copy(pkgdir.join("PKGBUILD"), dest.join("PKGBUILD"))?;
+    Ok(())
+}
+
+/// Show what changed in a freshly cloned PKGBUILD since we last built it.
+/// Returns false when there is no previous copy or nothing changed.
+pub fn diff_against_last_build(cfg: &Config, pkgbase: &str, pkgdir: &Path) -> Result<bool> {
+    let old = cfg.pkgbuild_cache_dir().join(pkgbase).join("PKGBUILD");
+    let new = pkgdir.join("PKGBUILD");
+    if !old.exists() || !new.exists() || fs::read(&old)? == fs::read(&new)? {
+        return Ok(false);
+    }
+    println!(
+        "{} {} {}",
+        info_icon(),
+        highlight().apply_to("PKGBUILD changes since last build:"),
+        package_name().apply_to(pkgbase)
+    );
+    show_pkgbuild_diff(cfg, &old, &new)?;
+    Ok(true)
+}
+
+/// Diff two PKGBUILDs with the configured `diffprog`, falling back to
+/// `git diff --no-index` (with a warning) when it isn't installed.
+pub fn show_pkgbuild_diff(cfg: &Config, old: &Path, new: &Path) -> Result<()> {
+    let old_s = old.to_string_lossy();
+    let new_s = new.to_string_lossy();
+    if let Some(diffprog) = &cfg.diffprog {
+        let mut parts = diffprog.split_whitespace();
+        if let Some(prog) = parts.next() {
+            if which::which(prog).is_ok() {
+                let args: Vec<&str> = parts.chain([old_s.as_ref(), new_s.as_ref()]).collect();
+                // diff tools exit non-zero when files differ; that's not a failure here
+                cmd(prog, args).unchecked().run()?;
+                return Ok(());
+            }
+            println!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!(
+                    "diffprog '{}' not found, falling back to git diff",
+                    prog
+                ))
+            );
+        }
+    }
+    cmd(
+        "git",
+        ["diff", "--no-index", "--", old_s.as_ref(), new_s.as_ref()],
+    )
+    .unchecked()
+    .run()?;
+    Ok(())
+}
+
+/// Whether turbo itself runs as root (containers, CI), where makepkg refuses to work.
+pub fn running_as_root() -> bool {
+    static ROOT: OnceLock<bool> = OnceLock::new();
+    *ROOT.get_or_init(|| cmd("id", ["-u"]).read().is_ok_and(|uid| uid.trim() == "0"))
+}
+
+/// `program args`, dropped to `build_user` via runuser when running as root.
+fn as_build_user<I, S>(cfg: &Config, program: &str, args: I) -> duct::Expression
+where
+    I: IntoIterator<Item = S>,
+    S: Into<OsString>,
+{
+    match cfg.build_user.as_deref().filter(|_| running_as_root()) {
+        Some(user) => {
+            let mut full: Vec<OsString> =
+                vec!["-u".into(), user.into(), "--".into(), program.into()];
+            full.extend(args.into_iter().map(Into::into));
+            cmd("runuser", full)
+        }
+        None => cmd(program, args),
+    }
+}
+
+/// Run a makepkg shell snippet for `pkgdir`; `args` reach it as "$@", one argv
+/// element each. As root the tree is first handed to `build_user`; without one
+/// makepkg would only refuse to run.
+fn run_makepkg_sh(
+    cfg: &Config,
+    pkgdir: &Path,
+    sh: &str,
+    args: &[String],
+) -> Result<std::process::Output> {
+    let expr = makepkg_sh(cfg, pkgdir, sh, args)?.stderr_to_stdout();
+    Ok(interrupt::run_shielded(&expr)?)
+}
+
+/// `bash -lc sh` as the build user, after making sure that user can work in `pkgdir`.
+fn makepkg_sh(cfg: &Config, pkgdir: &Path, sh: &str, args: &[String]) -> Result<duct::Expression> {
+    if running_as_root() {
+        let Some(user) = cfg.build_user.as_deref() else {
+            return Err(anyhow!(
+                "makepkg refuses to run as root; set build_user in the conf to build as a regular user"
+            ));
+        };
+        cmd("chown", [OsString::from("-R"), user.into(), pkgdir.into()])
+            .run()
+            .map_err(|e| anyhow!("Could not hand {} to {}: {}", pkgdir.display(), user, e))?;
+        let writable = as_build_user(cfg, "test", [OsString::from("-w"), pkgdir.into()])
+            .unchecked()
+            .run()?;
+        if !writable.status.success() {
+            return Err(anyhow!(
+                "build user {} cannot reach {}; point build_dir at a directory it can use (e.g. /tmp)",
+                user,
+                pkgdir.display()
+            ));
+        }
+    }
+    let argv = ["-lc", sh, "bash"]
+        .into_iter()
+        .chain(args.iter().map(String::as_str));
+    Ok(as_build_user(cfg, "bash", argv))
+}
+
+pub fn regen_srcinfo(cfg: &Config, pkgdir: &Path) -> Result<()> {
     // Ensure .SRCINFO is regenerated after edits
     let sh = format!(
-        "cd {} && makepkg --printsrcinfo > .SRCINFO",
+        "cd {} && makepkg --printsrcinfo \"$@\" > .SRCINFO",
         pkgdir.to_string_lossy()
     );
-    let status = cmd("bash", ["-lc", &sh]).stderr_to_stdout().run()?;
+    let status = run_makepkg_sh(cfg, pkgdir, &sh, &makepkg_conf_args(cfg))?;
     if !status.status.success() {
         return Err(anyhow!(
             "makepkg --printsrcinfo failed in {}",
@@ -159,85 +740,447 @@ pub fn regen_srcinfo(pkgdir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn makepkg_build(pkgdir: &Path) -> Result<()> {
+/// `regen_srcinfo` after the user had a chance to edit. Skipped when the committed
+/// .SRCINFO can be trusted: `regen_srcinfo` is off, or the PKGBUILD of a git
+/// checkout is unchanged.
+pub fn regen_srcinfo_if_edited(cfg: &Config, pkgdir: &Path) -> Result<()> {
+    if !cfg.regen_srcinfo {
+        return Ok(());
+    }
+    let unchanged = pkgdir.join(".git").exists()
+        && pkgdir.join(".SRCINFO").exists()
+        && cmd("git", ["diff", "--quiet", "HEAD", "--", "PKGBUILD"])
+            .dir(pkgdir)
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .is_ok_and(|o| o.status.success());
+    if unchanged {
+        return Ok(());
+    }
+    regen_srcinfo(cfg, pkgdir)
+}
+
+/// `regen_srcinfo_if_edited` for a batch of checkouts. Each is an independent
+/// `makepkg --printsrcinfo`, so they run in parallel; the first error is returned.
+pub fn regen_srcinfos_if_edited(cfg: &Config, pkgdirs: &[PathBuf]) -> Result<()> {
+    pkgdirs
+        .par_iter()
+        .try_for_each(|dir| regen_srcinfo_if_edited(cfg, dir))
+}
+
+/// Apply the configured `source_rewrites` to the PKGBUILD's `source=()` arrays
+/// (including arch-specific ones) and regenerate .SRCINFO if anything changed.
+/// Does nothing unless rewrites are configured. Returns the number of arrays changed.
+pub fn rewrite_sources(cfg: &Config, pkgdir: &Path) -> Result<usize> {
+    if cfg.source_rewrites.is_empty() {
+        return Ok(0);
+    }
+    let rules: Vec<(Regex, &str)> = cfg
+        .source_rewrites
+        .iter()
+        .map(|(pattern, replacement)| {
+            Regex::new(pattern)
+                .map(|re| (re, replacement.as_str()))
+                .map_err(|e| anyhow!("Invalid source_rewrite pattern '{}': {}", pattern, e))
+        })
+        .collect::<Result<_>>()?;
+
+    let pkgbuild = pkgdir.join("PKGBUILD");
+    let contents = fs::read_to_string(&pkgbuild)?;
+    let (rewritten, changed) = rewrite_source_arrays(&contents, &rules);
+    if changed == 0 {
+        return Ok(0);
+    }
+    fs::write(&pkgbuild, rewritten.as_bytes())?;
+    println!(
+        "{} {}",
+        info_icon(),
+        highlight().apply_to(format!(
+            "Rewrote source URLs in {}",
+            path().apply_to(pkgbuild.display())
+        ))
+    );
+    regen_srcinfo(cfg, pkgdir)?;
+    Ok(changed)
+}
+
+/// `contents` with `rules` applied inside every `source=()` / `source_<arch>=()`
+/// array, and how many arrays changed.
+fn rewrite_source_arrays(contents: &str, rules: &[(Regex, &str)]) -> (String, usize) {
+    static ARRAY_START: OnceLock<Regex> = OnceLock::new();
+    let start = ARRAY_START
+        .get_or_init(|| Regex::new(r"(?m)^\s*source(?:_[A-Za-z0-9_]+)?=\(").expect("valid regex"));
+    let mut out = String::with_capacity(contents.len());
+    let mut changed = 0;
+    let mut done = 0;
+    for m in start.find_iter(contents) {
+        if m.start() < done {
+            continue;
+        }
+        let Some(len) = closing_paren(&contents[m.end()..]) else {
+            continue;
+        };
+        let body = &contents[m.end()..m.end() + len];
+        let mut new_body = body.to_string();
+        for (re, replacement) in rules {
+            new_body = re.replace_all(&new_body, *replacement).into_owned();
+        }
+        if new_body != body {
+            changed += 1;
+        }
+        out.push_str(&contents[done..m.end()]);
+        out.push_str(&new_body);
+        done = m.end() + len;
+    }
+    out.push_str(&contents[done..]);
+    (out, changed)
+}
+
+/// Byte offset of the `)` closing a bash array or `$(...)` whose body starts
+/// at the beginning of `s`. Quotes, escapes, comments and nested parentheses
+/// are skipped, so `)` inside `"$(cmd)"` or `'a)b'` does not end it.
+fn closing_paren(s: &str) -> Option<usize> {
+    let b = s.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < b.len() {
+        match b[i] {
+            b'\\' => i += 1,
+            b'\'' => i += b[i + 1..].iter().position(|&c| c == b'\'')? + 1,
+            b'"' => {
+                i += 1;
+                while i < b.len() && b[i] != b'"' {
+                    match b[i] {
+                        b'\\' => i += 1,
+                        b'$' if b.get(i + 1) == Some(&b'(') => {
+                            i += 2 + closing_paren(&s[i + 2..])?;
+                        }
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                if i >= b.len() {
+                    return None;
+                }
+            }
+            b'#' if i == 0 || b[i - 1].is_ascii_whitespace() || b[i - 1] == b'(' => {
+                i += b[i..].iter().position(|&c| c == b'\n')?;
+            }
+            b'(' => depth += 1,
+            b')' if depth == 0 => return Some(i),
+            b')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// `--config <file>` for --makepkg-conf; every makepkg run gets it.
+fn makepkg_conf_args(cfg: &Config) -> Vec<String> {
+    cfg.makepkg_conf
+        .iter()
+        .flat_map(|conf| {
+            [
+                String::from("--config"),
+                conf.to_string_lossy().into_owned(),
+            ]
+        })
+        .collect()
+}
+
+fn makepkg_build_args(cfg: &Config) -> Vec<String> {
+    let mut args = makepkg_conf_args(cfg);
+    args.extend(
+        cfg.assume_installed
+            .iter()
+            .flat_map(|dep| [String::from("--assume-installed"), dep.clone()]),
+    );
+    args
+}
+
+/// What `makepkg_build` runs, as a shell command line (for --print).
+pub fn makepkg_command_line(cfg: &Config, pkgdir: &Path) -> String {
+    let mut line = format!(
+        "cd {} && {}makepkg -s -f --cleanbuild --noconfirm",
+        shell_quote(&pkgdir.to_string_lossy()),
+        priority_prefix(cfg.build_nice)
+    );
+    for arg in makepkg_build_args(cfg) {
+        line.push(' ');
+        line.push_str(&shell_quote(&arg));
+    }
+    match cfg.build_user.as_deref().filter(|_| running_as_root()) {
+        Some(user) => format!(
+            "runuser -u {} -- bash -lc {}",
+            shell_quote(user),
+            shell_quote(&line)
+        ),
+        None => line,
+    }
+}
+
+pub fn makepkg_build(cfg: &Config, pkgdir: &Path) -> Result<()> {
     let sh = format!(
-        "cd {} && makepkg -s -f --cleanbuild --noconfirm",
-        pkgdir.to_string_lossy()
+        "cd {} && {}makepkg -s -f --cleanbuild --noconfirm \"$@\"",
+        pkgdir.to_string_lossy(),
+        priority_prefix(cfg.build_nice)
     );
-    let status = cmd("bash", ["-lc", &sh]).stderr_to_stdout().run()?;
+    let args = makepkg_build_args(cfg);
+    crate::pac::refresh_sudo_timestamp();
+    let status = run_makepkg_sh(cfg, pkgdir, &sh, &args)?;
     if !status.status.success() {
         return Err(anyhow!("makepkg build failed in {}", pkgdir.display()));
     }
     Ok(())
 }
 
-pub fn collect_zsts(root: &Path, allowed: Option<&HashSet<String>>) -> Result<Vec<String>> {
-    let mut out: Vec<String> =
-        globwalk::GlobWalkerBuilder::from_patterns(root, &["**/*.pkg.tar.zst"])
-            .follow_links(true)
-            .build()?
-            .filter_map(Result::ok)
-            .map(|entry| entry.path().to_string_lossy().into_owned())
-            .collect();
-
-    if let Some(names) = allowed {
-        if !out.is_empty() {
-            let mut args: Vec<&str> = Vec::with_capacity(2 + out.len());
-            args.push("-Qpq");
-            args.push("--");
-            for path in &out {
-                args.push(path.as_str());
-            }
-            let output = cmd("pacman", args)
-                .stderr_to_stdout()
-                .read()
-                .map_err(|e| anyhow!("pacman -Qpq failed: {}", e))?;
-            let pkg_names: Vec<String> =
-                output.lines().map(|line| line.trim().to_string()).collect();
-            if pkg_names.len() != out.len() {
-                return Err(anyhow!(
-                    "pacman -Qpq returned {} names for {} artifacts",
-                    pkg_names.len(),
-                    out.len()
-                ));
+/// `nice`/`ionice` wrapper for low-priority builds; each is skipped if not installed.
+fn priority_prefix(nice: Option<u8>) -> String {
+    let Some(n) = nice else {
+        return String::new();
+    };
+    let mut prefix = String::new();
+    if which::which("nice").is_ok() {
+        prefix.push_str(&format!("nice -n {} ", n));
+    }
+    if which::which("ionice").is_ok() {
+        prefix.push_str("ionice -c 3 ");
+    }
+    prefix
+}
+
+/// Split an artifact file name `<pkgname>-<pkgver>-<pkgrel>-<arch>.pkg.tar.zst`
+/// into `(pkgname, "pkgver-pkgrel")`.
+fn split_artifact_name(path: &str) -> Option<(&str, String)> {
+    let file = Path::new(path).file_name()?.to_str()?;
+    let mut parts = file.rsplitn(4, '-');
+    let (_arch, rel, ver) = (parts.next()?, parts.next()?, parts.next()?);
+    Some((parts.next()?, format!("{}-{}", ver, rel)))
+}
+
+fn artifact_pkgname(path: &str) -> Option<&str> {
+    split_artifact_name(path).map(|(name, _)| name)
+}
+
+/// Versions of each package kept in `pkg_cache_dir()`, like `paccache -rk3`.
+const PKG_CACHE_KEEP: usize = 3;
+
+/// Copy installed artifacts into `pkg_cache_dir()`; file names carry the version,
+/// so older builds stay available next to newer ones, up to `PKG_CACHE_KEEP`.
+pub fn cache_artifacts(cfg: &Config, zsts: &[String]) -> Result<()> {
+    let dir = cfg.pkg_cache_dir();
+    fs::create_dir_all(&dir)?;
+    for z in zsts {
+        let src = Path::new(z);
+        if let Some(file) = src.file_name() {
+            fs::copy(src, dir.join(file))?;
+        }
+    }
+    let mut names: Vec<&str> = zsts.iter().filter_map(|z| artifact_pkgname(z)).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        prune_cached_versions(cfg, name)?;
+    }
+    Ok(())
+}
+
+/// Remove all but the newest `PKG_CACHE_KEEP` cached versions of `name`.
+fn prune_cached_versions(cfg: &Config, name: &str) -> Result<()> {
+    let mut cached = find_cached_artifacts(cfg, name)?;
+    if cached.len() <= PKG_CACHE_KEEP {
+        return Ok(());
+    }
+    // Newest first; a version vercmp cannot compare stays where it is
+    cached.sort_by(|(a, _), (b, _)| {
+        crate::pac::vercmp_blocking(b, a)
+            .map(|v| v.cmp(&0))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    for (_, path) in cached.into_iter().skip(PKG_CACHE_KEEP) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Cached artifacts for `name` as `(version, path)` pairs, unordered.
+pub fn find_cached_artifacts(cfg: &Config, name: &str) -> Result<Vec<(String, String)>> {
+    let dir = cfg.pkg_cache_dir();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut out = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path().to_string_lossy().into_owned();
+        if !path.ends_with(".pkg.tar.zst") {
+            continue;
+        }
+        if let Some((pkg, version)) = split_artifact_name(&path) {
+            if pkg == name {
+                out.push((version, path.clone()));
             }
-            let filtered: Vec<String> = out
-                .into_iter()
-                .zip(pkg_names.into_iter())
-                .filter_map(|(path, pkg_name)| {
-                    if names.contains(&pkg_name) {
-                        Some(path)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            out = filtered;
         }
     }
+    Ok(out)
+}
 
+/// Gather built artifacts under `root`. When `allowed` is given only artifacts whose
+/// pkgname is in the set are kept; `-debug` split packages are dropped unless
+/// `include_debug` is set or they were asked for explicitly.
+pub fn collect_zsts(
+    root: &Path,
+    allowed: Option<&HashSet<String>>,
+    include_debug: bool,
+) -> Result<Vec<String>> {
+    let out: Vec<String> = globwalk::GlobWalkerBuilder::from_patterns(root, &["**/*.pkg.tar.zst"])
+        .follow_links(true)
+        .build()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .collect();
+
+    // The file name can be ambiguous (e.g. foo-bar-1-1 vs foo-bar-1), so the
+    // allow list is matched against the pkgname recorded inside the artifact
+    let pkg_names = match allowed {
+        Some(_) if !out.is_empty() => query_pkgnames(&out)?,
+        _ => out
+            .iter()
+            .map(|path| artifact_pkgname(path).unwrap_or_default().to_string())
+            .collect(),
+    };
+    let mut out = select_artifacts(out.into_iter().zip(pkg_names), allowed, include_debug);
     out.sort();
     out.dedup();
     Ok(out)
 }
 
-pub fn verify_sources(pkgdir: &Path) -> Result<()> {
-    // Verify and fetch sources and signatures before heavy build
+/// The pkgname of each artifact, per `pacman -Qpq`.
+fn query_pkgnames(paths: &[String]) -> Result<Vec<String>> {
+    let output = cmd(
+        "pacman",
+        ["-Qpq", "--"]
+            .into_iter()
+            .chain(paths.iter().map(String::as_str)),
+    )
+    .stderr_to_stdout()
+    .read()
+    .map_err(|e| anyhow!("pacman -Qpq failed: {}", e))?;
+    let names: Vec<String> = output.lines().map(|line| line.trim().to_string()).collect();
+    if names.len() != paths.len() {
+        return Err(anyhow!(
+            "pacman -Qpq returned {} names for {} artifacts",
+            names.len(),
+            paths.len()
+        ));
+    }
+    Ok(names)
+}
+
+/// The paths of the `(path, pkgname)` artifacts that `collect_zsts` keeps.
+fn select_artifacts(
+    artifacts: impl IntoIterator<Item = (String, String)>,
+    allowed: Option<&HashSet<String>>,
+    include_debug: bool,
+) -> Vec<String> {
+    artifacts
+        .into_iter()
+        .filter(|(_, name)| allowed.is_none_or(|names| names.contains(name)))
+        .filter(|(_, name)| {
+            include_debug
+                || !name.ends_with("-debug")
+                || allowed.is_some_and(|names| names.contains(name))
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+const VERIFY_RETRY_DELAY_SECS: u64 = 5;
+
+/// How a single `makepkg --verifysource` run ended.
+enum VerifyOutcome {
+    Verified,
+    /// A checksum or signature did not match; fetching again won't help
+    Mismatch,
+    /// Download error, timeout, etc.
+    Failed,
+}
+
+/// makepkg messages (under LC_ALL=C) that mean the sources arrived but are wrong.
+const MISMATCH_MARKERS: &[&str] = &[
+    "did not pass the validity check",
+    "PGP signatures could not be verified",
+];
+
+fn verify_sources_once(cfg: &Config, pkgdir: &Path) -> Result<VerifyOutcome> {
     let sh = format!(
-        "cd {} && makepkg --verifysource --noconfirm",
+        "cd {} && LC_ALL=C makepkg --verifysource --noconfirm \"$@\"",
         pkgdir.to_string_lossy()
     );
-    let status = cmd("bash", ["-lc", &sh]).stderr_to_stdout().run()?;
-    if !status.status.success() {
-        return Err(anyhow!(
-            "makepkg --verifysource failed in {}",
-            pkgdir.display()
-        ));
+    let expr = makepkg_sh(cfg, pkgdir, &sh, &makepkg_conf_args(cfg))?;
+    // Echo the output as it comes (download progress), keeping a copy to classify a failure
+    let mut reader = interrupt::shielded(&expr.stderr_to_stdout().unchecked()).reader()?;
+    let _tracked = interrupt::track_children(reader.pids());
+    let mut log: Vec<u8> = vec![];
+    let mut buf = [0u8; 8192];
+    let mut stdout = std::io::stdout();
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        stdout.write_all(&buf[..n])?;
+        stdout.flush()?;
+        log.extend_from_slice(&buf[..n]);
     }
-    Ok(())
+    if reader.try_wait()?.is_some_and(|out| out.status.success()) {
+        return Ok(VerifyOutcome::Verified);
+    }
+    let log = String::from_utf8_lossy(&log);
+    if MISMATCH_MARKERS.iter().any(|m| log.contains(m)) {
+        Ok(VerifyOutcome::Mismatch)
+    } else {
+        Ok(VerifyOutcome::Failed)
+    }
+}
+
+/// Fetch and verify sources and signatures before the heavy build. A download
+/// error is retried up to `max_retries` times; a mismatch fails immediately.
+pub fn verify_sources(cfg: &Config, pkgdir: &Path) -> Result<()> {
+    let attempts = cfg.max_retries + 1;
+    for attempt in 1..=attempts {
+        match verify_sources_once(cfg, pkgdir)? {
+            VerifyOutcome::Verified => return Ok(()),
+            VerifyOutcome::Mismatch => {
+                return Err(anyhow!(
+                    "makepkg --verifysource failed in {}: checksum or signature mismatch",
+                    pkgdir.display()
+                ))
+            }
+            VerifyOutcome::Failed if attempt < attempts => {
+                println!(
+                    "  {} {}",
+                    warn_icon(),
+                    warning().apply_to(format!(
+                        "Fetching sources failed (attempt {}/{}); retrying in {}s",
+                        attempt, attempts, VERIFY_RETRY_DELAY_SECS
+                    ))
+                );
+                std::thread::sleep(std::time::Duration::from_secs(VERIFY_RETRY_DELAY_SECS));
+            }
+            VerifyOutcome::Failed => {}
+        }
+    }
+    Err(anyhow!(
+        "makepkg --verifysource failed in {} after {} attempt(s)",
+        pkgdir.display(),
+        attempts
+    ))
 }
 
-pub fn import_validpgpkeys(pkgdir: &Path) -> Result<()> {
+pub fn import_validpgpkeys(cfg: &Config, pkgdir: &Path) -> Result<()> {
     let sh = format!(
         "cd {} && set -a; source PKGBUILD >/dev/null 2>&1 || true; for k in \"${{validpgpkeys[@]}}\"; do echo $k; done",
         pkgdir.to_string_lossy()
@@ -253,21 +1196,26 @@ pub fn import_validpgpkeys(pkgdir: &Path) -> Result<()> {
     if keys.is_empty() {
         return Ok(());
     }
-    let servers = [
-        "hkps://keys.openpgp.org",
-        "hkps://keyserver.ubuntu.com",
-        "hkps://keys.mailvelope.com",
-    ];
+    recv_keys(cfg, &keys).map(|_| ())
+}
+
+const KEYSERVERS: [&str; 3] = [
+    "hkps://keys.openpgp.org",
+    "hkps://keyserver.ubuntu.com",
+    "hkps://keys.mailvelope.com",
+];
+
+/// `gpg --recv-keys`, trying each keyserver in turn. Returns the server that had the keys.
+/// As root with a `build_user`, the keys go into that user's keyring, which makepkg checks.
+pub fn recv_keys(cfg: &Config, keys: &[&str]) -> Result<&'static str> {
     let mut last_err: Option<anyhow::Error> = None;
-    for srv in &servers {
+    for srv in KEYSERVERS {
         let mut args: Vec<&str> = vec!["--keyserver", srv, "--recv-keys"];
-        for k in &keys {
-            args.push(k);
-        }
-        let res = cmd("gpg", args).stderr_to_stdout().run();
+        args.extend(keys);
+        let res = as_build_user(cfg, "gpg", args).stderr_to_stdout().run();
         match res {
             Ok(st) if st.status.success() => {
-                return Ok(());
+                return Ok(srv);
             }
             Ok(st) => {
                 last_err = Some(anyhow!(
@@ -284,11 +1232,157 @@ pub fn import_validpgpkeys(pkgdir: &Path) -> Result<()> {
     Err(last_err.unwrap_or_else(|| anyhow!("gpg --recv-keys failed")))
 }
 
+/// Free bytes (for unprivileged users) on the filesystem holding `path`; None if
+/// it can't be told.
+fn free_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is plain old data, filled in by the call below
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `st` is a valid statvfs to write to
+    if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    Some(st.f_bavail.saturating_mul(st.f_frsize))
+}
+
+/// Error when fewer than `min_bytes` are free at `path`. Unknown free space passes.
+pub fn check_free_space(path: &Path, min_bytes: u64) -> Result<()> {
+    match free_space(path) {
+        Some(free) if free < min_bytes => Err(anyhow!(
+            "only {} MiB free in {} (minimum {} MiB)",
+            free >> 20,
+            path.display(),
+            min_bytes >> 20
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Preflight before building: with less than `min_free_mb` free in `dir`, ask
+/// before building (unattended runs abort instead). Without an explicit
+/// `min_free_mb`, falling short of `DEFAULT_MIN_FREE_MB` only warns.
+pub fn ensure_free_space(cfg: &Config, dir: &Path) -> Result<()> {
+    let min_mb = cfg.min_free_mb.unwrap_or(DEFAULT_MIN_FREE_MB);
+    if min_mb == 0 {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    let Err(e) = check_free_space(dir, min_mb << 20) else {
+        return Ok(());
+    };
+    eprintln!(
+        "{} {}",
+        warn_icon(),
+        warning().apply_to(format!("Low disk space: {}", e))
+    );
+    if cfg.min_free_mb.is_none() {
+        return Ok(());
+    }
+    if cfg.noconfirm || !confirm("Build anyway?", false)? {
+        return Err(anyhow!("Not enough free disk space to build: {}", e));
+    }
+    Ok(())
+}
+
+/// Tools makepkg needs from base-devel; a missing one means builds will fail confusingly.
+const BUILD_TOOLS: [&str; 3] = ["gcc", "make", "fakeroot"];
+
+/// Preflight before the first build: offer to install base-devel when it isn't
+/// installed (as a package or, on older systems, a group) and build tools are missing.
+pub fn ensure_build_tools(cfg: &Config) -> Result<()> {
+    let pacman = crate::pac::get_pacman();
+    let installed = [["-Q", "base-devel"], ["-Qg", "base-devel"]]
+        .iter()
+        .any(|args| {
+            cmd(pacman, args)
+                .stdout_null()
+                .stderr_null()
+                .unchecked()
+                .run()
+                .is_ok_and(|o| o.status.success())
+        });
+    let missing: Vec<&str> = BUILD_TOOLS
+        .into_iter()
+        .filter(|tool| which::which(tool).is_err())
+        .collect();
+    if installed || missing.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "{} {}",
+        warn_icon(),
+        warning().apply_to(format!(
+            "base-devel is not installed and {} {} missing; AUR builds need them",
+            missing.join(", "),
+            if missing.len() == 1 { "is" } else { "are" }
+        ))
+    );
+    let install = cfg.noconfirm
+        || confirm(
+            "Install base-devel now (pacman -S --needed base-devel)?",
+            true,
+        )?;
+    if !install {
+        return Err(anyhow!(
+            "base-devel is required to build AUR packages ({} missing)",
+            missing.join(", ")
+        ));
+    }
+    let args = [String::from("--needed"), String::from("base-devel")];
+    crate::pac::install_repo_packages(&args, cfg.noconfirm)
+}
+
 pub fn ensure_persistent_dirs(cfg: &Config) -> Result<()> {
-    fs::create_dir_all(cfg.temp_dir())?;
+    fs::create_dir_all(cfg.cache_dir())?;
+    let temp = cfg.temp_dir();
+    fs::create_dir_all(&temp)
+        .map_err(|e| anyhow!("Cannot create build dir {}: {}", temp.display(), e))?;
+    if cfg.build_dir.is_some() {
+        check_build_dir(&temp)?;
+    }
     Ok(())
 }
 
+/// Make sure a custom build_dir is writable and warn when it sits on a tmpfs,
+/// which is fast but RAM-backed and may be too small for large builds.
+fn check_build_dir(dir: &Path) -> Result<()> {
+    let probe = dir.join(".turbo-write-test");
+    fs::write(&probe, b"")
+        .map_err(|e| anyhow!("Build dir {} is not writable: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+
+    if mount_fstype(dir).as_deref() == Some("tmpfs") {
+        println!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!(
+                "Build dir {} is on a tmpfs; large builds may run out of space",
+                dir.display()
+            ))
+        );
+    }
+    Ok(())
+}
+
+/// Filesystem type of the mount containing `dir`, from /proc/mounts.
+fn mount_fstype(dir: &Path) -> Option<String> {
+    let dir = fs::canonicalize(dir).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fstype = fields.next()?;
+            Some((mount_point, fstype))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, fstype)| fstype.to_string())
+}
+
 pub fn clean_dir_contents(dir: &Path) -> Result<()> {
     if dir.exists() {
         for entry in fs::read_dir(dir)? {
@@ -303,9 +1397,312 @@ pub fn clean_dir_contents(dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Wipes the temp build tree when dropped, so early returns (`?`) and panics
+/// between the start and the end of a build run do not leave it dirty.
+/// The normal path calls `disarm` and does its own, more selective cleanup.
+pub struct TempDirGuard<'a> {
+    cfg: &'a Config,
+    dir: PathBuf,
+    armed: bool,
+}
+
+impl<'a> TempDirGuard<'a> {
+    pub fn new(cfg: &'a Config, dir: &Path) -> Self {
+        Self {
+            cfg,
+            dir: dir.to_path_buf(),
+            armed: true,
+        }
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for TempDirGuard<'_> {
+    fn drop(&mut self) {
+        // Failed trees kept for inspection are wiped at the start of the next run anyway
+        if !self.armed || self.cfg.reuse_temp || self.cfg.keep_failed_builds {
+            return;
+        }
+        if let Err(e) = clean_dir_contents(&self.dir) {
+            eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!("Failed to clean {}: {}", self.dir.display(), e))
+            );
+        }
+    }
+}
+
+/// Removes this run's own temp subdir (`per_run_temp`) when dropped at the end
+/// of `main`. Only an empty dir goes, so failed trees kept for inspection stay.
+pub struct RunTempDir(pub Option<PathBuf>);
+
+impl Drop for RunTempDir {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.0 {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// `per_run_temp`: remove the subdirs left by runs that have exited, kept
+/// failed builds and -Sw downloads included, as the shared tree is wiped at
+/// the start of a run. Nothing is removed when `reuse_temp` is set.
+pub fn prune_stale_run_dirs(cfg: &Config) {
+    if !cfg.per_run_temp || cfg.reuse_temp {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(cfg.temp_root()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(run_dir_pid) else {
+            continue;
+        };
+        if process_alive(pid) {
+            continue;
+        }
+        if let Err(e) = fs::remove_dir_all(entry.path()) {
+            eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!(
+                    "Failed to remove {}: {}",
+                    entry.path().display(),
+                    e
+                ))
+            );
+        }
+    }
+}
+
+/// The pid in a `<pid>-<timestamp>` run dir name.
+fn run_dir_pid(name: &str) -> Option<libc::pid_t> {
+    let (pid, secs) = name.split_once('-')?;
+    secs.parse::<u64>().ok()?;
+    pid.parse().ok().filter(|pid| *pid > 0)
+}
+
+fn process_alive(pid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 only checks that the process exists
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Like `clean_dir_contents`, but leaves the top-level entries named in `keep`.
+pub fn clean_dir_except(dir: &Path, keep: &[String]) -> Result<()> {
+    if dir.exists() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if keep.iter().any(|k| entry.file_name() == k.as_str()) {
+                continue;
+            }
+            let p = entry.path();
+            if p.is_dir() {
+                fs::remove_dir_all(&p)?;
+            } else {
+                fs::remove_file(&p)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `-Scc`: wipe the temp build tree. Cached built packages stay (pruned to
+/// `PKG_CACHE_KEEP` versions as they are added), since they are what --reinstall
+/// and --downgrade work from when the AUR is out of reach.
 pub fn clean_cache(cfg: &Config) -> Result<()> {
     fs::create_dir_all(cfg.cache_dir())?;
-    cmd("sudo", ["rm", "-rf", cfg.temp_dir().to_str().unwrap()]).run()?;
-    println!("{} Cache cleaned", cfg.temp_dir().display());
+    crate::pac::sudo(["rm", "-rf", cfg.temp_root().to_str().unwrap()]).run()?;
+    println!("{} Cache cleaned", cfg.temp_root().display());
+    println!(
+        "  {} {}",
+        dim().apply_to("↳"),
+        dim().apply_to(format!(
+            "Built packages in {} are kept for --reinstall and --downgrade",
+            cfg.pkg_cache_dir().display()
+        ))
+    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temp tree with one package dir and one file in it.
+    fn populated_tree() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("foo/src")).unwrap();
+        fs::write(tmp.path().join("foo/PKGBUILD"), "pkgname=foo\n").unwrap();
+        tmp
+    }
+
+    fn is_empty(dir: &Path) -> bool {
+        fs::read_dir(dir).unwrap().next().is_none()
+    }
+
+    fn fails_midway(cfg: &Config, dir: &Path) -> Result<()> {
+        let _guard = TempDirGuard::new(cfg, dir);
+        Err(anyhow!("clone failed"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn armed_guard_empties_tree_on_early_return() {
+        let tmp = populated_tree();
+        assert!(fails_midway(&Config::default(), tmp.path()).is_err());
+        assert!(tmp.path().exists());
+        assert!(is_empty(tmp.path()));
+    }
+
+    #[test]
+    fn disarmed_guard_leaves_tree() {
+        let tmp = populated_tree();
+        let cfg = Config::default();
+        let mut guard = TempDirGuard::new(&cfg, tmp.path());
+        guard.disarm();
+        drop(guard);
+        assert!(tmp.path().join("foo/PKGBUILD").exists());
+    }
+
+    #[test]
+    fn reuse_temp_and_keep_failed_builds_leave_tree() {
+        for cfg in [
+            Config {
+                reuse_temp: true,
+                ..Config::default()
+            },
+            Config {
+                keep_failed_builds: true,
+                ..Config::default()
+            },
+        ] {
+            let tmp = populated_tree();
+            assert!(fails_midway(&cfg, tmp.path()).is_err());
+            assert!(tmp.path().join("foo/PKGBUILD").exists());
+        }
+    }
+
+    /// foo, its -debug split package and bar, as makepkg leaves them.
+    fn artifact_tree() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        for (dir, file) in [
+            ("foo", "foo-1.0-1-x86_64.pkg.tar.zst"),
+            ("foo", "foo-debug-1.0-1-x86_64.pkg.tar.zst"),
+            ("bar", "bar-2.0-1-any.pkg.tar.zst"),
+        ] {
+            fs::create_dir_all(tmp.path().join(dir)).unwrap();
+            fs::write(tmp.path().join(dir).join(file), "").unwrap();
+        }
+        tmp
+    }
+
+    fn file_names(paths: &[String]) -> Vec<&str> {
+        paths
+            .iter()
+            .filter_map(|p| Path::new(p).file_name()?.to_str())
+            .collect()
+    }
+
+    #[test]
+    fn collect_zsts_drops_debug_unless_included() {
+        let tmp = artifact_tree();
+        let zsts = collect_zsts(tmp.path(), None, false).unwrap();
+        assert_eq!(
+            file_names(&zsts),
+            ["bar-2.0-1-any.pkg.tar.zst", "foo-1.0-1-x86_64.pkg.tar.zst"]
+        );
+        let zsts = collect_zsts(tmp.path(), None, true).unwrap();
+        assert_eq!(zsts.len(), 3);
+    }
+
+    #[test]
+    fn select_artifacts_honours_allowed_names() {
+        let artifacts = || {
+            ["foo", "foo-debug", "bar"].map(|n| (format!("/b/{}.pkg.tar.zst", n), n.to_string()))
+        };
+        let only_foo: HashSet<String> = [String::from("foo")].into();
+        assert_eq!(
+            select_artifacts(artifacts(), Some(&only_foo), false),
+            ["/b/foo.pkg.tar.zst"]
+        );
+        // A -debug package asked for by name is kept without include_debug
+        let with_debug: HashSet<String> = ["foo", "foo-debug"].map(String::from).into();
+        assert_eq!(
+            select_artifacts(artifacts(), Some(&with_debug), false),
+            ["/b/foo.pkg.tar.zst", "/b/foo-debug.pkg.tar.zst"]
+        );
+    }
+
+    #[test]
+    fn clone_args_for_plus_names() {
+        let cfg = Config::default();
+        let target = Path::new("/tmp/t/gtk+");
+        let spec = |source| AurCloneSpec {
+            pkgbase: String::from("gtk+"),
+            source,
+        };
+        let (args, _) = clone_args(&cfg, &spec(AurSource::Official), target, 0);
+        assert_eq!(
+            args,
+            [
+                "clone",
+                "--progress",
+                "https://aur.archlinux.org/gtk%2B.git",
+                "/tmp/t/gtk+"
+            ]
+        );
+        // A branch name is no URL, so it stays as is
+        let (args, _) = clone_args(&cfg, &spec(AurSource::Github), target, 1);
+        assert_eq!(
+            args,
+            [
+                "clone",
+                "--progress",
+                "--depth",
+                "1",
+                "--single-branch",
+                "--branch",
+                "gtk+",
+                "https://github.com/archlinux/aur",
+                "/tmp/t/gtk+"
+            ]
+        );
+    }
+
+    #[test]
+    fn run_dir_names() {
+        assert_eq!(run_dir_pid("4242-1760000000"), Some(4242));
+        assert_eq!(run_dir_pid("0ad-data"), None);
+        assert_eq!(run_dir_pid("2048-qt"), None);
+        assert_eq!(run_dir_pid("foo"), None);
+    }
+
+    #[test]
+    fn source_rewrite_spans_parens_in_quotes() {
+        let rules = [(
+            Regex::new("https://github.com/").unwrap(),
+            "https://mirror.example/gh/",
+        )];
+        let pkgbuild = r#"pkgname=foo
+source=("foo-$(date +%Y).tar.gz::https://github.com/a/foo/archive/v1.tar.gz"
+        'notes (old).txt'
+        # a comment with ) in it
+        "https://github.com/a/foo/releases/v1.sig")
+source_x86_64=("https://github.com/a/foo-bin")
+build() { echo "source=(https://github.com/)"; }
+"#;
+        let (out, changed) = rewrite_source_arrays(pkgbuild, &rules);
+        assert_eq!(changed, 2);
+        assert!(out.contains("::https://mirror.example/gh/a/foo/archive/v1.tar.gz"));
+        assert!(out.contains("\"https://mirror.example/gh/a/foo/releases/v1.sig\")"));
+        assert!(out.contains("source_x86_64=(\"https://mirror.example/gh/a/foo-bin\")"));
+        // Not an array assignment at the start of a line
+        assert!(out.contains("echo \"source=(https://github.com/)\""));
+    }
+}
@@ -1,7 +1,130 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use home::home_dir;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Free space (MiB) below which a build is warned about when `min_free_mb` is unset.
+pub const DEFAULT_MIN_FREE_MB: u64 = 2048;
+
+/// `<pid>-<timestamp>` naming this run's temp subdir; fixed on first use.
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// A credential that must never show up in logs; `Debug` prints a placeholder.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// How to tell that the user is done with the file manager (`fm_wait`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FmWait {
+    /// Prompt for GUI file managers that return immediately, wait for the rest
+    Auto,
+    /// Wait for the file manager process to exit (terminal FMs like nnn/lf)
+    Exit,
+    /// Launch it without waiting and ask the user to press Enter when done
+    Prompt,
+}
+
+impl FmWait {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "auto" => Some(FmWait::Auto),
+            "exit" => Some(FmWait::Exit),
+            "prompt" | "enter" => Some(FmWait::Prompt),
+            _ => None,
+        }
+    }
+}
+
+/// What the update pickers select when the user just presses Enter (`default_selection`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultSelection {
+    All,
+    None,
+}
+
+impl DefaultSelection {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "all" => Some(DefaultSelection::All),
+            "none" => Some(DefaultSelection::None),
+            _ => None,
+        }
+    }
+}
+
+/// Color palette for all output (`color_theme`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorTheme {
+    Default,
+    /// Darker colors for light terminal backgrounds
+    Light,
+    /// Bold/italic only, no colors
+    Monochrome,
+    /// Bright base colors only
+    HighContrast,
+}
+
+impl ColorTheme {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "default" => Some(ColorTheme::Default),
+            "light" => Some(ColorTheme::Light),
+            "monochrome" | "mono" => Some(ColorTheme::Monochrome),
+            "high-contrast" | "high_contrast" => Some(ColorTheme::HighContrast),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ColorTheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorTheme::Default => "default",
+            ColorTheme::Light => "light",
+            ColorTheme::Monochrome => "monochrome",
+            ColorTheme::HighContrast => "high-contrast",
+        })
+    }
+}
+
+/// Whether to open the file manager on the cloned tree before building.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Ask,
+    Always,
+    Never,
+}
+
+impl EditMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "ask" => Some(EditMode::Ask),
+            "always" | "true" | "yes" => Some(EditMode::Always),
+            "never" | "false" | "no" => Some(EditMode::Never),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -13,6 +136,46 @@ pub struct Config {
     pub noconfirm: bool,
     pub pacman: String,
     pub sudo: String,
+    pub reuse_temp: bool, // keep the temp tree between runs instead of wiping it
+    pub per_run_temp: bool, // build under a <pid>-<timestamp> subdir of the temp tree
+    pub clone_depth: u32, // git clone --depth; 0 means a full clone
+    pub max_retries: u32, // extra makepkg --verifysource attempts after a download error
+    pub github_token: Option<Secret>, // sent as `Authorization: Bearer` to the GitHub mirror
+    pub mirror_headers: Vec<(String, Secret)>, // extra headers for GitHub mirror requests
+    pub include_debug: bool, // also install `-debug` split packages
+    pub build_dir: Option<PathBuf>, // clone/build here (e.g. a tmpfs) instead of cache/temp
+    pub edit: EditMode,   // ask (default), always or never open the file manager
+    pub diffprog: Option<String>, // e.g. "delta"; falls back to git diff --no-index
+    pub source_rewrites: Vec<(String, String)>, // regex -> replacement for PKGBUILD source=() URLs
+    pub sync_db_max_age_hours: u64, // warn before AUR builds when sync DBs are older than this
+    pub require_fresh_db: bool, // refuse (instead of warn) when they are
+    pub timeout_secs: Option<u64>, // network/git clone timeout; None keeps per-call defaults
+    pub keep_failed_builds: bool, // end-of-run cleanup keeps trees of failed builds
+    pub repo_priority: Vec<String>, // preferred repos for names found in several
+    pub news_check: bool, // show unread Arch news before -Syu and ask to continue
+    pub origins: HashMap<&'static str, &'static str>, // setting -> layer that last changed it (--print-config)
+    pub strict_config: bool, // error on unknown conf keys instead of ignoring them
+    pub unknown_conf_keys: Vec<String>, // conf keys not recognised, for strict mode
+    pub fm_wait: FmWait,     // how to know the file manager is done (GUI FMs return at once)
+    pub default_selection: DefaultSelection, // picker default: all (Enter updates everything) or none
+    pub color_theme: ColorTheme,
+    pub build_nice: Option<u8>, // run makepkg under nice -n N (and ionice -c 3) when set
+    pub aur_groups: BTreeMap<String, Vec<String>>, // informal AUR groups for --ignoregroup (group.NAME = pkgs)
+    pub pinned_refs: BTreeMap<String, String>, // pkgbase -> git ref to build (pin.NAME = ref), cloned in full
+    pub build_user: Option<String>, // as root, run makepkg (and gpg) as this user via runuser
+    pub sudoflags: Vec<String>,     // extra sudo arguments (e.g. -A), inserted before the command
+    pub min_free_mb: Option<u64>, // free space needed in the build dir before building; 0 disables the check, unset only warns below DEFAULT_MIN_FREE_MB
+    pub regen_srcinfo: bool,      // regenerate .SRCINFO after edits; false trusts the committed one
+    pub assume_installed: Vec<String>, // --assume-installed pkg=ver, passed on to makepkg
+    pub use_keyring: bool,        // look github_token up in the system keyring first
+    pub output_dir: Option<PathBuf>, // where -P writes needupdate.json (default: root dir)
+    pub clone_dir: Option<PathBuf>, // persistent AUR clones, pulled each run and copied to temp
+    pub post_install_hook: Option<PathBuf>, // run with the installed names after a clean install
+    pub pre_build_hook: Option<PathBuf>, // run with the build dir and pkgbases before building
+    pub pre_build_hook_abort: bool, // a failing pre_build_hook stops the run
+    pub verbose: bool,            // extra diagnostics, e.g. unknown AUR RPC fields
+    pub prefer_source: Vec<String>, // AUR sources to try in order ("official", "github")
+    pub makepkg_conf: Option<PathBuf>, // --makepkg-conf, passed on to makepkg as --config
 }
 
 impl Default for Config {
@@ -26,6 +189,46 @@ impl Default for Config {
             noconfirm: false,
             pacman: "pacman".to_string(),
             sudo: "sudo".to_string(),
+            reuse_temp: false,
+            per_run_temp: false,
+            clone_depth: 1,
+            max_retries: 2,
+            github_token: None,
+            mirror_headers: vec![],
+            include_debug: false,
+            build_dir: None,
+            edit: EditMode::Ask,
+            diffprog: None,
+            source_rewrites: vec![],
+            sync_db_max_age_hours: 24,
+            require_fresh_db: false,
+            timeout_secs: None,
+            keep_failed_builds: false,
+            repo_priority: vec![],
+            news_check: false,
+            origins: HashMap::new(),
+            strict_config: false,
+            unknown_conf_keys: vec![],
+            fm_wait: FmWait::Auto,
+            default_selection: DefaultSelection::All,
+            color_theme: ColorTheme::Default,
+            build_nice: None,
+            aur_groups: BTreeMap::new(),
+            pinned_refs: BTreeMap::new(),
+            build_user: None,
+            sudoflags: vec![],
+            min_free_mb: None,
+            regen_srcinfo: true,
+            assume_installed: vec![],
+            use_keyring: false,
+            output_dir: None,
+            clone_dir: None,
+            post_install_hook: None,
+            pre_build_hook: None,
+            pre_build_hook_abort: true,
+            verbose: false,
+            prefer_source: vec![],
+            makepkg_conf: None,
         }
     }
 }
@@ -34,6 +237,7 @@ impl Config {
     pub fn load() -> Result<Self> {
         // Start with defaults
         let mut cfg = Self::default();
+        let mut before = cfg.settings();
 
         // Load from legacy config file ~/.config/aurwrap/config.toml (if present)
         if let Ok(ed) = std::env::var("AURWRAP_EDITOR") {
@@ -71,6 +275,7 @@ impl Config {
                 cfg.sudo = s;
             }
         }
+        cfg.record_origins(&mut before, "env (AURWRAP_*)");
         // Config file: ~/.config/aurwrap/config.toml
         if let Some(home) = home_dir() {
             let path = home.join(".config/aurwrap/config.toml");
@@ -101,11 +306,175 @@ impl Config {
                     if let Some(t) = value.get("sudo").and_then(|v| v.as_str()) {
                         cfg.sudo = t.to_string();
                     }
+                    if let Some(t) = value.get("reuse_temp").and_then(|v| v.as_str()) {
+                        cfg.reuse_temp = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("per_run_temp").and_then(|v| v.as_str()) {
+                        cfg.per_run_temp = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("clone_depth").and_then(|v| v.as_integer()) {
+                        cfg.clone_depth = t.max(0) as u32;
+                    }
+                    if let Some(t) = value.get("max_retries").and_then(|v| v.as_integer()) {
+                        cfg.max_retries = t.max(0) as u32;
+                    }
+                    if let Some(m) = value
+                        .get("edit")
+                        .and_then(|v| v.as_str())
+                        .and_then(EditMode::parse)
+                    {
+                        cfg.edit = m;
+                    }
+                    if let Some(t) = value.get("diffprog").and_then(|v| v.as_str()) {
+                        cfg.diffprog = Some(t.to_string());
+                    }
+                    if let Some(t) = value.get("source_rewrites").and_then(|v| v.as_table()) {
+                        for (pattern, replacement) in t {
+                            if let Some(replacement) = replacement.as_str() {
+                                cfg.source_rewrites
+                                    .push((pattern.clone(), replacement.to_string()));
+                            }
+                        }
+                    }
+                    if let Some(t) = value
+                        .get("sync_db_max_age_hours")
+                        .and_then(|v| v.as_integer())
+                    {
+                        cfg.sync_db_max_age_hours = t.max(0) as u64;
+                    }
+                    if let Some(t) = value.get("require_fresh_db").and_then(|v| v.as_str()) {
+                        cfg.require_fresh_db = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("timeout").and_then(|v| v.as_integer()) {
+                        cfg.timeout_secs = Some(t.max(1) as u64);
+                    }
+                    if let Some(t) = value.get("keep_failed_builds").and_then(|v| v.as_str()) {
+                        cfg.keep_failed_builds = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("repo_priority").and_then(|v| v.as_array()) {
+                        cfg.repo_priority = t
+                            .iter()
+                            .filter_map(|r| r.as_str().map(str::to_string))
+                            .collect();
+                    }
+                    if let Some(t) = value.get("news_check").and_then(|v| v.as_str()) {
+                        cfg.news_check = t.to_lowercase() == "true";
+                    }
+                    if let Some(m) = value
+                        .get("fm_wait")
+                        .and_then(|v| v.as_str())
+                        .and_then(FmWait::parse)
+                    {
+                        cfg.fm_wait = m;
+                    }
+                    if let Some(t) = value
+                        .get("color_theme")
+                        .and_then(|v| v.as_str())
+                        .and_then(ColorTheme::parse)
+                    {
+                        cfg.color_theme = t;
+                    }
+                    if let Some(s) = value
+                        .get("default_selection")
+                        .and_then(|v| v.as_str())
+                        .and_then(DefaultSelection::parse)
+                    {
+                        cfg.default_selection = s;
+                    }
+                    if let Some(n) = value
+                        .get("build_nice")
+                        .and_then(|v| v.as_integer())
+                        .and_then(|n| u8::try_from(n).ok())
+                    {
+                        cfg.build_nice = Some(n.min(19));
+                    }
+                    // [group] myset = ["pkg1", "pkg2"] (or a space separated string)
+                    if let Some(t) = value.get("group").and_then(|v| v.as_table()) {
+                        for (name, pkgs) in t {
+                            let pkgs: Vec<String> = match pkgs {
+                                toml::Value::Array(a) => a
+                                    .iter()
+                                    .filter_map(|p| p.as_str().map(str::to_string))
+                                    .collect(),
+                                toml::Value::String(s) => {
+                                    s.split_whitespace().map(str::to_string).collect()
+                                }
+                                _ => continue,
+                            };
+                            cfg.aur_groups.insert(name.clone(), pkgs);
+                        }
+                    }
+                    // [pin] pkgbase = "ref"
+                    if let Some(t) = value.get("pin").and_then(|v| v.as_table()) {
+                        for (name, git_ref) in t {
+                            if let Some(git_ref) = git_ref.as_str() {
+                                cfg.pinned_refs.insert(name.clone(), git_ref.to_string());
+                            }
+                        }
+                    }
+                    if let Some(t) = value.get("build_user").and_then(|v| v.as_str()) {
+                        cfg.build_user = Some(t.to_string());
+                    }
+                    if let Some(t) = value.get("sudoflags").and_then(|v| v.as_str()) {
+                        cfg.sudoflags = t.split_whitespace().map(str::to_string).collect();
+                    }
+                    if let Some(t) = value.get("min_free_mb").and_then(|v| v.as_integer()) {
+                        cfg.min_free_mb = Some(t.max(0) as u64);
+                    }
+                    if let Some(t) = value.get("regen_srcinfo").and_then(|v| v.as_str()) {
+                        cfg.regen_srcinfo = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("use_keyring").and_then(|v| v.as_str()) {
+                        cfg.use_keyring = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("output_dir").and_then(|v| v.as_str()) {
+                        cfg.output_dir = expand_home(t);
+                    }
+                    if let Some(t) = value.get("clone_dir").and_then(|v| v.as_str()) {
+                        cfg.clone_dir = expand_home(t);
+                    }
+                    if let Some(t) = value.get("post_install_hook").and_then(|v| v.as_str()) {
+                        cfg.post_install_hook = expand_home(t);
+                    }
+                    if let Some(t) = value.get("pre_build_hook").and_then(|v| v.as_str()) {
+                        cfg.pre_build_hook = expand_home(t);
+                    }
+                    if let Some(t) = value.get("pre_build_hook_abort").and_then(|v| v.as_str()) {
+                        cfg.pre_build_hook_abort = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("verbose").and_then(|v| v.as_str()) {
+                        cfg.verbose = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("prefer_source").and_then(|v| v.as_array()) {
+                        cfg.prefer_source = t
+                            .iter()
+                            .filter_map(|s| s.as_str().map(str::to_lowercase))
+                            .collect();
+                    }
+                    if let Some(t) = value.get("build_dir").and_then(|v| v.as_str()) {
+                        cfg.build_dir = expand_home(t);
+                    }
+                    if let Some(t) = value.get("include_debug").and_then(|v| v.as_str()) {
+                        cfg.include_debug = t.to_lowercase() == "true";
+                    }
+                    if let Some(t) = value.get("github_token").and_then(|v| v.as_str()) {
+                        cfg.github_token = Some(Secret::new(t));
+                    }
+                    if let Some(t) = value.get("mirror_headers").and_then(|v| v.as_table()) {
+                        for (name, val) in t {
+                            if let Some(val) = val.as_str() {
+                                cfg.mirror_headers.push((name.clone(), Secret::new(val)));
+                            }
+                        }
+                    }
                 }
             }
         }
+        cfg.record_origins(&mut before, "~/.config/aurwrap/config.toml");
         //bruh moment
         // Also support simple conf at ~/turbo/conf (key=value lines)
+        validate_root_dir_name(&cfg.root_dir_name)?;
+        validate_prefer_source(&cfg.prefer_source)?;
         if let Some(home) = home_dir() {
             let conf_path = home.join(cfg.root_dir_name.as_str()).join("conf");
             if conf_path.exists() {
@@ -125,13 +494,147 @@ impl Config {
                                 "mirror_base" => cfg.mirror_base = Some(v.to_string()),
                                 "pacman_cmd" => cfg.pacman = v.to_string(),
                                 "sudo_cmd" => cfg.sudo = v.to_string(),
-                                _ => {}
+                                "reuse_temp" => cfg.reuse_temp = v.eq_ignore_ascii_case("true"),
+                                "per_run_temp" => cfg.per_run_temp = v.eq_ignore_ascii_case("true"),
+                                "clone_depth" => {
+                                    if let Ok(d) = v.parse::<u32>() {
+                                        cfg.clone_depth = d;
+                                    }
+                                }
+                                "max_retries" => {
+                                    if let Ok(n) = v.parse::<u32>() {
+                                        cfg.max_retries = n;
+                                    }
+                                }
+                                "build_dir" => cfg.build_dir = expand_home(v),
+                                "diffprog" => cfg.diffprog = Some(v.to_string()),
+                                // prefer_source=github,official
+                                "prefer_source" => {
+                                    cfg.prefer_source = v
+                                        .split(',')
+                                        .map(str::trim)
+                                        .filter(|s| !s.is_empty())
+                                        .map(str::to_lowercase)
+                                        .collect()
+                                }
+                                "verbose" => cfg.verbose = v.eq_ignore_ascii_case("true"),
+                                "pre_build_hook" => cfg.pre_build_hook = expand_home(v),
+                                "pre_build_hook_abort" => {
+                                    cfg.pre_build_hook_abort = v.eq_ignore_ascii_case("true")
+                                }
+                                "post_install_hook" => cfg.post_install_hook = expand_home(v),
+                                "output_dir" => cfg.output_dir = expand_home(v),
+                                "clone_dir" => cfg.clone_dir = expand_home(v),
+                                "use_keyring" => cfg.use_keyring = v.eq_ignore_ascii_case("true"),
+                                "regen_srcinfo" => {
+                                    cfg.regen_srcinfo = v.eq_ignore_ascii_case("true")
+                                }
+                                "min_free_mb" => {
+                                    if let Ok(n) = v.parse() {
+                                        cfg.min_free_mb = Some(n);
+                                    }
+                                }
+                                "sudoflags" => {
+                                    cfg.sudoflags =
+                                        v.split_whitespace().map(str::to_string).collect()
+                                }
+                                "build_user" => cfg.build_user = Some(v.to_string()),
+                                "build_nice" => {
+                                    if let Ok(n) = v.parse::<u8>() {
+                                        cfg.build_nice = Some(n.min(19));
+                                    }
+                                }
+                                "default_selection" => {
+                                    if let Some(s) = DefaultSelection::parse(v) {
+                                        cfg.default_selection = s;
+                                    }
+                                }
+                                "fm_wait" => {
+                                    if let Some(m) = FmWait::parse(v) {
+                                        cfg.fm_wait = m;
+                                    }
+                                }
+                                "color_theme" => {
+                                    if let Some(t) = ColorTheme::parse(v) {
+                                        cfg.color_theme = t;
+                                    }
+                                }
+                                "strict_config" => {
+                                    cfg.strict_config = v.eq_ignore_ascii_case("true")
+                                }
+                                "news_check" => cfg.news_check = v.eq_ignore_ascii_case("true"),
+                                // repo_priority=custom,core,extra
+                                "repo_priority" => {
+                                    cfg.repo_priority = v
+                                        .split(',')
+                                        .map(str::trim)
+                                        .filter(|r| !r.is_empty())
+                                        .map(str::to_string)
+                                        .collect()
+                                }
+                                "keep_failed_builds" => {
+                                    cfg.keep_failed_builds = v.eq_ignore_ascii_case("true")
+                                }
+                                "timeout" => {
+                                    if let Ok(t) = v.parse::<u64>() {
+                                        cfg.timeout_secs = Some(t.max(1));
+                                    }
+                                }
+                                "sync_db_max_age_hours" => {
+                                    if let Ok(h) = v.parse::<u64>() {
+                                        cfg.sync_db_max_age_hours = h;
+                                    }
+                                }
+                                "require_fresh_db" => {
+                                    cfg.require_fresh_db = v.eq_ignore_ascii_case("true")
+                                }
+                                // source_rewrite=<regex> => <replacement> (may be repeated)
+                                "source_rewrite" => {
+                                    if let Some((pattern, replacement)) = v.split_once("=>") {
+                                        cfg.source_rewrites.push((
+                                            pattern.trim().to_string(),
+                                            replacement.trim().to_string(),
+                                        ));
+                                    }
+                                }
+                                "edit" => {
+                                    if let Some(m) = EditMode::parse(v) {
+                                        cfg.edit = m;
+                                    }
+                                }
+                                "include_debug" => {
+                                    cfg.include_debug = v.eq_ignore_ascii_case("true")
+                                }
+                                "github_token" => cfg.github_token = Some(Secret::new(v)),
+                                // mirror_header=Name: value (may be repeated)
+                                "mirror_header" => {
+                                    if let Some((name, val)) = v.split_once(':') {
+                                        cfg.mirror_headers.push((
+                                            name.trim().to_string(),
+                                            Secret::new(val.trim()),
+                                        ));
+                                    }
+                                }
+                                // group.NAME = pkg1 pkg2
+                                _ if k.starts_with("group.") && k.len() > "group.".len() => {
+                                    cfg.aur_groups.insert(
+                                        k["group.".len()..].to_string(),
+                                        v.split_whitespace().map(str::to_string).collect(),
+                                    );
+                                }
+                                // pin.PKGBASE = commit, tag or branch
+                                _ if k.starts_with("pin.") && k.len() > "pin.".len() => {
+                                    cfg.pinned_refs
+                                        .insert(k["pin.".len()..].to_string(), v.to_string());
+                                }
+                                _ => cfg.unknown_conf_keys.push(k.to_string()),
                             }
                         }
                     }
                 }
             }
         }
+        cfg.record_origins(&mut before, "conf");
 
         // Finally, apply env overrides again to supersede conf (as requested)
         if let Ok(ed) = std::env::var("AURWRAP_EDITOR") {
@@ -169,9 +672,177 @@ impl Config {
                 cfg.sudo = s;
             }
         }
+        if let Ok(v) = std::env::var("AURWRAP_VERBOSE") {
+            cfg.verbose = matches!(v.trim(), "1" | "true");
+        }
+        if let Ok(t) = std::env::var("AURWRAP_GITHUB_TOKEN") {
+            if !t.trim().is_empty() {
+                cfg.github_token = Some(Secret::new(t.trim()));
+            }
+        }
+        cfg.record_origins(&mut before, "env (AURWRAP_*)");
+        validate_root_dir_name(&cfg.root_dir_name)?;
+        validate_prefer_source(&cfg.prefer_source)?;
+        if cfg.strict_config {
+            cfg.check_unknown_keys()?;
+        }
         Ok(cfg)
     }
 
+    /// Strict mode: fail on conf keys that were ignored, suggesting the closest known one.
+    pub fn check_unknown_keys(&self) -> Result<()> {
+        if self.unknown_conf_keys.is_empty() {
+            return Ok(());
+        }
+        let described: Vec<String> = self
+            .unknown_conf_keys
+            .iter()
+            .map(|k| match closest_conf_key(k) {
+                Some(s) => format!("{} (did you mean {}?)", k, s),
+                None => k.clone(),
+            })
+            .collect();
+        Err(anyhow!(
+            "Unknown key(s) in {}: {}",
+            self.root_dir().join("conf").display(),
+            described.join(", ")
+        ))
+    }
+
+    /// Every setting as (name, display value), in declaration order. Secrets are
+    /// redacted; only the names of mirror headers are shown.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        fn opt<T: fmt::Display>(v: &Option<T>) -> String {
+            v.as_ref()
+                .map_or_else(|| String::from("(unset)"), T::to_string)
+        }
+        vec![
+            ("editor", self.editor.clone()),
+            ("file_manager", self.file_manager.clone()),
+            ("root_dir_name", self.root_dir_name.clone()),
+            ("aur_mirror", self.aur_mirror.clone()),
+            ("mirror_base", opt(&self.mirror_base)),
+            ("noconfirm", self.noconfirm.to_string()),
+            ("pacman", self.pacman.clone()),
+            ("sudo", self.sudo.clone()),
+            ("sudoflags", self.sudoflags.join(" ")),
+            ("reuse_temp", self.reuse_temp.to_string()),
+            ("per_run_temp", self.per_run_temp.to_string()),
+            ("clone_depth", self.clone_depth.to_string()),
+            ("max_retries", self.max_retries.to_string()),
+            ("github_token", format!("{:?}", self.github_token)),
+            (
+                "mirror_headers",
+                self.mirror_headers
+                    .iter()
+                    .map(|(name, _)| format!("{}: <redacted>", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            ("include_debug", self.include_debug.to_string()),
+            (
+                "build_dir",
+                opt(&self.build_dir.as_ref().map(|p| p.display())),
+            ),
+            ("edit", format!("{:?}", self.edit).to_lowercase()),
+            ("diffprog", opt(&self.diffprog)),
+            (
+                "source_rewrites",
+                self.source_rewrites
+                    .iter()
+                    .map(|(from, to)| format!("{} => {}", from, to))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            (
+                "sync_db_max_age_hours",
+                self.sync_db_max_age_hours.to_string(),
+            ),
+            ("require_fresh_db", self.require_fresh_db.to_string()),
+            ("timeout_secs", opt(&self.timeout_secs)),
+            ("keep_failed_builds", self.keep_failed_builds.to_string()),
+            ("repo_priority", self.repo_priority.join(", ")),
+            ("news_check", self.news_check.to_string()),
+            ("strict_config", self.strict_config.to_string()),
+            ("build_nice", opt(&self.build_nice)),
+            ("build_user", opt(&self.build_user)),
+            (
+                "min_free_mb",
+                self.min_free_mb.map_or_else(
+                    || format!("{} (warn only)", DEFAULT_MIN_FREE_MB),
+                    |n| n.to_string(),
+                ),
+            ),
+            ("regen_srcinfo", self.regen_srcinfo.to_string()),
+            ("use_keyring", self.use_keyring.to_string()),
+            ("prefer_source", self.prefer_source.join(", ")),
+            ("verbose", self.verbose.to_string()),
+            (
+                "pre_build_hook",
+                opt(&self.pre_build_hook.as_ref().map(|p| p.display())),
+            ),
+            (
+                "pre_build_hook_abort",
+                self.pre_build_hook_abort.to_string(),
+            ),
+            (
+                "post_install_hook",
+                opt(&self.post_install_hook.as_ref().map(|p| p.display())),
+            ),
+            (
+                "output_dir",
+                opt(&self.output_dir.as_ref().map(|p| p.display())),
+            ),
+            (
+                "clone_dir",
+                opt(&self.clone_dir.as_ref().map(|p| p.display())),
+            ),
+            ("assume_installed", self.assume_installed.join(", ")),
+            (
+                "makepkg_conf",
+                opt(&self.makepkg_conf.as_ref().map(|p| p.display())),
+            ),
+            (
+                "aur_groups",
+                self.aur_groups
+                    .iter()
+                    .map(|(name, pkgs)| format!("{}: {}", name, pkgs.join(" ")))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            (
+                "pinned_refs",
+                self.pinned_refs
+                    .iter()
+                    .map(|(name, git_ref)| format!("{}: {}", name, git_ref))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            ("fm_wait", format!("{:?}", self.fm_wait).to_lowercase()),
+            (
+                "default_selection",
+                format!("{:?}", self.default_selection).to_lowercase(),
+            ),
+            ("color_theme", self.color_theme.to_string()),
+        ]
+    }
+
+    /// Attribute every setting that changed since `before` to `origin`, then
+    /// advance `before` to the current values.
+    pub fn record_origins(
+        &mut self,
+        before: &mut Vec<(&'static str, String)>,
+        origin: &'static str,
+    ) {
+        let now = self.settings();
+        for ((name, old), (_, new)) in before.iter().zip(&now) {
+            if old != new {
+                self.origins.insert(name, origin);
+            }
+        }
+        *before = now;
+    }
+
     pub fn root_dir(&self) -> PathBuf {
         let home = home_dir().unwrap_or_else(|| PathBuf::from("/"));
         home.join(&self.root_dir_name)
@@ -181,7 +852,191 @@ impl Config {
         self.root_dir().join("cache")
     }
 
+    /// Built packages kept after a successful install, for offline reinstalls.
+    pub fn pkg_cache_dir(&self) -> PathBuf {
+        self.cache_dir().join("pkg")
+    }
+
+    /// Copies of the PKGBUILDs last built, used to diff against fresh clones.
+    pub fn pkgbuild_cache_dir(&self) -> PathBuf {
+        self.cache_dir().join("pkgbuild")
+    }
+
+    /// The update list `-P` writes, under `output_dir` if set, else the root dir.
+    pub fn needupdate_path(&self) -> PathBuf {
+        self.output_dir
+            .clone()
+            .unwrap_or_else(|| self.root_dir())
+            .join("needupdate.json")
+    }
+
+    /// Where this run clones and builds: the shared temp tree, or with
+    /// `per_run_temp` a subdir of it unique to this process, so that
+    /// concurrent runs never wipe each other's trees.
     pub fn temp_dir(&self) -> PathBuf {
-        self.cache_dir().join("temp")
+        let root = self.temp_root();
+        if !self.per_run_temp {
+            return root;
+        }
+        root.join(RUN_ID.get_or_init(|| {
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!("{}-{}", std::process::id(), secs)
+        }))
+    }
+
+    /// The shared temp tree that holds every run's build dirs.
+    pub fn temp_root(&self) -> PathBuf {
+        match &self.build_dir {
+            // Namespaced so that wiping the temp tree never touches the rest of e.g. /tmp
+            Some(dir) => dir.join(&self.root_dir_name),
+            None => self.cache_dir().join("temp"),
+        }
+    }
+}
+
+fn expand_home(value: &str) -> Option<PathBuf> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    match value.strip_prefix("~/") {
+        Some(rest) => home_dir().map(|h| h.join(rest)),
+        None => Some(PathBuf::from(value)),
+    }
+}
+
+/// `root_dir_name` is joined onto $HOME (and build_dir), so it must be a single
+/// plain directory name; anything like `../../etc` would escape it.
+fn validate_root_dir_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(c)), None) if c == name && !name.contains('/') => Ok(()),
+        _ => Err(anyhow!(
+            "Invalid root_dir_name {:?}: it must be a single directory name under $HOME (no '/', '.' or '..')",
+            name
+        )),
+    }
+}
+
+fn validate_prefer_source(sources: &[String]) -> Result<()> {
+    match sources
+        .iter()
+        .find(|s| !matches!(s.as_str(), "official" | "aur" | "github" | "github-aur"))
+    {
+        Some(bad) => Err(anyhow!(
+            "Invalid prefer_source entry {:?}: expected official or github",
+            bad
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Every key the conf parser understands; keep in sync with the `match k` in `Config::load`.
+const CONF_KEYS: &[&str] = &[
+    "editor",
+    "file_manager",
+    "mirror",
+    "mirror_base",
+    "pacman_cmd",
+    "sudo_cmd",
+    "sudoflags",
+    "mirror_header",
+    "github_token",
+    "use_keyring",
+    "prefer_source",
+    "verbose",
+    "reuse_temp",
+    "per_run_temp",
+    "clone_depth",
+    "max_retries",
+    "include_debug",
+    "build_dir",
+    "output_dir",
+    "clone_dir",
+    "post_install_hook",
+    "pre_build_hook",
+    "pre_build_hook_abort",
+    "build_user",
+    "min_free_mb",
+    "regen_srcinfo",
+    "edit",
+    "diffprog",
+    "source_rewrite",
+    "sync_db_max_age_hours",
+    "require_fresh_db",
+    "timeout",
+    "keep_failed_builds",
+    "repo_priority",
+    "news_check",
+    "strict_config",
+    "fm_wait",
+    "color_theme",
+    "default_selection",
+    "build_nice",
+];
+
+/// Nearest known conf key by edit distance, if it's plausibly a typo.
+fn closest_conf_key(key: &str) -> Option<&'static str> {
+    CONF_KEYS
+        .iter()
+        .map(|k| (edit_distance(key, k), *k))
+        .filter(|(d, k)| *d <= (k.len() / 3).max(2))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn needupdate_path_follows_root_dir_name_and_output_dir() {
+        let cfg = Config {
+            root_dir_name: String::from("turbo-test"),
+            ..Config::default()
+        };
+        assert_eq!(
+            cfg.needupdate_path(),
+            cfg.root_dir().join("needupdate.json")
+        );
+        assert!(cfg.root_dir().ends_with("turbo-test"));
+
+        let cfg = Config {
+            output_dir: Some(PathBuf::from("/srv/turbo")),
+            ..cfg
+        };
+        assert_eq!(
+            cfg.needupdate_path(),
+            PathBuf::from("/srv/turbo/needupdate.json")
+        );
+    }
+
+    #[test]
+    fn root_dir_name_must_be_one_component() {
+        for bad in ["../../etc", "a/b", "", "..", ".", "/abs"] {
+            assert!(
+                validate_root_dir_name(bad).is_err(),
+                "{:?} was accepted",
+                bad
+            );
+        }
+        assert!(validate_root_dir_name("turbo").is_ok());
     }
 }
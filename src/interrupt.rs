@@ -0,0 +1,92 @@
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Output;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::style::*;
+
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+/// Process groups of the shielded children currently running.
+static CHILD_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Install the Ctrl-C handler. The first Ctrl-C only raises a flag so the
+/// pipeline can stop between packages; shielded children (see `shielded`) never
+/// see it and finish their step. A second one passes SIGINT on to them and exits.
+pub fn install_handler() {
+    tokio::spawn(async {
+        loop {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            if INTERRUPTS.fetch_add(1, Ordering::SeqCst) == 0 {
+                eprintln!(
+                    "\n{} {}",
+                    warn_icon(),
+                    warning().apply_to(
+                        "Interrupted: stopping after the current step (Ctrl-C again to abort now)"
+                    )
+                );
+            } else {
+                eprintln!("\n{} {}", error_icon(), error().apply_to("Aborted."));
+                forward_sigint();
+                std::process::exit(130);
+            }
+        }
+    });
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 0
+}
+
+fn forward_sigint() {
+    let groups = CHILD_GROUPS.lock().map(|g| g.clone()).unwrap_or_default();
+    for pgid in groups {
+        if let Ok(pgid) = libc::pid_t::try_from(pgid) {
+            // SAFETY: kill(2) has no memory-safety preconditions
+            unsafe {
+                libc::kill(-pgid, libc::SIGINT);
+            }
+        }
+    }
+}
+
+/// `expr` in a process group of its own. The terminal sends Ctrl-C to the
+/// foreground group only, so makepkg, git and whatever they spawn (curl, gpg)
+/// keep running until the step is done or the user presses Ctrl-C again.
+/// Such children cannot read from the terminal, so they must not prompt: git
+/// runs with GIT_TERMINAL_PROMPT=0 and `makepkg -s` relies on a sudo timestamp
+/// refreshed beforehand (not possible for a build_user's own sudo).
+pub fn shielded(expr: &duct::Expression) -> duct::Expression {
+    expr.before_spawn(|command| {
+        command.process_group(0);
+        Ok(())
+    })
+}
+
+/// Keep `pids` (each the leader of its own group) as targets of a forced abort
+/// until the returned guard is dropped.
+pub fn track_children(pids: Vec<u32>) -> TrackedChildren {
+    if let Ok(mut groups) = CHILD_GROUPS.lock() {
+        groups.extend(&pids);
+    }
+    TrackedChildren(pids)
+}
+
+pub struct TrackedChildren(Vec<u32>);
+
+impl Drop for TrackedChildren {
+    fn drop(&mut self) {
+        if let Ok(mut groups) = CHILD_GROUPS.lock() {
+            groups.retain(|pid| !self.0.contains(pid));
+        }
+    }
+}
+
+/// Run `expr` shielded from the first Ctrl-C and wait for it, like `run()`.
+pub fn run_shielded(expr: &duct::Expression) -> io::Result<Output> {
+    let handle = shielded(expr).start()?;
+    let _tracked = track_children(handle.pids());
+    handle.into_output()
+}
@@ -1,12 +1,10 @@
 use anyhow::{anyhow, Result};
 use clap::{Arg, ArgAction, Command};
-use dialoguer::Confirm;
-use home::home_dir;
-use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::time::sleep;
 
 use crate::style::*;
@@ -14,19 +12,30 @@ use crate::style::*;
 mod aur;
 mod build;
 mod config;
+mod interrupt;
+mod news;
 mod pac;
 mod self_update;
+mod soname;
+mod state;
 mod style;
 mod ui;
 
 use crate::build::{
-    clean_cache, clean_dir_contents, clone_aur_pkgs, collect_zsts, ensure_persistent_dirs,
-    makepkg_build, open_file_manager, regen_srcinfo, AurCloneSpec, AurSource,
+    cache_artifacts, check_free_space, clean_cache, clean_dir_contents, clean_dir_except,
+    clone_aur_pkgs, clone_command_line, collect_zsts, diff_against_last_build, ensure_build_tools,
+    ensure_free_space, ensure_persistent_dirs, find_cached_artifacts, makepkg_build,
+    makepkg_command_line, open_editor, open_file_manager, open_in_browser, prune_stale_run_dirs,
+    regen_srcinfo_if_edited, regen_srcinfos_if_edited, run_post_install_hook, run_pre_build_hook,
+    save_built_pkgbuild, shell_quote, AurCloneSpec, AurSource, RunTempDir, TempDirGuard,
+};
+use crate::build::{import_validpgpkeys, recv_keys, rewrite_sources, verify_sources};
+use crate::config::{ColorTheme, Config, EditMode, DEFAULT_MIN_FREE_MB};
+use crate::self_update::{checked_recently, ensure_latest_release_installed};
+use crate::ui::{
+    confirm, pick_repo, pick_updates_numeric, pick_version, sort_updates, summarize_updates,
+    Pickable, SortKey,
 };
-use crate::build::{import_validpgpkeys, verify_sources};
-use crate::config::Config;
-use crate::self_update::ensure_latest_release_installed;
-use crate::ui::{pick_updates_numeric, Pickable};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -35,44 +44,325 @@ async fn main() -> Result<()> {
         .arg(Arg::new("sync").short('S').action(ArgAction::SetTrue).help("Sync / install mode (pacman -S ...)"))
         .arg(Arg::new("refresh").short('y').action(ArgAction::Count).help("Refresh databases (can be doubled, like -yy)"))
         .arg(Arg::new("sysupgrade").short('u').action(ArgAction::SetTrue).help("System upgrade"))
+        .arg(Arg::new("downloadonly").short('w').action(ArgAction::SetTrue).help("With -S: download only; AUR packages are cloned and their sources fetched, but not built"))
+        .arg(Arg::new("search").short('s').action(ArgAction::SetTrue).help("With -S: search repos and the AUR (pacman -Ss)"))
+        .arg(Arg::new("by").long("by").value_name("FIELD").value_parser(aur::SEARCH_FIELDS.to_vec()).default_value("name-desc").help("AUR field to search with -Ss"))
         .arg(Arg::new("print_updates").short('P').action(ArgAction::SetTrue).help("Print list of packages that need to be upgraded"))
         .arg(Arg::new("clone_package_base").short('G').action(ArgAction::SetTrue).help("Clone package base"))
         .arg(Arg::new("noconfirm").long("noconfirm").action(ArgAction::SetTrue).help("No confirm mode (pacman -U --noconfirm)"))
+        .arg(Arg::new("noconfirm_install_only").long("noconfirm-install-only").action(ArgAction::SetTrue).help("Keep review/edit prompts interactive but pass --noconfirm to the final pacman -U"))
+        .arg(Arg::new("unified").long("unified").action(ArgAction::SetTrue).help("With -Syu: pick repo and AUR updates from one menu instead of running pacman -Syu first; repo picks are installed with pacman -S"))
+        .arg(Arg::new("upgrades_only").long("upgrades-only").action(ArgAction::SetTrue).help("With -Syu: build and install every outdated AUR package without showing the menu"))
+        .arg(Arg::new("ignoregroup").long("ignoregroup").value_name("GROUP").action(ArgAction::Append).help("With -Syu: skip a package group (comma separated, repeatable); repo groups go to pacman, AUR ones come from group.NAME in the conf"))
+        .arg(Arg::new("export_installed_aur").long("export-installed-aur").value_name("PATH").help("Write the names of explicitly installed foreign packages (pacman -Qqem), one per line, to restore them later with -S"))
+        .arg(Arg::new("export_foreign").long("export-foreign").value_name("PATH").help("Write AUR metadata for all installed foreign packages to a JSON file"))
+        .arg(Arg::new("downgrade").long("downgrade").value_name("PKG").help("Roll a package back to an older version from turbo's artifact cache, optionally holding it there"))
+        .arg(Arg::new("unhold").long("unhold").value_name("PKG").help("Let -Syu upgrade a package held by --downgrade again"))
+        .arg(Arg::new("reinstall").long("reinstall").value_name("PKG").help("Reinstall a package from turbo's artifact cache without touching the network"))
+        .arg(Arg::new("edit").long("edit").action(ArgAction::SetTrue).conflicts_with("noedit").help("Open the file manager on the cloned PKGBUILDs without asking"))
+        .arg(Arg::new("noedit").long("noedit").action(ArgAction::SetTrue).help("Skip the edit prompt and build right away"))
+        .arg(Arg::new("recv_key").long("recv-key").visible_alias("pgp-key").value_name("KEYID").num_args(1..).help("Import PGP keys from the keyservers used for validpgpkeys, then exit"))
+        .arg(Arg::new("strict_config").long("strict-config").action(ArgAction::SetTrue).help("Fail on unknown keys in the conf file instead of ignoring them"))
+        .arg(Arg::new("print_config").long("print-config").action(ArgAction::SetTrue).help("Print the effective configuration and where each value came from"))
+        .arg(Arg::new("refresh_keys").long("refresh-keys").action(ArgAction::SetTrue).help("Re-populate the pacman keyring and refresh its keys from the keyservers"))
+        .arg(Arg::new("news").long("news").action(ArgAction::SetTrue).help("Show Arch Linux news published since the last time it was read"))
+        .arg(Arg::new("installed_deps_check").long("installed-deps-check").action(ArgAction::SetTrue).help("Check that the AUR dependencies of every foreign package are still installed"))
+        .arg(Arg::new("update_self").long("update-self").action(ArgAction::SetTrue).help("Update turbo itself to the latest release, then exit"))
+        .arg(Arg::new("force").long("force").action(ArgAction::SetTrue).help("With --update-self: check for a release even if one was checked in the last 24h"))
+        .arg(Arg::new("sudoflags").long("sudoflags").value_name("FLAGS").allow_hyphen_values(true).help("Extra arguments for sudo, split on whitespace (e.g. \"-A\")"))
+        .arg(Arg::new("confirm_timeout").long("confirm-timeout").value_name("SECS").value_parser(clap::value_parser!(u64)).help("Take the default answer of prompts nobody answers within SECS seconds"))
+        .arg(Arg::new("only_repo").long("only-repo").action(ArgAction::SetTrue).conflicts_with_all(["only_aur", "deps_only"]).help("With -S: install only the repo targets and leave the AUR ones for later"))
+        .arg(Arg::new("only_aur").long("only-aur").action(ArgAction::SetTrue).help("With -S: build only the AUR targets and leave the repo ones alone"))
+        .arg(Arg::new("no_regen_srcinfo").long("no-regen-srcinfo").action(ArgAction::SetTrue).help("Trust the committed .SRCINFO instead of regenerating it after the edit step"))
+        .arg(Arg::new("verify_only").long("verify-only").value_name("PKG").num_args(1..).help("Clone AUR packages and check their sources (checksums, signatures) without building, then exit"))
+        .arg(Arg::new("assume_installed").long("assume-installed").value_name("PKG=VER").action(ArgAction::Append).help("Passed on to makepkg: treat PKG=VER as installed when checking build deps (repeatable)"))
+        .arg(Arg::new("print_commands").long("print").action(ArgAction::SetTrue).help("With -S, print the clone, build and install commands as a shell script instead of running them"))
+        .arg(Arg::new("clone_dir").long("clone-dir").value_name("DIR").value_parser(clap::value_parser!(PathBuf)).help("Keep AUR git clones in DIR, pulled each run; builds use a copy in the temp dir"))
+        .arg(Arg::new("output_dir").long("output-dir").value_name("DIR").value_parser(clap::value_parser!(PathBuf)).help("With -P, write needupdate.json to DIR instead of the turbo root dir"))
+        .arg(Arg::new("verbose_http").long("verbose-http").action(ArgAction::SetTrue).help("Log every HTTP request with its status and how long it took"))
+        .arg(Arg::new("makepkg_conf").long("makepkg-conf").value_name("FILE").value_parser(clap::value_parser!(PathBuf)).help("Build with this makepkg.conf instead of the system one (makepkg --config)"))
+        .arg(Arg::new("stats").long("stats").action(ArgAction::SetTrue).help("Show artifact cache size and build history metrics"))
+        .arg(Arg::new("rdeps").long("rdeps").value_name("PKG").help("Show what depends on a package: AUR packages (depends/makedepends) and installed packages"))
+        .arg(Arg::new("open").long("open").value_name("PKG").num_args(1..).help("Open the AUR page of the given packages in the browser (xdg-open)"))
+        .arg(Arg::new("upstream").long("upstream").action(ArgAction::SetTrue).requires("open").help("With --open, also open each package's upstream URL"))
+        .arg(Arg::new("explicit").long("explicit").action(ArgAction::SetTrue).help("List explicitly installed packages, native (repo, like -Qen) and foreign (AUR, like -Qem) side by side"))
+        .arg(Arg::new("orphans").long("orphans").action(ArgAction::SetTrue).help("List orphaned packages (pacman -Qtdq) and offer to remove them"))
+        .arg(Arg::new("sort").long("sort").value_name("KEY").value_parser(["name", "version-delta", "repo"]).default_value("name").help("Order of update listings (-P and the -Syu menu)"))
+        .arg(Arg::new("require_fresh_db").long("require-fresh-db").action(ArgAction::SetTrue).help("Refuse to build AUR packages when the sync databases are stale"))
+        .arg(Arg::new("keep_going_timeout").long("keep-going-timeout").value_name("MINUTES").value_parser(clap::value_parser!(u64)).help("With -Syu: once this much time has passed, start no new builds and install what is done; checked between packages, so a build already running is not cut short"))
+        .arg(Arg::new("batch_install").long("batch-install").value_name("N").value_parser(clap::value_parser!(u64).range(1..)).help("With -Syu: install built packages in groups of N as they finish instead of all at the end"))
+        .arg(Arg::new("nice").long("nice").visible_alias("low-priority").value_name("N").num_args(0..=1).default_missing_value("10").value_parser(clap::value_parser!(u8).range(0..=19)).help("Build with nice -n N (default 10) and ionice -c 3"))
+        .arg(Arg::new("color_theme").long("color-theme").value_name("THEME").value_parser(["default", "light", "monochrome", "high-contrast"]).help("Output colors: default, light (for light backgrounds), monochrome or high-contrast"))
+        .arg(Arg::new("timeout").long("timeout").value_name("SECS").value_parser(clap::value_parser!(u64)).help("Timeout for network requests and git clones"))
+        .arg(Arg::new("local").long("local").value_name("DIR").help("Build and install a local PKGBUILD directory, skipping the AUR entirely"))
+        .arg(Arg::new("print_build_order").long("print-build-order").action(ArgAction::SetTrue).help("Resolve the given AUR packages and print the build order and dependency tree"))
+        .arg(Arg::new("print_deps").long("print-deps").action(ArgAction::SetTrue).help("List the full dependency closure of the given AUR packages: what would be built and what pacman would pull in"))
+        .arg(Arg::new("no_refresh").long("no-refresh").action(ArgAction::SetTrue).help("With -P, compare against the current sync DBs without a sudo -Sy refresh"))
+        .arg(Arg::new("choose_repo").long("choose-repo").action(ArgAction::SetTrue).help("Ask which repo to use for -S targets found in several sync repos"))
+        .arg(Arg::new("ndjson").long("ndjson").action(ArgAction::SetTrue).conflicts_with("sort").help("With -P, stream one JSON object per update to stdout instead of the tables and needupdate.json (implies --no-refresh)"))
+        .arg(Arg::new("deps_only").long("deps-only").action(ArgAction::SetTrue).help("With -S: install only the dependencies of the given AUR packages (as --asdeps), not the packages themselves"))
+        .arg(Arg::new("reuse_temp").long("reuse-temp").action(ArgAction::SetTrue).help("Keep the temp clone/build tree between runs instead of wiping it"))
+        .arg(Arg::new("max_retries").long("max-retries").value_name("N").value_parser(clap::value_parser!(u32)).help("Retry fetching sources up to N times after a download error (default 2)"))
+        .arg(Arg::new("depth").long("depth").value_name("N").value_parser(clap::value_parser!(u32)).help("git clone depth for AUR repos (0 = full clone, needed to check out older commits)"))
         .arg(Arg::new("args").num_args(0..).trailing_var_arg(true).allow_hyphen_values(true).help("Additional pacman-like args or package names"))
         .get_matches();
 
-    let cfg = Config::load()?;
+    interrupt::install_handler();
+    let mut cfg = Config::load()?;
+    if matches.get_flag("strict_config") {
+        cfg.check_unknown_keys()?;
+    }
+    let mut before_cli = cfg.settings();
+    if matches.get_flag("noconfirm") {
+        cfg.noconfirm = true;
+    }
+    if matches.get_flag("edit") {
+        cfg.edit = EditMode::Always;
+    } else if matches.get_flag("noedit") {
+        cfg.edit = EditMode::Never;
+    }
+    if matches.get_flag("require_fresh_db") {
+        cfg.require_fresh_db = true;
+    }
+    if let Some(conf) = matches.get_one::<PathBuf>("makepkg_conf") {
+        // makepkg runs after a `cd` into each package, so the path must be absolute
+        let conf = fs::canonicalize(conf)
+            .ok()
+            .filter(|p| p.is_file())
+            .ok_or_else(|| anyhow!("--makepkg-conf {}: no such file", conf.display()))?;
+        cfg.makepkg_conf = Some(conf);
+    }
+    if let Some(deps) = matches.get_many::<String>("assume_installed") {
+        cfg.assume_installed = deps.cloned().collect();
+    }
+    if matches.get_flag("no_regen_srcinfo") {
+        cfg.regen_srcinfo = false;
+    }
+    if matches.get_flag("reuse_temp") {
+        cfg.reuse_temp = true;
+    }
+    if let Some(dir) = matches.get_one::<PathBuf>("output_dir") {
+        cfg.output_dir = Some(dir.clone());
+    }
+    if let Some(dir) = matches.get_one::<PathBuf>("clone_dir") {
+        cfg.clone_dir = Some(dir.clone());
+    }
+    if let Some(t) = matches.get_one::<u64>("timeout") {
+        cfg.timeout_secs = Some(*t);
+    }
+    if let Some(t) = matches
+        .get_one::<String>("color_theme")
+        .and_then(|t| ColorTheme::parse(t))
+    {
+        cfg.color_theme = t;
+    }
+    if let Some(depth) = matches.get_one::<u32>("depth") {
+        cfg.clone_depth = *depth;
+    }
+    if let Some(n) = matches.get_one::<u32>("max_retries") {
+        cfg.max_retries = *n;
+    }
+    if let Some(n) = matches.get_one::<u8>("nice") {
+        cfg.build_nice = Some(*n);
+    }
+    if let Some(flags) = matches.get_one::<String>("sudoflags") {
+        cfg.sudoflags = flags.split_whitespace().map(str::to_string).collect();
+    }
+    cfg.record_origins(&mut before_cli, "command line");
+    pac::set_sudo_tool(cfg.sudo.clone());
+    pac::set_sudo_flags(cfg.sudoflags.clone());
+    pac::set_verbose(cfg.verbose);
+    style::set_theme(cfg.color_theme);
+    aur::set_verbose_http(matches.get_flag("verbose_http"));
+    if let Some(secs) = matches.get_one::<u64>("confirm_timeout") {
+        ui::set_confirm_timeout(*secs);
+    }
+    if matches.get_flag("print_config") {
+        print_config(&cfg);
+        return Ok(());
+    }
     ensure_persistent_dirs(&cfg)?;
+    let _run_temp = RunTempDir(cfg.per_run_temp.then(|| cfg.temp_dir()));
+    prune_stale_run_dirs(&cfg);
 
     let sync = matches.get_flag("sync");
     let ycount = matches.get_count("refresh");
     let sysupgrade = matches.get_flag("sysupgrade");
     let print_updates = matches.get_flag("print_updates");
-    let just_clone = matches.get_flag("clone_package_base");
-    let args: Vec<String> = matches
+    let mut args: Vec<String> = matches
         .get_many::<String>("args")
         .map(|v| v.map(|s| s.to_string()).collect())
-        .unwrap_or_else(Vec::new);
+        .unwrap_or_default();
+    let before = args.len();
+    args.retain(|a| !a.trim().is_empty());
+    if args.len() < before {
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!(
+                "Ignoring {} empty argument(s)",
+                before - args.len()
+            ))
+        );
+    }
+
+    // Almost everything below goes through sudo; check for it once up front
+    let unprivileged = matches.get_flag("news")
+        || matches.get_flag("stats")
+        || matches.get_flag("explicit")
+        || matches.get_flag("installed_deps_check")
+        || matches.get_flag("orphans") // checks itself before removing
+        || matches.get_one::<String>("export_installed_aur").is_some()
+        || matches.get_one::<String>("export_foreign").is_some()
+        || matches.get_many::<String>("verify_only").is_some()
+        || matches.get_one::<String>("unhold").is_some()
+        || matches.get_flag("print_build_order")
+        || matches.get_flag("print_deps")
+        || matches.get_many::<String>("open").is_some()
+        || matches.get_one::<String>("rdeps").is_some()
+        || matches.get_many::<String>("recv_key").is_some()
+        || (sync && matches.get_flag("search"))
+        || (!sync && pac::is_read_only_query(&args))
+        || ((print_updates || args.iter().any(|a| a == "-P"))
+            && (matches.get_flag("no_refresh") || matches.get_flag("ndjson")));
+    if !unprivileged {
+        pac::ensure_privilege_tool()?;
+    }
+
+    if matches.get_flag("update_self") {
+        if !matches.get_flag("force") {
+            if let Some(hours) = checked_recently(&cfg) {
+                println!(
+                    "{} {}",
+                    info_icon(),
+                    dim().apply_to(format!(
+                        "Checked for a Turbo release {}h ago; use --force to check again.",
+                        hours
+                    ))
+                );
+                return Ok(());
+            }
+        }
+        return ensure_latest_release_installed(&cfg);
+    }
 
     // Handle -P: print list of packages that need to be upgraded
     // Check both the flag and args in case it wasn't parsed as a flag
     if print_updates || args.iter().any(|a| a == "-P") {
         let forcerefresh = ycount > 1;
+        let refresh = !matches.get_flag("no_refresh");
 
-        return handle_print_updates(&cfg, forcerefresh).await;
+        if matches.get_flag("ndjson") {
+            return handle_print_updates_ndjson(&cfg).await;
+        }
+        return handle_print_updates(&cfg, refresh, forcerefresh, sort_key(&matches)).await;
+    }
+
+    if let Some(dir) = matches.get_one::<String>("local") {
+        return handle_local(&cfg, Path::new(dir), &matches);
+    }
+    if matches.get_flag("print_build_order") {
+        return handle_print_build_order(&cfg, &args);
+    }
+    if matches.get_flag("print_deps") {
+        return handle_print_deps(&cfg, &args).await;
+    }
+    if let Some(name) = matches.get_one::<String>("rdeps") {
+        return handle_rdeps(&cfg, name);
+    }
+    if let Some(pkgs) = matches.get_many::<String>("open") {
+        let pkgs: Vec<String> = pkgs.cloned().collect();
+        return handle_open(&cfg, &pkgs, matches.get_flag("upstream"));
+    }
+    if let Some(keys) = matches.get_many::<String>("recv_key") {
+        let keys: Vec<&str> = keys.map(String::as_str).collect();
+        return handle_recv_keys(&cfg, &keys);
+    }
+    if matches.get_flag("refresh_keys") {
+        return handle_refresh_keys();
+    }
+    if matches.get_flag("news") {
+        let client = aur::http_client(&cfg)?;
+        news::show_unread_news(&cfg, &client, false)?;
+        return Ok(());
+    }
+    if matches.get_flag("installed_deps_check") {
+        return handle_installed_deps_check(&cfg).await;
+    }
+    if matches.get_flag("stats") {
+        return handle_stats(&cfg);
+    }
+    if matches.get_flag("explicit") {
+        return handle_explicit().await;
+    }
+
+    if matches.get_flag("orphans") {
+        return handle_orphans(&cfg).await;
+    }
+    if let Some(name) = matches.get_one::<String>("downgrade") {
+        return handle_downgrade(&cfg, name).await;
+    }
+
+    if let Some(name) = matches.get_one::<String>("unhold") {
+        if state::set_held(&cfg, name, None)? {
+            println!(
+                "{} {} {}",
+                success_icon(),
+                highlight().apply_to("No longer held:"),
+                package_name().apply_to(name)
+            );
+        } else {
+            println!(
+                "{} {} {}",
+                info_icon(),
+                package_name().apply_to(name),
+                dim().apply_to("was not held.")
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(name) = matches.get_one::<String>("reinstall") {
+        return handle_reinstall(&cfg, name).await;
+    }
+    if let Some(path) = matches.get_one::<String>("export_installed_aur") {
+        return handle_export_installed_aur(Path::new(path)).await;
+    }
+    if let Some(path) = matches.get_one::<String>("export_foreign") {
+        return handle_export_foreign(&cfg, path).await;
     }
-    if just_clone || args.iter().any(|a| a == "-G") {}
 
-    // Special handling for -Scc: run pacman cache clean, then wipe our cache contents (keep dir)
+    // Special handling for -Scc: run pacman cache clean, then wipe our temp tree (built packages stay)
     if args.iter().any(|a| a == "-Scc") {
         pac::sudo_pacman_scc()?;
         clean_cache(&cfg)?;
         return Ok(());
     }
 
+    if sync && matches.get_flag("search") {
+        let by = matches
+            .get_one::<String>("by")
+            .map(String::as_str)
+            .unwrap_or("name-desc");
+        return handle_search(&cfg, &args, by).await;
+    }
+
     if sync && (sysupgrade || ycount > 0) && args.is_empty() {
         // Treat as -Syu or -Syyu: show update menu for AUR packages (Trizen-like).
-        return handle_sysupgrade(&cfg, ycount as u8, &matches).await;
+        return handle_sysupgrade(&cfg, ycount, &matches).await;
+    }
+
+    if let Some(pkgs) = matches.get_many::<String>("verify_only") {
+        let pkgs: Vec<String> = pkgs.cloned().collect();
+        return handle_verify_only(&cfg, &pkgs);
+    }
+
+    if sync && matches.get_flag("print_commands") {
+        return handle_print_commands(&cfg, &args);
+    }
+
+    if sync && matches.get_flag("downloadonly") {
+        return handle_download_only(&cfg, &args, &matches);
     }
 
     if sync {
@@ -98,6 +388,13 @@ struct UpdateList {
     pacman: Vec<PackageUpdate>,
 }
 
+#[derive(Debug, Serialize)]
+struct ForeignExport {
+    #[serde(flatten)]
+    info: aur::AurInfo,
+    installed_version: String,
+}
+
 #[derive(Clone, Debug)]
 struct AurRequest {
     name: String,
@@ -109,17 +406,48 @@ fn split_repo_notation(arg: &str) -> Option<(&str, &str)> {
     if arg.starts_with('-') {
         return None;
     }
-    let mut iter = arg.splitn(2, '/');
-    let repo = iter.next()?;
-    let pkg = iter.next()?;
+    let (repo, pkg) = arg.split_once('/')?;
     if repo.is_empty() || pkg.is_empty() {
         return None;
     }
     Some((repo, pkg))
 }
 
-fn classify_sync_targets(cfg: &Config, pkgs: &[String]) -> Result<(Vec<String>, Vec<AurRequest>)> {
-    let default_source = AurSource::from_cfg(cfg);
+/// Package names as pacman and the AUR allow them: letters, digits and `@._+-`,
+/// not starting with `-` or `.`.
+fn is_valid_pkgname(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+}
+
+fn classify_sync_targets(
+    cfg: &Config,
+    pkgs: &[String],
+    choose_repo: bool,
+) -> Result<(Vec<String>, Vec<AurRequest>)> {
+    let invalid: Vec<&str> = pkgs
+        .iter()
+        .filter(|p| !p.starts_with('-'))
+        .filter(|p| {
+            let name = split_repo_notation(p).map_or(p.as_str(), |(_, name)| name);
+            !is_valid_pkgname(name)
+        })
+        .map(String::as_str)
+        .collect();
+    if !invalid.is_empty() {
+        return Err(anyhow!(
+            "Invalid package name(s): {}",
+            invalid
+                .iter()
+                .map(|n| format!("{:?}", n))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    let default_source = AurSource::preferred(cfg);
     let mut repo_pkgs: Vec<String> = vec![];
     let mut aur_pkgs: Vec<AurRequest> = vec![];
     let mut needs_detection: Vec<String> = vec![];
@@ -158,11 +486,27 @@ fn classify_sync_targets(cfg: &Config, pkgs: &[String]) -> Result<(Vec<String>,
         for name in aur_detected {
             *aur_counts.entry(name).or_insert(0) += 1;
         }
+        note_aur_shadowed(cfg, &repo_counts);
+
+        // Only pay for `pacman -Sl` when something could pick a non-default repo
+        let sources = if !repo_counts.is_empty()
+            && (!cfg.repo_priority.is_empty() || (choose_repo && !cfg.noconfirm))
+        {
+            pac::list_sync_sources()?
+        } else {
+            HashMap::new()
+        };
 
         for name in needs_detection {
             if let Some(count) = repo_counts.get_mut(&name) {
                 if *count > 0 {
-                    repo_pkgs.push(name.clone());
+                    let target = match sources.get(&name) {
+                        Some(repos) if repos.len() > 1 => {
+                            resolve_repo(cfg, &name, repos, choose_repo)?
+                        }
+                        _ => name.clone(),
+                    };
+                    repo_pkgs.push(target);
                     *count -= 1;
                     continue;
                 }
@@ -184,18 +528,128 @@ fn classify_sync_targets(cfg: &Config, pkgs: &[String]) -> Result<(Vec<String>,
     Ok((repo_pkgs, aur_pkgs))
 }
 
-async fn handle_print_updates(cfg: &Config, forcerefresh: bool) -> Result<()> {
-    let client = Client::builder().user_agent("aurwrap/0.1").build()?;
+/// Bare names go to the sync repos when pacman knows them. Point out the ones the
+/// AUR has as well, since the AUR package is then only reachable as `aur/<name>`.
+fn note_aur_shadowed(cfg: &Config, repo_names: &HashMap<String, usize>) {
+    if repo_names.is_empty() {
+        return;
+    }
+    let names: Vec<String> = repo_names.keys().cloned().collect();
+    let Ok(also_aur) = aur::names_in_aur(cfg, &names) else {
+        return;
+    };
+    let mut also_aur: Vec<&String> = also_aur.iter().collect();
+    also_aur.sort();
+    for name in also_aur {
+        println!(
+            "{} {} {}",
+            info_icon(),
+            pacman_badge(),
+            dim().apply_to(format!(
+                "{} is in both a sync repo and the AUR; using the repo package (aur/{} for the AUR one)",
+                name, name
+            ))
+        );
+    }
+}
+
+/// Target for a name several sync repos provide: the first `repo_priority`
+/// match, else the user's pick with --choose-repo, else the bare name so
+/// pacman takes its usual first match (always the case under --noconfirm).
+fn resolve_repo(cfg: &Config, name: &str, repos: &[String], choose_repo: bool) -> Result<String> {
+    if let Some(repo) = cfg
+        .repo_priority
+        .iter()
+        .find(|r| repos.iter().any(|have| have == *r))
+    {
+        return Ok(format!("{}/{}", repo, name));
+    }
+    if choose_repo && !cfg.noconfirm {
+        let repo = pick_repo(name, repos)?;
+        return Ok(format!("{}/{}", repo, name));
+    }
+    Ok(name.to_string())
+}
+
+fn update_row(p: &PackageUpdate) -> (&str, &str, &str) {
+    (&p.name, &p.old_version, &p.new_version)
+}
+
+fn sort_key(matches: &clap::ArgMatches) -> SortKey {
+    matches
+        .get_one::<String>("sort")
+        .and_then(|s| SortKey::parse(s))
+        .unwrap_or(SortKey::Name)
+}
+
+async fn sync_repos_for(sort: SortKey) -> HashMap<String, String> {
+    if sort == SortKey::Repo {
+        pac::list_sync_repos().await.unwrap_or_default()
+    } else {
+        HashMap::new()
+    }
+}
+
+/// `-P --ndjson`: one `{"repo","name","old","new"}` line per update, flushed as
+/// soon as it is known. Nothing else goes to stdout, which is why it never
+/// refreshes (the sudo -Sy would print pacman's own output there); every query
+/// it makes is read-only, so it needs no sudo either.
+async fn handle_print_updates_ndjson(cfg: &Config) -> Result<()> {
+    let client = aur::http_client(cfg)?;
+    let foreign = pac::list_foreign_packages().await?;
+    if let Err(e) = state::warn_on_downgrades(cfg, &foreign).await {
+        eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+    }
+    if !foreign.is_empty() {
+        let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?;
+        for (name, curver) in foreign.iter() {
+            if let Some(info) = infos.get(name) {
+                if let Ok(ord) = pac::vercmp_cached(curver, &info.version).await {
+                    if ord < 0 {
+                        emit_ndjson("aur", name, curver, &info.version)?;
+                    }
+                }
+            }
+        }
+    }
+
+    let repo_of = pac::list_sync_repos().await.unwrap_or_default();
+    for (name, old_ver, new_ver) in pac::list_outdated_pacman_packages(false, false).await? {
+        let repo = repo_of.get(&name).map(String::as_str).unwrap_or("pacman");
+        emit_ndjson(repo, &name, &old_ver, &new_ver)?;
+    }
+    Ok(())
+}
+
+fn emit_ndjson(repo: &str, name: &str, old: &str, new: &str) -> Result<()> {
+    use std::io::Write;
+    let line = serde_json::json!({ "repo": repo, "name": name, "old": old, "new": new });
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "{}", line)?;
+    out.flush()?;
+    Ok(())
+}
+
+async fn handle_print_updates(
+    cfg: &Config,
+    refresh: bool,
+    forcerefresh: bool,
+    sort: SortKey,
+) -> Result<()> {
+    let client = aur::http_client(cfg)?;
 
     // Get outdated AUR packages
     let foreign = pac::list_foreign_packages().await?;
+    if let Err(e) = state::warn_on_downgrades(cfg, &foreign).await {
+        eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+    }
     let mut aur_updates = Vec::<PackageUpdate>::new();
 
     if !foreign.is_empty() {
         let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?;
         for (name, curver) in foreign.iter() {
             if let Some(info) = infos.get(name) {
-                if let Ok(ord) = pac::vercmp(curver, &info.version).await {
+                if let Ok(ord) = pac::vercmp_cached(curver, &info.version).await {
                     if ord < 0 {
                         // installed < aur
                         aur_updates.push(PackageUpdate {
@@ -210,8 +664,8 @@ async fn handle_print_updates(cfg: &Config, forcerefresh: bool) -> Result<()> {
     }
 
     // Get outdated pacman packages
-    let pacman_outdated = pac::list_outdated_pacman_packages(forcerefresh).await?;
-    let pacman_updates: Vec<PackageUpdate> = pacman_outdated
+    let pacman_outdated = pac::list_outdated_pacman_packages(refresh, forcerefresh).await?;
+    let mut pacman_updates: Vec<PackageUpdate> = pacman_outdated
         .into_iter()
         .map(|(name, old_ver, new_ver)| PackageUpdate {
             name,
@@ -220,6 +674,10 @@ async fn handle_print_updates(cfg: &Config, forcerefresh: bool) -> Result<()> {
         })
         .collect();
 
+    let repo_of = sync_repos_for(sort).await;
+    sort_updates(&mut aur_updates, sort, &repo_of, update_row);
+    sort_updates(&mut pacman_updates, sort, &repo_of, update_row);
+
     // Display AUR updates
     println!(
         "\n{} {}",
@@ -284,10 +742,7 @@ async fn handle_print_updates(cfg: &Config, forcerefresh: bool) -> Result<()> {
         pacman: pacman_updates,
     };
 
-    let json_path = home_dir()
-        .ok_or_else(|| anyhow!("Cannot determine home directory"))?
-        .join("turbo")
-        .join("needupdate.json");
+    let json_path = cfg.needupdate_path();
 
     // Ensure directory exists
     if let Some(parent) = json_path.parent() {
@@ -307,271 +762,2483 @@ async fn handle_print_updates(cfg: &Config, forcerefresh: bool) -> Result<()> {
     Ok(())
 }
 
-async fn handle_sysupgrade(cfg: &Config, ycount: u8, arg_matches: &clap::ArgMatches) -> Result<()> {
-    // If requested, refresh sync databases first (-y / -yy)
-    if ycount > 0 {
-        let mut flags = vec![String::from("-Syu")];
-        if ycount > 1 {
-            flags = vec![String::from("-Syyu")];
-        }
-        let command_str = format!("Running: sudo pacman {}", flags[0].as_str());
+/// Wind down after a first Ctrl-C: report what finished, clean up, and skip the install.
+fn stop_interrupted(cfg: &Config, temp_path: &Path, built_ok: &[String]) -> Result<()> {
+    println!(
+        "\n{} {}",
+        section_title().apply_to("Interrupted"),
+        aur_badge()
+    );
+    if built_ok.is_empty() {
         println!(
-            "{} {} {}",
+            "  {} {}",
             info_icon(),
-            pacman_badge(),
-            prompt().apply_to(command_str.as_str())
+            dim().apply_to("No packages were built.")
+        );
+    } else {
+        println!(
+            "  {} {}",
+            info_icon(),
+            highlight().apply_to(format!("Built (not installed): {}", built_ok.join(", ")))
         );
-        pac::run_pacman(&flags).await?;
-        sleep(Duration::from_secs(3)).await;
     }
+    if !cfg.reuse_temp {
+        clean_dir_contents(temp_path)?;
+    }
+    Err(anyhow!("Interrupted by user"))
+}
 
-    if ycount > 1 {
-        ensure_latest_release_installed(cfg)?;
+fn show_pkgbuild_diffs(cfg: &Config, pkgbases: &[String], skip: &[String], temp_path: &Path) {
+    for base in pkgbases.iter().filter(|b| !skip.contains(b)) {
+        if let Err(e) = diff_against_last_build(cfg, base, &temp_path.join(base)) {
+            eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!("Could not diff PKGBUILD for {}: {}", base, e))
+            );
+        }
     }
+}
 
-    // Foreign packages (installed that are not in repos) - typically AUR ones.
-    let foreign = pac::list_foreign_packages().await?; // name -> version
-    if foreign.is_empty() {
-        println!(
-            "{} {}",
-            info_icon(),
-            dim().apply_to("No foreign (AUR) packages installed.")
-        );
+/// AUR builds link against whatever the repos currently ship; building on top of
+/// stale sync DBs is the classic partial-upgrade footgun, so warn (or refuse).
+fn check_sync_db_age(cfg: &Config) -> Result<()> {
+    let max_age = Duration::from_secs(cfg.sync_db_max_age_hours * 3600);
+    let Ok(Some(age)) = pac::sync_db_age() else {
+        return Ok(());
+    };
+    if age <= max_age {
         return Ok(());
     }
+    let message = format!(
+        "Sync databases were last refreshed {}h ago; run turbo -Syu first to avoid a partial upgrade",
+        age.as_secs() / 3600
+    );
+    if cfg.require_fresh_db {
+        return Err(anyhow!(message));
+    }
+    eprintln!(
+        "{} {} {}",
+        warn_icon(),
+        pacman_badge(),
+        warning().apply_to(message)
+    );
+    Ok(())
+}
 
-    // Query AUR for latest versions
-    let client = Client::builder().user_agent("aurwrap/0.1").build()?;
-    let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?; // name -> AurInfo
+/// Resolve the edit step: --edit/--noedit (or the `edit` config) short-circuit the
+/// prompt; otherwise ask, unless running unattended.
+fn should_edit(cfg: &Config, unattended: bool) -> Result<bool> {
+    match cfg.edit {
+        EditMode::Always => Ok(true),
+        EditMode::Never => Ok(false),
+        EditMode::Ask if unattended => Ok(false),
+        EditMode::Ask => confirm(
+            "Edit PKGBUILDs/source files in file manager before building?",
+            false,
+        ),
+    }
+}
 
-    // Collect outdated (AUR version strictly newer than installed using pacman's vercmp)
-    let mut outdated: Vec<Pickable> = vec![];
-    for (name, curver) in foreign.iter() {
+/// Note built packages whose real version differs from what .SRCINFO/the AUR
+/// advertised (typical for VCS packages that compute pkgver() at build time).
+fn report_version_drift(built: &[(String, String)], infos: &HashMap<String, aur::AurInfo>) {
+    for (name, version) in built {
         if let Some(info) = infos.get(name) {
-            if let Ok(ord) = pac::vercmp(curver, &info.version).await {
-                if ord < 0 {
-                    // installed < aur
-                    outdated.push(Pickable {
-                        name: name.clone(),
-                        current: curver.clone(),
-                        latest: info.version.clone(),
-                    });
-                }
+            if &info.version != version {
+                println!(
+                    "{} {} {}",
+                    info_icon(),
+                    aur_badge(),
+                    dim().apply_to(format!(
+                        "{} built as {} (.SRCINFO said {})",
+                        name, version, info.version
+                    ))
+                );
             }
         }
     }
+}
 
-    if outdated.is_empty() {
-        println!(
+/// Put back the install reasons captured before a `pacman -U`, so rebuilt
+/// dependencies do not turn explicit (or the other way round).
+fn restore_install_reasons(before: &HashMap<String, bool>) {
+    let names: Vec<String> = before.keys().cloned().collect();
+    let after = pac::install_reasons(&names).unwrap_or_default();
+    for asdeps in [true, false] {
+        let changed = reasons_to_restore(before, &after, asdeps);
+        if let Err(e) = pac::set_install_reason(&changed, asdeps) {
+            eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!("Could not restore install reasons: {}", e))
+            );
+        }
+    }
+}
+
+/// Names whose reason was `asdeps` before and flipped after, sorted.
+fn reasons_to_restore(
+    before: &HashMap<String, bool>,
+    after: &HashMap<String, bool>,
+    asdeps: bool,
+) -> Vec<String> {
+    let mut changed: Vec<String> = before
+        .iter()
+        .filter(|(name, was_dep)| **was_dep == asdeps && after.get(*name) == Some(&!asdeps))
+        .map(|(name, _)| name.clone())
+        .collect();
+    changed.sort();
+    changed
+}
+
+fn installed_names(built: &[(String, String)]) -> Vec<String> {
+    built.iter().map(|(name, _)| name.clone()).collect()
+}
+
+/// Best-effort bookkeeping after a successful install: record versions in
+/// state.json and keep a copy of the artifacts for offline --reinstall.
+/// `built` holds the (name, version) pairs read back from `zsts`.
+fn remember_installed(cfg: &Config, zsts: &[String], built: &[(String, String)]) {
+    if let Err(e) = state::record_installed(cfg, built) {
+        eprintln!(
             "{} {}",
-            success_icon(),
-            success().apply_to("All AUR packages are up to date.")
+            warn_icon(),
+            warning().apply_to(format!("Could not record installed versions: {}", e))
         );
-        return Ok(());
     }
-
-    let selection = pick_updates_numeric(&outdated)?;
-    if selection.is_empty() {
-        println!(
+    if let Err(e) = cache_artifacts(cfg, zsts) {
+        eprintln!(
             "{} {}",
-            info_icon(),
-            dim().apply_to("No packages selected.")
+            warn_icon(),
+            warning().apply_to(format!("Could not cache built packages: {}", e))
         );
-        return Ok(());
     }
+}
 
-    // Resolve dependencies and build order for selected updates (by package names)
-    let order = aur::resolve_build_order(cfg, &client, &selection)?;
-    let temp_path = cfg.temp_dir();
-    clean_dir_contents(&temp_path)?; // start with a clean temp each run
+/// Run the build half of the pipeline on a PKGBUILD directory the user already has.
+/// The directory is built in place and never cleaned.
+fn handle_local(cfg: &Config, dir: &Path, arg_matches: &clap::ArgMatches) -> Result<()> {
+    if !dir.join("PKGBUILD").exists() {
+        return Err(anyhow!("No PKGBUILD found in {}", dir.display()));
+    }
+    let started = SystemTime::now();
 
-    // Track failures
-    let mut clone_failed: Vec<String> = vec![]; // track by pkgbase
-    let mut build_failed: Vec<String> = vec![]; // track by pkgbase
-    let mut built_ok: Vec<String> = vec![]; // track by pkgbase
+    ensure_build_tools(cfg)?;
+    ensure_free_space(cfg, dir)?;
+    regen_srcinfo_if_edited(cfg, dir)?;
+    let _ = import_validpgpkeys(cfg, dir);
+    verify_sources(cfg, dir)?;
+    let base = dir
+        .canonicalize()
+        .ok()
+        .and_then(|d| d.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    timed_build(cfg, &base, dir)?;
 
-    // Group targets by AUR pkgbase: only clone/build unique pkgbase repos
-    let info_for_order = aur::aur_info_batch(cfg, &client, order.clone())?; // name -> AurInfo
-    let mut seen_base: HashSet<String> = HashSet::new();
-    let mut pkgbases: Vec<String> = vec![];
-    for name in &order {
-        if let Some(info) = info_for_order.get(name) {
-            if seen_base.insert(info.pkgbase.clone()) {
-                pkgbases.push(info.pkgbase.clone());
-            }
-        }
+    // Only what this build produced; older artifacts may still sit in the directory
+    let zsts: Vec<String> = collect_zsts(dir, None, cfg.include_debug)?
+        .into_iter()
+        .filter(|z| {
+            fs::metadata(z)
+                .and_then(|m| m.modified())
+                .is_ok_and(|t| t >= started)
+        })
+        .collect();
+    if zsts.is_empty() {
+        return Err(anyhow!("No built *.pkg.tar.zst artifacts found."));
     }
 
-    // Clone each, continue on error
-    let default_source = AurSource::from_cfg(cfg);
-    for base in &pkgbases {
-        let spec = AurCloneSpec {
-            pkgbase: base.clone(),
-            source: default_source,
-        };
-        if let Err(e) = clone_aur_pkgs(cfg, std::slice::from_ref(&spec), &temp_path) {
-            let pretty_base = format!("{}", package_name().apply_to(base));
-            eprintln!(
-                "{} {} {}",
-                error_icon(),
-                aur_badge(),
-                error().apply_to(format!("Clone failed for {}: {}", pretty_base, e))
-            );
-            clone_failed.push(base.clone());
-        }
-    }
+    let built = read_artifacts(&zsts);
+
+    let install_noconfirm = cfg.noconfirm || arg_matches.get_flag("noconfirm_install_only");
+    if install_noconfirm {
+        pac::sudo_pacman_U_noconfirm(&zsts)?;
+    } else {
+        pac::sudo_pacman_U(&zsts)?;
+    }
+    remember_installed(cfg, &zsts, &built);
+    run_post_install_hook(cfg, &installed_names(&built));
+    Ok(())
+}
+
+/// Diagnostic: show what resolve_build_order makes of the given roots, in plain
+/// text so it can be pasted into a bug report.
+fn handle_print_build_order(cfg: &Config, roots: &[String]) -> Result<()> {
+    if roots.is_empty() {
+        return Err(anyhow!("No packages specified."));
+    }
+    let client = aur::http_client(cfg)?;
+    let order = aur::resolve_build_order(cfg, &client, roots, true)?;
+    let infos = aur::aur_info_batch(cfg, &client, order.clone())?;
+
+    println!("Build order:");
+    for (i, name) in order.iter().enumerate() {
+        let kind = if roots.contains(name) { "root" } else { "dep" };
+        let base = infos.get(name).map(|i| i.pkgbase.as_str()).unwrap_or("?");
+        println!("  {:>2}) {} [{}] (pkgbase: {})", i + 1, name, kind, base);
+    }
+    let missing: Vec<&String> = roots.iter().filter(|r| !infos.contains_key(*r)).collect();
+    if !missing.is_empty() {
+        println!(
+            "Not found in the AUR: {}",
+            missing
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    println!("\nDependency tree (AUR deps only):");
+    for root in roots.iter().filter(|r| infos.contains_key(*r)) {
+        let mut path: Vec<String> = vec![];
+        print_dep_tree(root, &infos, 0, &mut path);
+    }
+    Ok(())
+}
+
+/// The complete footprint of installing `roots`: every AUR package that would be
+/// built, and every repo package pacman would pull in for them (transitively),
+/// marking what is already installed. Names found nowhere are listed apart.
+async fn handle_print_deps(cfg: &Config, roots: &[String]) -> Result<()> {
+    if roots.is_empty() {
+        return Err(anyhow!("No packages specified."));
+    }
+    let client = aur::http_client(cfg)?;
+    let order = aur::resolve_build_order(cfg, &client, roots, false)?;
+    let infos = aur::aur_info_batch(cfg, &client, order.clone())?;
+    let missing: Vec<&str> = roots
+        .iter()
+        .filter(|r| !infos.contains_key(*r))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!("Not found in the AUR: {}", missing.join(", ")));
+    }
+
+    // Whatever the BFS did not resolve in the AUR is left for pacman
+    let mut repo_deps: Vec<String> = order
+        .iter()
+        .filter_map(|name| infos.get(name))
+        .flat_map(aur::resolve_dep_names)
+        .filter(|dep| !infos.contains_key(dep))
+        .collect();
+    repo_deps.sort();
+    repo_deps.dedup();
+    let unmet = pac::unsatisfied_deps(repo_deps.clone()).await?;
+    let (pulled, unresolved) = pac::sync_closure(&unmet)?;
+    // Direct deps something installed satisfies, then what pacman would install
+    let mut repo_rows: Vec<(&str, &str)> = repo_deps
+        .iter()
+        .filter(|d| !unmet.contains(d))
+        .map(|d| (d.as_str(), " (installed)"))
+        .collect();
+    repo_rows.extend(pulled.iter().map(|name| {
+        let note = if unmet.contains(name) { "" } else { " (dep)" };
+        (name.as_str(), note)
+    }));
+    repo_rows.sort();
+    repo_rows.dedup_by(|a, b| a.0 == b.0);
+
+    println!(
+        "\n{} {}",
+        section_title().apply_to(format!("AUR packages to build ({})", order.len())),
+        aur_badge()
+    );
+    for name in &order {
+        let kind = if roots.contains(name) { "" } else { " (dep)" };
+        println!(
+            "  {} {}{}",
+            bullet(),
+            package_name().apply_to(name),
+            dim().apply_to(kind)
+        );
+    }
+
+    println!(
+        "\n{} {}",
+        section_title().apply_to(format!(
+            "Repo packages pacman will pull in ({})",
+            repo_rows.len()
+        )),
+        pacman_badge()
+    );
+    if repo_rows.is_empty() {
+        println!("  {} {}", info_icon(), dim().apply_to("None."));
+    }
+    for (name, note) in &repo_rows {
+        println!(
+            "  {} {}{}",
+            bullet(),
+            package_name().apply_to(name),
+            dim().apply_to(note)
+        );
+    }
+
+    if !unresolved.is_empty() {
+        println!(
+            "\n{} {}",
+            section_title().apply_to(format!(
+                "Not found in the AUR or the repos ({})",
+                unresolved.len()
+            )),
+            warn_icon()
+        );
+        for name in &unresolved {
+            println!("  {} {}", bullet(), warning().apply_to(name));
+        }
+    }
+    Ok(())
+}
+
+/// `--rdeps`: reverse dependencies of `name`, installed ones and across the AUR,
+/// to judge whether it is safe to remove.
+fn handle_rdeps(cfg: &Config, name: &str) -> Result<()> {
+    println!(
+        "\n{} {}",
+        section_title().apply_to(format!("Installed packages requiring {}", name)),
+        pacman_badge()
+    );
+    match pac::required_by(name)? {
+        None => println!(
+            "  {} {}",
+            info_icon(),
+            dim().apply_to(format!("{} is not installed.", name))
+        ),
+        Some(local) if local.is_empty() => {
+            println!("  {} {}", info_icon(), dim().apply_to("None."))
+        }
+        Some(local) => {
+            for dep in &local {
+                println!("  {} {}", bullet(), package_name().apply_to(dep));
+            }
+        }
+    }
+
+    println!(
+        "\n{} {}",
+        section_title().apply_to(format!("AUR packages depending on {}", name)),
+        aur_badge()
+    );
+    let client = aur::http_client(cfg)?;
+    match aur::aur_reverse_deps(cfg, &client, name) {
+        Err(e) => eprintln!(
+            "  {} {}",
+            warn_icon(),
+            warning().apply_to(format!("{:#}", e))
+        ),
+        Ok(rdeps) if rdeps.is_empty() => {
+            println!("  {} {}", info_icon(), dim().apply_to("None."))
+        }
+        Ok(rdeps) => {
+            for (dep, fields) in &rdeps {
+                println!(
+                    "  {} {:<32} {}",
+                    bullet(),
+                    package_name().apply_to(dep),
+                    dim().apply_to(fields.join(", "))
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `--open`: the AUR page of each package, and with `upstream` its `url=` too.
+fn handle_open(cfg: &Config, pkgs: &[String], upstream: bool) -> Result<()> {
+    let client = aur::http_client(cfg)?;
+    let infos = aur::aur_info_batch(cfg, &client, pkgs.to_vec())?;
+    let missing: Vec<&str> = pkgs
+        .iter()
+        .filter(|p| !infos.contains_key(*p))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!("Not found in the AUR: {}", missing.join(", ")));
+    }
+    for name in pkgs {
+        let mut urls = vec![aur::aur_page_url(name)];
+        if upstream {
+            match &infos[name].url {
+                Some(url) => urls.push(url.clone()),
+                None => println!(
+                    "{} {}",
+                    info_icon(),
+                    dim().apply_to(format!("{} has no upstream URL", name))
+                ),
+            }
+        }
+        for url in &urls {
+            println!("{} {}", info_icon(), path().apply_to(url));
+            open_in_browser(url)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_dep_tree(
+    name: &str,
+    infos: &HashMap<String, aur::AurInfo>,
+    depth: usize,
+    path: &mut Vec<String>,
+) {
+    let indent = "  ".repeat(depth);
+    if path.iter().any(|p| p == name) {
+        println!("{}{} (cycle)", indent, name);
+        return;
+    }
+    println!("{}{}", indent, name);
+    let Some(info) = infos.get(name) else {
+        return;
+    };
+    path.push(name.to_string());
+    let mut deps: Vec<String> = aur::resolve_dep_names(info)
+        .into_iter()
+        .filter(|d| infos.contains_key(d))
+        .collect();
+    deps.sort();
+    deps.dedup();
+    for dep in deps {
+        print_dep_tree(&dep, infos, depth + 1, path);
+    }
+    path.pop();
+}
+
+async fn handle_search(cfg: &Config, terms: &[String], by: &str) -> Result<()> {
+    if terms.is_empty() {
+        return Err(anyhow!("No search terms given."));
+    }
+    // Repo results straight from pacman, then the AUR
+    let mut pacman_args = vec![String::from("-Ss")];
+    pacman_args.extend(terms.iter().cloned());
+    pac::passthrough_to_pacman(&pacman_args).await?;
+
+    let client = aur::http_client(cfg)?;
+    let results = aur::aur_search(cfg, &client, by, terms)?;
+    if results.is_empty() {
+        println!(
+            "{} {} {}",
+            info_icon(),
+            aur_badge(),
+            dim().apply_to("No AUR packages found.")
+        );
+        return Ok(());
+    }
+    for info in &results {
+        println!(
+            "{}{} {}",
+            aur_accent().apply_to("aur/"),
+            package_name().apply_to(&info.name),
+            new_version().apply_to(&info.version)
+        );
+        if let Some(desc) = &info.description {
+            println!("    {}", desc);
+        }
+    }
+    Ok(())
+}
+
+/// Health check: every foreign package's AUR `depends` must still be satisfied
+/// by something installed (e.g. not removed as an orphan in the meantime).
+async fn handle_installed_deps_check(cfg: &Config) -> Result<()> {
+    let foreign = pac::list_foreign_packages().await?;
+    let client = aur::http_client(cfg)?;
+    let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?;
+
+    let mut all_deps: Vec<String> = infos
+        .values()
+        .flat_map(|info| info.depends.iter().flatten().cloned())
+        .collect();
+    all_deps.sort();
+    all_deps.dedup();
+    let unmet: HashSet<String> = pac::unsatisfied_deps(all_deps).await?.into_iter().collect();
+
+    let mut broken = 0;
+    for (name, info) in infos.iter().filter(|(n, _)| foreign.contains_key(*n)) {
+        let missing: Vec<&str> = info
+            .depends
+            .iter()
+            .flatten()
+            .filter(|d| unmet.contains(*d))
+            .map(String::as_str)
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        if broken == 0 {
+            println!("\n{}", section_title().apply_to("Unmet dependencies"));
+        }
+        broken += 1;
+        println!(
+            "  {} {:<32} {}",
+            bullet(),
+            package_name().apply_to(name),
+            warning().apply_to(missing.join(", "))
+        );
+    }
+
+    let unchecked = foreign.keys().filter(|n| !infos.contains_key(*n)).count();
+    if broken == 0 {
+        println!(
+            "{} {}",
+            success_icon(),
+            success().apply_to("All AUR package dependencies are satisfied.")
+        );
+    }
+    if unchecked > 0 {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to(format!(
+                "{} foreign package(s) not found in the AUR were not checked",
+                unchecked
+            ))
+        );
+    }
+    Ok(())
+}
+
+/// Cache size, rebuild frequency and build times from state.json, to help decide
+/// what to prune and which packages cost the most build time.
+fn handle_stats(cfg: &Config) -> Result<()> {
+    const TOP: usize = 5;
+    let st = state::load_state(cfg)?;
+
+    let (mut count, mut bytes) = (0usize, 0u64);
+    if let Ok(entries) = fs::read_dir(cfg.pkg_cache_dir()) {
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().contains(".pkg.tar") {
+                count += 1;
+                bytes += entry.metadata().map_or(0, |m| m.len());
+            }
+        }
+    }
+    println!("{}", section_title().apply_to("Artifact cache"));
+    println!(
+        "  {} {} package(s), {:.1} MiB in {}",
+        bullet(),
+        number().apply_to(count),
+        bytes as f64 / (1024.0 * 1024.0),
+        path().apply_to(cfg.pkg_cache_dir().display())
+    );
+
+    // pkgbase -> (successful builds, total seconds, failures)
+    let mut per_base: HashMap<&str, (u64, u64, u64)> = HashMap::new();
+    for b in &st.builds {
+        let e = per_base.entry(b.pkgbase.as_str()).or_default();
+        if b.success {
+            e.0 += 1;
+            e.1 += b.secs;
+        } else {
+            e.2 += 1;
+        }
+    }
+    let mut rows: Vec<(&str, u64, u64, u64)> = per_base
+        .into_iter()
+        .filter(|(_, (ok, _, _))| *ok > 0)
+        .map(|(base, (ok, secs, failed))| (base, ok, secs / ok, failed))
+        .collect();
+
+    println!(
+        "\n{}",
+        section_title().apply_to(format!("Build history ({} runs)", st.builds.len()))
+    );
+    if rows.is_empty() {
+        println!(
+            "  {} {}",
+            bullet(),
+            dim().apply_to("No successful builds recorded yet.")
+        );
+    } else {
+        println!("  {}", highlight().apply_to("Most rebuilt"));
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        for (base, ok, avg, failed) in rows.iter().take(TOP) {
+            println!(
+                "  {} {} {} build(s), avg {}s{}",
+                bullet(),
+                package_name().apply_to(base),
+                number().apply_to(ok),
+                avg,
+                if *failed > 0 {
+                    format!(", {} failed", failed)
+                } else {
+                    String::new()
+                }
+            );
+        }
+        println!("  {}", highlight().apply_to("Slowest on average"));
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(b.0)));
+        for (base, ok, avg, _) in rows.iter().take(TOP) {
+            println!(
+                "  {} {} avg {}s over {} build(s)",
+                bullet(),
+                package_name().apply_to(base),
+                number().apply_to(avg),
+                ok
+            );
+        }
+    }
+
+    let last = match st.last_install {
+        Some(t) => {
+            let days = (state::unix_now() - t).max(0) / 86400;
+            match days {
+                0 => String::from("today"),
+                1 => String::from("yesterday"),
+                n => format!("{} days ago", n),
+            }
+        }
+        None => String::from("never (not recorded yet)"),
+    };
+    println!(
+        "\n{} Last successful upgrade: {}",
+        info_icon(),
+        highlight().apply_to(last)
+    );
+    Ok(())
+}
+
+/// `--explicit`: explicitly installed packages split into native (sync repos) and
+/// foreign (AUR or otherwise local) ones, for auditing where things came from.
+async fn handle_explicit() -> Result<()> {
+    let explicit = pac::list_explicit_packages().await?;
+    let foreign = pac::list_foreign_packages().await?;
+    let (foreign_explicit, native): (Vec<_>, Vec<_>) = explicit
+        .iter()
+        .partition(|(name, _)| foreign.contains_key(*name));
+
+    for (title, badge, pkgs) in [
+        ("Native (repo)", pacman_badge(), &native),
+        ("Foreign (AUR)", aur_badge(), &foreign_explicit),
+    ] {
+        println!(
+            "\n{} {} {}",
+            section_title().apply_to(title),
+            badge,
+            dim().apply_to(format!("({})", pkgs.len()))
+        );
+        for (name, version) in pkgs.iter() {
+            println!(
+                "  {} {:<32} {}",
+                bullet(),
+                package_name().apply_to(name),
+                current_version().apply_to(version)
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn handle_orphans(cfg: &Config) -> Result<()> {
+    let orphans = pac::list_orphans().await?;
+    if orphans.is_empty() {
+        println!(
+            "{} {}",
+            success_icon(),
+            success().apply_to("No orphaned packages.")
+        );
+        return Ok(());
+    }
+    let foreign = pac::list_foreign_packages().await?;
+
+    println!("\n{}", section_title().apply_to("Orphaned Packages"));
+    for name in &orphans {
+        let badge = if foreign.contains_key(name) {
+            aur_badge()
+        } else {
+            pacman_badge()
+        };
+        println!(
+            "  {} {:<32} {}",
+            bullet(),
+            package_name().apply_to(name),
+            badge
+        );
+    }
+
+    pac::ensure_privilege_tool()?;
+    let remove = cfg.noconfirm
+        || confirm(
+            format!("Remove {} orphaned packages?", orphans.len()),
+            false,
+        )?;
+    if !remove {
+        println!("{} {}", info_icon(), dim().apply_to("No packages removed."));
+        return Ok(());
+    }
+
+    // The prompt may have sat for a while; only remove what is still an orphan
+    let current: HashSet<String> = pac::list_orphans().await?.into_iter().collect();
+    let (orphans, gone): (Vec<String>, Vec<String>) =
+        orphans.into_iter().partition(|name| current.contains(name));
+    if !gone.is_empty() {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to(format!("No longer orphaned, skipped: {}", gone.join(", ")))
+        );
+    }
+    if orphans.is_empty() {
+        return Ok(());
+    }
+
+    let mut args = vec![String::from("-Rns")];
+    if cfg.noconfirm {
+        args.push(String::from("--noconfirm"));
+    }
+    args.extend(orphans);
+    pac::passthrough_to_pacman(&args).await?;
+    Ok(())
+}
+
+async fn handle_reinstall(cfg: &Config, name: &str) -> Result<()> {
+    let cached = find_cached_artifacts(cfg, name)?;
+    let mut newest: Option<(String, String)> = None;
+    for (version, file) in cached {
+        let is_newer = match &newest {
+            Some((best, _)) => pac::vercmp_cached(&version, best).await? > 0,
+            None => true,
+        };
+        if is_newer {
+            newest = Some((version, file));
+        }
+    }
+    let Some((version, file)) = newest else {
+        return Err(anyhow!(
+            "No cached artifact for {} in {}; not fetching from the network",
+            name,
+            cfg.pkg_cache_dir().display()
+        ));
+    };
+
+    println!(
+        "{} {} {} {}",
+        info_icon(),
+        highlight().apply_to("Reinstalling from cache"),
+        package_name().apply_to(name),
+        new_version().apply_to(&version)
+    );
+    let zsts = vec![file];
+    if cfg.noconfirm {
+        pac::sudo_pacman_U_noconfirm(&zsts)
+    } else {
+        pac::sudo_pacman_U(&zsts)
+    }
+}
+
+/// `--downgrade`: install an older cached build of `name`, picked from the
+/// artifact cache, and optionally hold it so -Syu does not upgrade it again.
+async fn handle_downgrade(cfg: &Config, name: &str) -> Result<()> {
+    let foreign = pac::list_foreign_packages().await?;
+    let installed = foreign.get(name);
+    let mut older: Vec<(String, String)> = vec![];
+    for (version, file) in find_cached_artifacts(cfg, name)? {
+        let is_older = match installed {
+            Some(cur) => pac::vercmp_cached(&version, cur).await? < 0,
+            None => true,
+        };
+        if is_older {
+            older.push((version, file));
+        }
+    }
+    // Newest first, so the default pick is the closest rollback
+    let mut sorted: Vec<(String, String)> = Vec::with_capacity(older.len());
+    for entry in older {
+        let mut at = sorted.len();
+        for (i, (v, _)) in sorted.iter().enumerate() {
+            if pac::vercmp_cached(&entry.0, v).await? > 0 {
+                at = i;
+                break;
+            }
+        }
+        sorted.insert(at, entry);
+    }
+    if sorted.is_empty() {
+        return Err(anyhow!(
+            "No cached version of {} older than {} in {}",
+            name,
+            installed.map_or("the installed one", String::as_str),
+            cfg.pkg_cache_dir().display()
+        ));
+    }
+
+    let choice = if cfg.noconfirm {
+        0
+    } else {
+        let versions: Vec<String> = sorted.iter().map(|(v, _)| v.clone()).collect();
+        match pick_version(name, &versions)? {
+            Some(i) => i,
+            None => {
+                println!("{} {}", info_icon(), dim().apply_to("Downgrade cancelled."));
+                return Ok(());
+            }
+        }
+    };
+    let (version, file) = &sorted[choice];
+    println!(
+        "{} {} {} {}",
+        info_icon(),
+        highlight().apply_to("Downgrading from cache"),
+        package_name().apply_to(name),
+        new_version().apply_to(version)
+    );
+    let zsts = vec![file.clone()];
+    if cfg.noconfirm {
+        pac::sudo_pacman_U_noconfirm(&zsts)?;
+    } else {
+        pac::sudo_pacman_U(&zsts)?;
+    }
+    // So the next run does not report this as an unexpected downgrade
+    if let Err(e) = state::record_installed(cfg, &[(name.to_string(), version.clone())]) {
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!("Could not record installed versions: {}", e))
+        );
+    }
+
+    if !cfg.noconfirm
+        && confirm(
+            format!("Hold {} at {} so -Syu skips it?", name, version),
+            true,
+        )?
+    {
+        state::set_held(cfg, name, Some(version))?;
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to(format!("Held; lift it with turbo --unhold {}", name))
+        );
+    }
+    Ok(())
+}
+
+/// `--export-installed-aur`: the explicitly installed foreign packages as a plain
+/// name list, so a new machine can get them back with `aurwrap -S $(cat PATH)`.
+/// AUR packages installed only as dependencies are left out; they come back
+/// with whatever needs them.
+async fn handle_export_installed_aur(out_path: &Path) -> Result<()> {
+    let foreign = pac::list_foreign_packages().await?;
+    let explicit = pac::list_explicit_packages().await?;
+    let names: Vec<&str> = foreign
+        .keys()
+        .filter(|name| explicit.contains_key(*name))
+        .map(String::as_str)
+        .collect();
+
+    if let Some(parent) = out_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let mut contents = names.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    fs::write(out_path, contents)?;
+
+    println!(
+        "{} {} {} {}",
+        success_icon(),
+        highlight().apply_to(format!(
+            "Exported {} explicitly installed AUR packages to",
+            names.len()
+        )),
+        path().apply_to(out_path.display()),
+        aur_badge()
+    );
+    Ok(())
+}
+
+async fn handle_export_foreign(cfg: &Config, out_path: &str) -> Result<()> {
+    let client = aur::http_client(cfg)?;
+    let foreign = pac::list_foreign_packages().await?;
+    let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?;
+
+    let mut entries: Vec<ForeignExport> = vec![];
+    let mut missing: Vec<String> = vec![];
+    for (name, installed) in &foreign {
+        match infos.get(name) {
+            Some(info) => entries.push(ForeignExport {
+                info: info.clone(),
+                installed_version: installed.clone(),
+            }),
+            None => missing.push(name.clone()),
+        }
+    }
+
+    let json_path = Path::new(out_path);
+    if let Some(parent) = json_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(json_path, serde_json::to_string_pretty(&entries)?)?;
+
+    println!(
+        "{} {} {} {}",
+        success_icon(),
+        highlight().apply_to(format!("Exported {} AUR packages to", entries.len())),
+        path().apply_to(json_path.display()),
+        aur_badge()
+    );
+    if !missing.is_empty() {
+        println!(
+            "  {} {}",
+            warn_icon(),
+            highlight().apply_to(format!("Not found on the AUR: {}", missing.join(", ")))
+        );
+    }
+    Ok(())
+}
+
+async fn handle_sysupgrade(cfg: &Config, ycount: u8, arg_matches: &clap::ArgMatches) -> Result<()> {
+    // --noconfirm / --upgrades-only: never read stdin, take every outdated package
+    let unattended = cfg.noconfirm || arg_matches.get_flag("upgrades_only");
+    // --keep-going-timeout: a soft budget, checked before each package only, so
+    // a long build that starts just in time can overrun it; builds already
+    // finished still get installed
+    let started = Instant::now();
+    let budget = arg_matches
+        .get_one::<u64>("keep_going_timeout")
+        .map(|mins| Duration::from_secs(mins * 60));
+    // --batch-install: install every N built bases instead of once at the end
+    let batch_size = arg_matches
+        .get_one::<u64>("batch_install")
+        .map(|n| *n as usize);
+
+    if cfg.news_check {
+        let client = aur::http_client(cfg)?;
+        match news::show_unread_news(cfg, &client, true) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{} {}", info_icon(), dim().apply_to("Upgrade cancelled."));
+                return Ok(());
+            }
+            Err(e) => eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e))),
+        }
+    }
+
+    let ignored_groups: Vec<String> = arg_matches
+        .get_many::<String>("ignoregroup")
+        .map(|v| {
+            v.flat_map(|g| g.split(','))
+                .map(str::trim)
+                .filter(|g| !g.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // --unified: repo updates go into the menu below instead of a pacman -Syu here
+    let unified = arg_matches.get_flag("unified");
+    let mut repo_updates = if unified {
+        pac::list_outdated_pacman_packages(ycount > 0, ycount > 1).await?
+    } else {
+        vec![]
+    };
+    if !repo_updates.is_empty() {
+        // pacman -S on a picked name would override IgnorePkg/IgnoreGroup and --ignoregroup
+        let patterns = pac::ignored_pkg_patterns();
+        let groups: Vec<String> = pac::ignored_group_names()
+            .into_iter()
+            .chain(ignored_groups.iter().cloned())
+            .collect();
+        let members = pac::group_members(&groups);
+        repo_updates.retain(|(name, _, _)| {
+            !members.contains(name)
+                && !patterns
+                    .iter()
+                    .any(|p| pac::matches_ignore_pattern(p, name))
+        });
+    }
+
+    // If requested, refresh sync databases first (-y / -yy)
+    if ycount > 0 && !unified {
+        let mut flags = vec![String::from("-Syu")];
+        if ycount > 1 {
+            flags = vec![String::from("-Syyu")];
+        }
+        if cfg.noconfirm {
+            flags.push(String::from("--noconfirm"));
+        }
+        if !ignored_groups.is_empty() {
+            flags.push(String::from("--ignoregroup"));
+            flags.push(ignored_groups.join(","));
+        }
+        let command_str = format!("Running: sudo pacman {}", flags.join(" "));
+        println!(
+            "{} {} {}",
+            info_icon(),
+            pacman_badge(),
+            prompt().apply_to(command_str.as_str())
+        );
+        pac::run_pacman(&flags).await?;
+        sleep(Duration::from_secs(3)).await;
+    }
+
+    if ycount > 1 && checked_recently(cfg).is_none() {
+        ensure_latest_release_installed(cfg)?;
+    }
+
+    // Foreign packages (installed that are not in repos) - typically AUR ones.
+    let foreign = pac::list_foreign_packages().await?; // name -> version
+    if let Err(e) = state::warn_on_downgrades(cfg, &foreign).await {
+        eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+    }
+    if foreign.is_empty() && repo_updates.is_empty() {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to("No foreign (AUR) packages installed.")
+        );
+        return Ok(());
+    }
+
+    // Query AUR for latest versions
+    let client = aur::http_client(cfg)?;
+    let infos = aur::aur_info_batch(cfg, &client, foreign.keys().cloned().collect())?; // name -> AurInfo
+
+    // Collect outdated (AUR version strictly newer than installed using pacman's vercmp)
+    let mut outdated: Vec<Pickable> = vec![];
+    for (name, curver) in foreign.iter() {
+        if let Some(info) = infos.get(name) {
+            if let Ok(ord) = pac::vercmp_cached(curver, &info.version).await {
+                if ord < 0 {
+                    // installed < aur
+                    outdated.push(Pickable {
+                        name: name.clone(),
+                        current: curver.clone(),
+                        latest: info.version.clone(),
+                        from_repo: false,
+                    });
+                }
+            }
+        }
+    }
+
+    // --ignoregroup: drop members of the informal AUR groups defined in the conf
+    let ignored: HashSet<&str> = ignored_groups
+        .iter()
+        .filter_map(|g| cfg.aur_groups.get(g))
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    outdated.retain(|p| {
+        let skip = ignored.contains(p.name.as_str());
+        if skip {
+            println!(
+                "{} {} {}",
+                info_icon(),
+                dim().apply_to("Ignoring (--ignoregroup):"),
+                package_name().apply_to(&p.name)
+            );
+        }
+        !skip
+    });
+
+    // Held by --downgrade until --unhold
+    let held = state::load_state(cfg).map(|s| s.held).unwrap_or_default();
+    outdated.retain(|p| {
+        let Some(version) = held.get(&p.name) else {
+            return true;
+        };
+        println!(
+            "{} {} {} {}",
+            info_icon(),
+            dim().apply_to("Held (--unhold to upgrade):"),
+            package_name().apply_to(&p.name),
+            current_version().apply_to(version)
+        );
+        false
+    });
+
+    outdated.extend(repo_updates.iter().map(|(name, current, latest)| Pickable {
+        name: name.clone(),
+        current: current.clone(),
+        latest: latest.clone(),
+        from_repo: true,
+    }));
+
+    // Packages that vanished from the AUR may just have been renamed
+    let missing: Vec<String> = foreign
+        .keys()
+        .filter(|name| !infos.contains_key(*name))
+        .cloned()
+        .collect();
+    let replacements = aur::find_replacements(cfg, &client, &missing).unwrap_or_else(|e| {
+        eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+        BTreeMap::new()
+    });
+
+    // After a repo upgrade, AUR packages linked against a library whose soname
+    // changed break without a new AUR release; offer to rebuild them. Under
+    // --unified the repo upgrade only happens after the menu, see below
+    let broken: Vec<String> = if ycount > 0 && !unified {
+        let pending: Vec<&str> = outdated.iter().map(|p| p.name.as_str()).collect();
+        find_rebuilds(cfg, &infos, &pending)
+    } else {
+        vec![]
+    };
+
+    if outdated.is_empty() && replacements.is_empty() && broken.is_empty() {
+        println!(
+            "{} {}",
+            success_icon(),
+            success().apply_to("All AUR packages are up to date.")
+        );
+        return Ok(());
+    }
+
+    let mut selection = if outdated.is_empty() {
+        vec![]
+    } else {
+        let sort = sort_key(arg_matches);
+        let repo_of = sync_repos_for(sort).await;
+        sort_updates(&mut outdated, sort, &repo_of, |p| {
+            (p.name.as_str(), p.current.as_str(), p.latest.as_str())
+        });
+        let flagged: HashSet<&str> = infos
+            .values()
+            .filter(|i| i.out_of_date.is_some())
+            .map(|i| i.name.as_str())
+            .collect();
+        println!(
+            "{} {}",
+            info_icon(),
+            highlight().apply_to(summarize_updates(&outdated, &flagged))
+        );
+        pick_updates_numeric(&outdated, unattended, cfg.default_selection)?
+    };
+    selection.extend(pick_replacements(&replacements, unattended)?);
+    selection.extend(pick_rebuilds(&broken, unattended)?);
+    if selection.is_empty() {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to("No packages selected.")
+        );
+        return Ok(());
+    }
+
+    // --unified: repo picks go straight to pacman, before the AUR builds that may need them
+    let (repo_selection, mut selection): (Vec<String>, Vec<String>) = selection
+        .into_iter()
+        .partition(|name| repo_updates.iter().any(|(n, _, _)| n == name));
+    if !repo_selection.is_empty() {
+        if repo_selection.len() < repo_updates.len() {
+            println!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(
+                    "Not every repo update was picked; partial upgrades are unsupported on Arch"
+                )
+            );
+        }
+        pac::install_repo_packages(&repo_selection, cfg.noconfirm)?;
+        let pending: Vec<&str> = selection.iter().map(String::as_str).collect();
+        let broken = find_rebuilds(cfg, &infos, &pending);
+        selection.extend(pick_rebuilds(&broken, unattended)?);
+    }
+    if selection.is_empty() {
+        return Ok(());
+    }
+
+    // Resolve dependencies and build order for selected updates (by package names)
+    let order = aur::resolve_build_order(cfg, &client, &selection, true)?;
+    warn_ignored_pkgs(&order);
+    let temp_path = cfg.temp_dir();
+    if !cfg.reuse_temp {
+        clean_dir_contents(&temp_path)?; // start with a clean temp each run
+    }
+    let mut temp_guard = TempDirGuard::new(cfg, &temp_path);
+
+    // Track failures
+    let mut clone_failed: Vec<String> = vec![]; // track by pkgbase
+    let mut build_failed: Vec<String> = vec![]; // track by pkgbase
+    let mut built_ok: Vec<String> = vec![]; // track by pkgbase
+
+    // Group targets by AUR pkgbase: only clone/build unique pkgbase repos
+    let info_for_order = aur::aur_info_batch(cfg, &client, order.clone())?; // name -> AurInfo
+    let mut seen_base: HashSet<String> = HashSet::new();
+    let mut pkgbases: Vec<String> = vec![];
+    for name in &order {
+        if let Some(info) = info_for_order.get(name) {
+            if seen_base.insert(info.pkgbase.clone()) {
+                pkgbases.push(info.pkgbase.clone());
+            }
+        }
+    }
+
+    // Clone each, continue on error
+    let default_source = AurSource::preferred(cfg);
+    for base in &pkgbases {
+        if interrupt::interrupted() {
+            break;
+        }
+        let spec = AurCloneSpec {
+            pkgbase: base.clone(),
+            source: default_source,
+        };
+        if let Err(e) = clone_aur_pkgs(cfg, std::slice::from_ref(&spec), &temp_path) {
+            let pretty_base = format!("{}", package_name().apply_to(base));
+            eprintln!(
+                "{} {} {}",
+                error_icon(),
+                aur_badge(),
+                error().apply_to(format!("Clone failed for {}: {}", pretty_base, e))
+            );
+            clone_failed.push(base.clone());
+        }
+    }
+
+    if interrupt::interrupted() {
+        return stop_interrupted(cfg, &temp_path, &built_ok);
+    }
 
     // Offer edit
-    let edit = Confirm::new()
-        .with_prompt("Edit PKGBUILDs/source files in file manager before building?")
-        .default(false)
-        .interact()?;
-    if edit {
+    if !unattended {
+        show_pkgbuild_diffs(cfg, &pkgbases, &clone_failed, &temp_path);
+    }
+    if should_edit(cfg, unattended)? {
         open_file_manager(cfg, &temp_path)?;
         // After user returns, regenerate .SRCINFO for all
-        for base in &pkgbases {
-            regen_srcinfo(&temp_path.join(base))?;
+        let dirs: Vec<PathBuf> = pkgbases.iter().map(|b| temp_path.join(b)).collect();
+        regen_srcinfos_if_edited(cfg, &dirs)?;
+    }
+
+    ensure_build_tools(cfg)?;
+    ensure_free_space(cfg, &temp_path)?;
+    let to_build: Vec<String> = pkgbases
+        .iter()
+        .filter(|b| !clone_failed.contains(b))
+        .cloned()
+        .collect();
+    run_pre_build_hook(cfg, &temp_path, &to_build)?;
+    let mut deferred: Vec<String> = vec![];
+    let install_noconfirm =
+        arg_matches.get_flag("noconfirm") || arg_matches.get_flag("noconfirm_install_only");
+    let mut install_failed: Vec<String> = vec![];
+    let mut pending: Vec<String> = vec![]; // built, waiting for the next batch
+    let mut batched: Vec<String> = vec![]; // already went through a batch install
+    let mut batch_built: Vec<(String, String)> = vec![];
+    // AUR deps installed ahead of a build
+    let mut dep_installed: HashSet<String> = HashSet::new();
+    // Verify sources (and import keys) then build
+    for base in &pkgbases {
+        if interrupt::interrupted() {
+            break;
+        }
+        if clone_failed.contains(base) {
+            continue;
+        }
+        if budget.is_some_and(|b| started.elapsed() >= b) {
+            if deferred.is_empty() {
+                println!(
+                    "{} {}",
+                    warn_icon(),
+                    warning().apply_to(format!(
+                        "--keep-going-timeout reached after {} min; installing what is built and skipping the remaining builds",
+                        started.elapsed().as_secs() / 60
+                    ))
+                );
+            }
+            deferred.push(base.clone());
+            continue;
+        }
+        let dir = temp_path.join(base);
+        if let Err(e) = rewrite_sources(cfg, &dir) {
+            eprintln!("{} {}", warn_icon(), warning().apply_to(e.to_string()));
+        }
+        // Try to import valid PGP keys (best effort)
+        let _ = import_validpgpkeys(cfg, &dir);
+        // Verify sources before committing to a long build
+        if let Err(e) = verify_sources(cfg, &dir) {
+            let pretty_base = format!("{}", package_name().apply_to(base));
+            eprintln!(
+                "{} {} {}",
+                warn_icon(),
+                aur_badge(),
+                warning().apply_to(format!(
+                    "Source verification failed for {}: {}",
+                    pretty_base, e
+                ))
+            );
+            build_failed.push(base.clone());
+            continue;
+        }
+        match install_built_aur_deps(
+            cfg,
+            base,
+            &temp_path,
+            &info_for_order,
+            &built_ok,
+            install_noconfirm,
+        )
+        .await
+        {
+            Ok(built) => {
+                dep_installed.extend(built.iter().map(|(name, _)| name.clone()));
+                batch_built.extend(built);
+            }
+            Err(e) => {
+                let pretty_base = format!("{}", package_name().apply_to(base));
+                eprintln!(
+                    "{} {} {}",
+                    error_icon(),
+                    aur_badge(),
+                    error().apply_to(format!(
+                        "Could not install the AUR dependencies of {}: {}",
+                        pretty_base, e
+                    ))
+                );
+                build_failed.push(base.clone());
+                continue;
+            }
+        }
+        match build_with_retry(cfg, base, &dir, unattended) {
+            Ok(()) => {
+                let _ = save_built_pkgbuild(cfg, base, &dir);
+                built_ok.push(base.clone());
+                pending.push(base.clone());
+            }
+            Err(e) => {
+                let pretty_base = format!("{}", package_name().apply_to(base));
+                eprintln!(
+                    "{} {} {}",
+                    error_icon(),
+                    aur_badge(),
+                    error().apply_to(format!("Build failed for {}: {}", pretty_base, e))
+                );
+                build_failed.push(base.clone());
+            }
+        }
+        if batch_size.is_some_and(|n| pending.len() >= n) {
+            // Bases are built in dependency order, so a batch never needs a later one
+            let targets: HashSet<String> = install_targets(&selection, &info_for_order, &built_ok)
+                .into_iter()
+                .filter(|n| !dep_installed.contains(n))
+                .collect();
+            match install_batch(
+                cfg,
+                &temp_path,
+                &pending,
+                &targets,
+                &info_for_order,
+                install_noconfirm,
+            )
+            .await
+            {
+                Ok(built) => batch_built.extend(built),
+                Err(e) => {
+                    eprintln!(
+                        "{} {} {}",
+                        error_icon(),
+                        pacman_badge(),
+                        error().apply_to(format!("Install failed: {}", e))
+                    );
+                    install_failed.extend(pending.iter().cloned());
+                }
+            }
+            batched.append(&mut pending);
         }
     }
 
-    // Verify sources (and import keys) then build
+    if interrupt::interrupted() {
+        let not_installed: Vec<String> = built_ok
+            .iter()
+            .filter(|b| !batched.contains(b))
+            .cloned()
+            .collect();
+        return stop_interrupted(cfg, &temp_path, &not_installed);
+    }
+
+    // Gather artifacts and install with single pacman -U (with or without prompt);
+    // under --batch-install only what the last partial batch left over
+    let desired_pkg_names: HashSet<String> =
+        install_targets(&selection, &info_for_order, &built_ok)
+            .into_iter()
+            .filter(|n| {
+                !dep_installed.contains(n)
+                    && info_for_order
+                        .get(n)
+                        .is_some_and(|i| !batched.contains(&i.pkgbase))
+            })
+            .collect();
+    let mut zsts = if desired_pkg_names.is_empty() {
+        vec![]
+    } else {
+        collect_zsts(&temp_path, Some(&desired_pkg_names), cfg.include_debug)?
+    };
+    if zsts.is_empty() && batched.is_empty() {
+        return Err(anyhow!("No built *.pkg.tar.zst artifacts found."));
+    }
+    let mut built = read_artifacts(&zsts);
+    drop_superseded(&mut zsts, &mut built).await;
+    let mut desired: Vec<String> = desired_pkg_names.iter().cloned().collect();
+    desired.sort();
+    let reasons = pac::install_reasons(&desired).unwrap_or_default();
+    let install_res = if zsts.is_empty() {
+        Ok(())
+    } else if install_noconfirm {
+        pac::sudo_pacman_U_noconfirm(&zsts)
+    } else {
+        pac::sudo_pacman_U(&zsts)
+    };
+    if install_res.is_err() {
+        install_failed.extend(built_ok.iter().filter(|b| !batched.contains(b)).cloned());
+    } else {
+        remember_installed(cfg, &zsts, &built);
+        restore_install_reasons(&reasons);
+    }
+    built.splice(0..0, batch_built);
+    report_version_drift(&built, &info_for_order);
+    if let Err(e) = install_res {
+        eprintln!(
+            "{} {} {}",
+            error_icon(),
+            pacman_badge(),
+            error().apply_to(format!("Install failed: {}", e))
+        );
+    }
+
+    if clone_failed.is_empty()
+        && build_failed.is_empty()
+        && install_failed.is_empty()
+        && deferred.is_empty()
+    {
+        run_post_install_hook(cfg, &installed_names(&built));
+    }
+
+    // Summary
+    if !clone_failed.is_empty()
+        || !build_failed.is_empty()
+        || !install_failed.is_empty()
+        || !deferred.is_empty()
+    {
+        println!("\n{} {}", section_title().apply_to("Summary"), aur_badge());
+        if !clone_failed.is_empty() {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!("Clone failed: {}", clone_failed.join(", ")))
+            );
+        }
+        if !deferred.is_empty() {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!(
+                    "Not built (time budget exceeded): {}",
+                    deferred.join(", ")
+                ))
+            );
+        }
+        if !build_failed.is_empty() {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!("Build failed: {}", build_failed.join(", ")))
+            );
+        }
+        if let Some(low) = low_disk_space(cfg, &temp_path, &build_failed) {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!(
+                    "Build likely failed due to low disk space: {}",
+                    low
+                ))
+            );
+        }
+        if !install_failed.is_empty() {
+            println!(
+                "  {} {}",
+                error_icon(),
+                highlight_value()
+                    .apply_to(format!("Install failed: {}", install_failed.join(", ")))
+            );
+        }
+    }
+    temp_guard.disarm();
+    cleanup_temp(cfg, &temp_path, &build_failed)
+}
+
+/// `pac::query_artifacts`, warning instead of failing: the versions only feed
+/// bookkeeping and reports, which can do without them.
+fn read_artifacts(zsts: &[String]) -> Vec<(String, String)> {
+    pac::query_artifacts(zsts).unwrap_or_else(|e| {
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!("Could not read the built packages: {:#}", e))
+        );
+        vec![]
+    })
+}
+
+/// Drop artifacts whose package is by now installed at a newer version than the
+/// one built, e.g. by another run while this one was building; installing them
+/// would be a downgrade. Checks live state, not the snapshot -Syu started from.
+async fn drop_superseded(zsts: &mut Vec<String>, built: &mut Vec<(String, String)>) {
+    if zsts.len() != built.len() {
+        return;
+    }
+    let Ok(installed) = pac::list_foreign_packages().await else {
+        return;
+    };
+    let mut keep = vec![true; zsts.len()];
+    for (i, (name, version)) in built.iter().enumerate() {
+        let Some(current) = installed.get(name) else {
+            continue;
+        };
+        if pac::vercmp_cached(current, version)
+            .await
+            .is_ok_and(|ord| ord > 0)
+        {
+            keep[i] = false;
+            println!(
+                "{} {} {} {}",
+                info_icon(),
+                dim().apply_to("Skipping, a newer version was installed meanwhile:"),
+                package_name().apply_to(name),
+                current_version().apply_to(current)
+            );
+        }
+    }
+    let (kept_zsts, kept_built): (Vec<String>, Vec<(String, String)>) = zsts
+        .drain(..)
+        .zip(built.drain(..))
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(pair, _)| pair)
+        .unzip();
+    *zsts = kept_zsts;
+    *built = kept_built;
+}
+
+/// makepkg -s only pulls dependencies from the sync repos, so AUR deps of `base`
+/// that were built earlier in this run (e.g. one its update newly introduced) and
+/// are not installed in a version it accepts get installed before it is built:
+/// as deps when new, with their install reason kept when an older version is
+/// there. Returns the installed artifacts as (name, version).
+async fn install_built_aur_deps(
+    cfg: &Config,
+    base: &str,
+    temp_path: &Path,
+    infos: &HashMap<String, aur::AurInfo>,
+    built_ok: &[String],
+    noconfirm: bool,
+) -> Result<Vec<(String, String)>> {
+    let missing = aur_deps_to_install(base, infos, built_ok, pac::deptest)?;
+    if missing.is_empty() {
+        return Ok(vec![]);
+    }
+    let zsts = collect_zsts(temp_path, Some(&missing), cfg.include_debug)?;
+    if zsts.is_empty() {
+        return Ok(vec![]);
+    }
+    let built = read_artifacts(&zsts);
+    let mut names: Vec<&String> = missing.iter().collect();
+    names.sort();
+    println!(
+        "{} {} {}",
+        info_icon(),
+        aur_badge(),
+        highlight().apply_to(format!(
+            "Installing AUR dependencies of {} first: {}",
+            base,
+            names
+                .iter()
+                .map(|n| n.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    );
+    let names: Vec<String> = names.into_iter().cloned().collect();
+    let reasons = pac::install_reasons(&names).unwrap_or_default();
+    pac::sudo_pacman_U_asdeps(&zsts, noconfirm)?;
+    remember_installed(cfg, &zsts, &built);
+    restore_install_reasons(&reasons);
+    Ok(built)
+}
+
+/// Names of the AUR deps of `base` that were built earlier in the run and whose
+/// specs, version constraints included, `deptest` (normally `pacman -T`)
+/// reports as unmet. An older installed version does not satisfy `bar>=2`.
+fn aur_deps_to_install(
+    base: &str,
+    infos: &HashMap<String, aur::AurInfo>,
+    built_ok: &[String],
+    deptest: impl Fn(&[String]) -> Result<Vec<String>>,
+) -> Result<HashSet<String>> {
+    let mut specs: Vec<String> = infos
+        .values()
+        .filter(|info| info.pkgbase == base)
+        .flat_map(aur::dep_specs)
+        .filter(|spec| {
+            infos
+                .get(&aur::strip_version(spec))
+                .is_some_and(|i| i.pkgbase != base && built_ok.contains(&i.pkgbase))
+        })
+        .map(str::to_string)
+        .collect();
+    specs.sort();
+    specs.dedup();
+    if specs.is_empty() {
+        return Ok(HashSet::new());
+    }
+    Ok(deptest(&specs)?
+        .iter()
+        .map(|spec| aur::strip_version(spec))
+        .collect())
+}
+
+/// `--batch-install`: install the targets among the freshly built `bases` right
+/// away, so a long -Syu keeps its progress if a later build fails. Returns the
+/// installed artifacts as (name, version).
+async fn install_batch(
+    cfg: &Config,
+    temp_path: &Path,
+    bases: &[String],
+    targets: &HashSet<String>,
+    infos: &HashMap<String, aur::AurInfo>,
+    noconfirm: bool,
+) -> Result<Vec<(String, String)>> {
+    let names: HashSet<String> = targets
+        .iter()
+        .filter(|n| infos.get(*n).is_some_and(|i| bases.contains(&i.pkgbase)))
+        .cloned()
+        .collect();
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut zsts = collect_zsts(temp_path, Some(&names), cfg.include_debug)?;
+    let mut built = read_artifacts(&zsts);
+    drop_superseded(&mut zsts, &mut built).await;
+    if zsts.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut desired: Vec<String> = names.into_iter().collect();
+    desired.sort();
+    println!(
+        "\n{} {}",
+        info_icon(),
+        highlight().apply_to(format!("Installing batch: {}", desired.join(", ")))
+    );
+    let reasons = pac::install_reasons(&desired).unwrap_or_default();
+    if noconfirm {
+        pac::sudo_pacman_U_noconfirm(&zsts)?;
+    } else {
+        pac::sudo_pacman_U(&zsts)?;
+    }
+    remember_installed(cfg, &zsts, &built);
+    restore_install_reasons(&reasons);
+    Ok(built)
+}
+
+/// Warn up front about AUR packages about to be built that pacman.conf lists in
+/// `IgnorePkg`, since pacman -U will hold them back or ask about them at the end.
+fn warn_ignored_pkgs(names: &[String]) {
+    let patterns = pac::ignored_pkg_patterns();
+    let ignored: Vec<&str> = names
+        .iter()
+        .filter(|name| {
+            patterns
+                .iter()
+                .any(|p| pac::matches_ignore_pattern(p, name))
+        })
+        .map(String::as_str)
+        .collect();
+    if ignored.is_empty() {
+        return;
+    }
+    eprintln!(
+        "{} {}",
+        warn_icon(),
+        warning().apply_to(format!(
+            "In IgnorePkg (pacman.conf), pacman may skip installing them: {}",
+            ignored.join(", ")
+        ))
+    );
+}
+
+/// Package names to install after a build: the requested `roots` plus their AUR
+/// runtime deps, transitively, limited to bases that built. Other packages of a
+/// split base and make/check-only deps are built along the way but not installed.
+fn install_targets(
+    roots: &[String],
+    infos: &HashMap<String, aur::AurInfo>,
+    built_ok: &[String],
+) -> HashSet<String> {
+    let mut out: HashSet<String> = HashSet::new();
+    let mut queue: Vec<&str> = roots.iter().map(String::as_str).collect();
+    while let Some(name) = queue.pop() {
+        let Some(info) = infos.get(name) else {
+            continue;
+        };
+        if !built_ok.contains(&info.pkgbase) || !out.insert(name.to_string()) {
+            continue;
+        }
+        for dep in aur::runtime_dep_names(info) {
+            if let Some((dep, _)) = infos.get_key_value(dep.as_str()) {
+                queue.push(dep);
+            }
+        }
+    }
+    out
+}
+
+/// After failed builds, whether the build dir is now short of `min_free_mb`.
+fn low_disk_space(cfg: &Config, temp_path: &Path, build_failed: &[String]) -> Option<String> {
+    let min_mb = cfg.min_free_mb.unwrap_or(DEFAULT_MIN_FREE_MB);
+    if build_failed.is_empty() || min_mb == 0 {
+        return None;
+    }
+    check_free_space(temp_path, min_mb << 20)
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// `makepkg_build`, recording how long it took in the build history.
+fn timed_build(cfg: &Config, base: &str, dir: &Path) -> Result<()> {
+    let started = Instant::now();
+    let res = makepkg_build(cfg, dir);
+    let secs = started.elapsed().as_secs();
+    if let Err(e) = state::record_build(cfg, base, secs, res.is_ok()) {
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!("Could not record build history: {}", e))
+        );
+    }
+    res
+}
+
+/// How many times a failed build may be edited and retried before it is given up.
+const BUILD_RETRIES: usize = 3;
+
+/// Build `dir`; after a failure, offer to fix the PKGBUILD in the editor and
+/// rebuild straight away. Never prompts when `unattended`.
+fn build_with_retry(cfg: &Config, base: &str, dir: &Path, unattended: bool) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        let err = match timed_build(cfg, base, dir) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if unattended || retries >= BUILD_RETRIES || interrupt::interrupted() {
+            return Err(err);
+        }
+        let retry = confirm(
+            format!(
+                "Build of {} failed. Edit the PKGBUILD and retry ({}/{})?",
+                base,
+                retries + 1,
+                BUILD_RETRIES
+            ),
+            false,
+        )?;
+        if !retry {
+            return Err(err);
+        }
+        retries += 1;
+        open_editor(cfg, &dir.join("PKGBUILD"))?;
+        regen_srcinfo_if_edited(cfg, dir)?;
+    }
+}
+
+/// Offer to move renamed AUR packages to their replacement. The replacement is
+/// built like any other update; pacman -U then asks to remove the old package
+/// through the replacement's conflicts=(). Unattended runs only report them.
+fn pick_replacements(
+    replacements: &BTreeMap<String, String>,
+    unattended: bool,
+) -> Result<Vec<String>> {
+    let mut out = vec![];
+    for (old, new) in replacements {
+        println!(
+            "{} {} {}",
+            info_icon(),
+            aur_badge(),
+            highlight().apply_to(format!(
+                "{} is no longer in the AUR; it has been replaced by {}",
+                package_name().apply_to(old),
+                package_name().apply_to(new)
+            ))
+        );
+        if unattended {
+            continue;
+        }
+        if confirm(format!("Migrate {} to {}?", old, new), false)? {
+            out.push(new.clone());
+        }
+    }
+    Ok(out)
+}
+
+/// AUR packages left with a missing soname, except those in `pending` (they
+/// get rebuilt anyway).
+fn find_rebuilds(
+    cfg: &Config,
+    infos: &HashMap<String, aur::AurInfo>,
+    pending: &[&str],
+) -> Vec<String> {
+    soname::find_broken_foreign(cfg)
+        .unwrap_or_else(|e| {
+            eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+            vec![]
+        })
+        .into_iter()
+        .filter(|name| infos.contains_key(name) && !pending.contains(&name.as_str()))
+        .collect()
+}
+
+/// Offer to rebuild foreign packages with unresolved shared libraries. One prompt
+/// covers all of them; unattended runs take them all.
+fn pick_rebuilds(broken: &[String], unattended: bool) -> Result<Vec<String>> {
+    if broken.is_empty() {
+        return Ok(vec![]);
+    }
+    println!(
+        "{} {} {}",
+        warn_icon(),
+        aur_badge(),
+        warning().apply_to(format!(
+            "{} package(s) link against shared libraries that are no longer installed:",
+            broken.len()
+        ))
+    );
+    for name in broken {
+        println!("  {} {}", bullet(), package_name().apply_to(name));
+    }
+    if unattended || confirm("Rebuild them against the upgraded libraries?", true)? {
+        Ok(broken.to_vec())
+    } else {
+        Ok(vec![])
+    }
+}
+
+fn print_config(cfg: &Config) {
+    println!("{}", section_title().apply_to("Effective configuration"));
+    for (name, value) in cfg.settings() {
+        let origin = cfg.origins.get(name).copied().unwrap_or("default");
+        println!(
+            "  {} {:<24} {:<40} {}",
+            bullet(),
+            highlight().apply_to(name),
+            if value.is_empty() {
+                dim().apply_to(String::from("(none)"))
+            } else {
+                highlight_value().apply_to(value)
+            },
+            dim().apply_to(format!("[{}]", origin))
+        );
+    }
+}
+
+/// Keyring maintenance for widespread signature failures: `--populate` restores
+/// the keys shipped in archlinux-keyring, `--refresh-keys` updates expiries and
+/// revocations from the keyservers (slow, and may partially fail).
+fn handle_refresh_keys() -> Result<()> {
+    let mut failed = vec![];
+    for step in [["--populate"], ["--refresh-keys"]] {
+        if !pac::sudo_pacman_key(&step)? {
+            failed.push(step[0]);
+        }
+    }
+    if failed.is_empty() {
+        println!(
+            "{} {}",
+            success_icon(),
+            highlight().apply_to("pacman keyring populated and refreshed")
+        );
+        Ok(())
+    } else {
+        Err(anyhow!("pacman-key {} failed", failed.join(", ")))
+    }
+}
+
+fn handle_recv_keys(cfg: &Config, keys: &[&str]) -> Result<()> {
+    println!(
+        "{} Importing PGP key(s) {}",
+        info_icon(),
+        highlight().apply_to(keys.join(", "))
+    );
+    let server = recv_keys(cfg, keys)?;
+    println!(
+        "{} {} {}",
+        success_icon(),
+        highlight().apply_to("Imported from"),
+        path().apply_to(server)
+    );
+    Ok(())
+}
+
+/// What a --deps-only run put on the system.
+fn report_deps_installed(repo_deps: &[String], built: &[(String, String)]) {
+    println!(
+        "\n{} {}",
+        section_title().apply_to("Dependencies installed"),
+        dim().apply_to("(--deps-only)")
+    );
+    if repo_deps.is_empty() && built.is_empty() {
+        println!(
+            "  {} {}",
+            info_icon(),
+            dim().apply_to("Nothing to install.")
+        );
+    }
+    for dep in repo_deps {
+        println!(
+            "  {} {} {}",
+            bullet(),
+            package_name().apply_to(dep),
+            pacman_badge()
+        );
+    }
+    for (name, version) in built {
+        println!(
+            "  {} {} {} {}",
+            bullet(),
+            package_name().apply_to(name),
+            new_version().apply_to(version),
+            aur_badge()
+        );
+    }
+}
+
+/// End-of-run temp cleanup. Nothing is removed when the tree is being kept for
+/// reuse; with `keep_failed_builds`, failed pkgbase trees survive for inspection.
+fn cleanup_temp(cfg: &Config, temp_path: &Path, build_failed: &[String]) -> Result<()> {
+    if cfg.reuse_temp {
+        return Ok(());
+    }
+    if !cfg.keep_failed_builds || build_failed.is_empty() {
+        return clean_dir_contents(temp_path);
+    }
+    clean_dir_except(temp_path, build_failed)?;
+    println!(
+        "{} {}",
+        info_icon(),
+        highlight().apply_to("Kept failed build trees for inspection:")
+    );
+    for base in build_failed {
+        println!(
+            "  {} {}",
+            bullet(),
+            path().apply_to(temp_path.join(base).display())
+        );
+    }
+    Ok(())
+}
+
+/// Outcome of cloning AUR targets and running `makepkg --verifysource` on them.
+struct SourceCheck {
+    temp_path: PathBuf,
+    verified: Vec<String>, // pkgbases whose sources downloaded and verified
+    failed: Vec<(String, String)>, // pkgbase -> error
+    unfound: Vec<String>,
+}
+
+/// Clone the pkgbases of `aur_requests` (plus their AUR deps with `with_deps`)
+/// into the temp tree and fetch and verify their sources. Nothing is built.
+fn clone_and_verify(
+    cfg: &Config,
+    aur_requests: &[AurRequest],
+    with_deps: bool,
+) -> Result<SourceCheck> {
+    let client = aur::http_client(cfg)?;
+    let names: Vec<String> = aur_requests.iter().map(|req| req.name.clone()).collect();
+    let order = if with_deps {
+        aur::resolve_build_order(cfg, &client, &names, true)?
+    } else {
+        names.clone()
+    };
+    let infos = aur::aur_info_batch(cfg, &client, order.clone())?;
+    let mut pkgbases: Vec<String> = vec![];
+    for name in &order {
+        if let Some(info) = infos.get(name) {
+            if !pkgbases.contains(&info.pkgbase) {
+                pkgbases.push(info.pkgbase.clone());
+            }
+        }
+    }
+    let unfound: Vec<String> = names
+        .iter()
+        .filter(|n| !infos.contains_key(*n))
+        .cloned()
+        .collect();
+
+    let temp_path = cfg.temp_dir();
+    if !cfg.reuse_temp {
+        clean_dir_contents(&temp_path)?;
+    }
+    let default_source = AurSource::preferred(cfg);
+    let mut verified: Vec<String> = vec![];
+    let mut failed: Vec<(String, String)> = vec![];
     for base in &pkgbases {
-        if clone_failed.contains(base) {
-            continue;
+        if interrupt::interrupted() {
+            break;
         }
+        let source = aur_requests
+            .iter()
+            .find(|req| infos.get(&req.name).is_some_and(|i| &i.pkgbase == base))
+            .map(|req| req.source)
+            .unwrap_or(default_source);
+        let spec = AurCloneSpec {
+            pkgbase: base.clone(),
+            source,
+        };
         let dir = temp_path.join(base);
-        // Try to import valid PGP keys (best effort)
-        let _ = import_validpgpkeys(&dir);
-        // Verify sources before committing to a long build
-        if let Err(e) = verify_sources(&dir) {
-            let pretty_base = format!("{}", package_name().apply_to(base));
-            eprintln!(
-                "{} {} {}",
-                warn_icon(),
-                aur_badge(),
-                warning().apply_to(format!(
-                    "Source verification failed for {}: {}",
-                    pretty_base, e
-                ))
-            );
-            build_failed.push(base.clone());
-            continue;
-        }
-        match makepkg_build(&dir) {
-            Ok(()) => built_ok.push(base.clone()),
+        let res = clone_aur_pkgs(cfg, std::slice::from_ref(&spec), &temp_path).and_then(|_| {
+            let _ = import_validpgpkeys(cfg, &dir);
+            verify_sources(cfg, &dir)
+        });
+        match res {
+            Ok(()) => verified.push(base.clone()),
             Err(e) => {
-                let pretty_base = format!("{}", package_name().apply_to(base));
                 eprintln!(
                     "{} {} {}",
                     error_icon(),
                     aur_badge(),
-                    error().apply_to(format!("Build failed for {}: {}", pretty_base, e))
+                    error().apply_to(format!(
+                        "Source check failed for {}: {}",
+                        package_name().apply_to(base),
+                        e
+                    ))
                 );
-                build_failed.push(base.clone());
+                failed.push((base.clone(), e.to_string()));
             }
         }
     }
+    Ok(SourceCheck {
+        temp_path,
+        verified,
+        failed,
+        unfound,
+    })
+}
 
-    // Gather artifacts and install with single pacman -U (with or without prompt)
-    let built_ok_bases: HashSet<String> = built_ok.iter().cloned().collect();
-    let desired_pkg_names: HashSet<String> = order
-        .iter()
-        .filter_map(|name| {
-            info_for_order.get(name).and_then(|info| {
-                if built_ok_bases.contains(&info.pkgbase) {
-                    Some(name.clone())
-                } else {
-                    None
-                }
-            })
-        })
-        .collect();
-    let zsts = collect_zsts(&temp_path, Some(&desired_pkg_names))?;
-    if zsts.is_empty() {
-        return Err(anyhow!("No built *.pkg.tar.zst artifacts found."));
+/// `--verify-only`: clone the given AUR packages and check their sources
+/// (download, checksums, signatures), report pass/fail per package, and stop.
+/// The fetched sources stay in the temp tree for a later offline build.
+fn handle_verify_only(cfg: &Config, pkgs: &[String]) -> Result<()> {
+    let (repo, aur_requests) = classify_sync_targets(cfg, pkgs, false)?;
+    let repo: Vec<&String> = repo.iter().filter(|r| !r.starts_with('-')).collect();
+    if !repo.is_empty() {
+        eprintln!(
+            "{} {}",
+            warn_icon(),
+            warning().apply_to(format!(
+                "Not AUR packages, nothing to verify: {}",
+                repo.iter()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        );
     }
-    let mut install_failed: Vec<String> = vec![];
-    let install_res = if arg_matches.get_flag("noconfirm") {
-        pac::sudo_pacman_U_noconfirm(&zsts)
-    } else {
-        pac::sudo_pacman_U(&zsts)
-    };
-    if install_res.is_err() {
-        install_failed = built_ok.clone();
+    if aur_requests.is_empty() {
+        return Ok(());
     }
-    if let Err(e) = install_res {
-        eprintln!(
-            "{} {} {}",
+    let check = clone_and_verify(cfg, &aur_requests, false)?;
+
+    println!(
+        "\n{} {}",
+        section_title().apply_to("Source verification"),
+        aur_badge()
+    );
+    for base in &check.verified {
+        println!(
+            "  {} {:<32} {}",
+            success_icon(),
+            package_name().apply_to(base),
+            success().apply_to("ok")
+        );
+    }
+    for (base, err) in &check.failed {
+        println!(
+            "  {} {:<32} {}",
             error_icon(),
-            pacman_badge(),
-            error().apply_to(format!("Install failed: {}", e))
+            package_name().apply_to(base),
+            error().apply_to(err)
+        );
+    }
+    for name in &check.unfound {
+        println!(
+            "  {} {:<32} {}",
+            warn_icon(),
+            package_name().apply_to(name),
+            dim().apply_to("not found in the AUR")
         );
     }
+    if !check.failed.is_empty() || !check.unfound.is_empty() {
+        return Err(anyhow!(
+            "Source verification failed for {} package(s)",
+            check.failed.len() + check.unfound.len()
+        ));
+    }
+    Ok(())
+}
 
-    // Summary
-    if !clone_failed.is_empty() || !build_failed.is_empty() || !install_failed.is_empty() {
-        println!("\n{} {}", section_title().apply_to("Summary"), aur_badge());
-        if !clone_failed.is_empty() {
-            println!(
-                "  {} {}",
-                warn_icon(),
-                highlight().apply_to(format!("Clone failed: {}", clone_failed.join(", ")))
-            );
-        }
-        if !build_failed.is_empty() {
-            println!(
-                "  {} {}",
-                warn_icon(),
-                highlight().apply_to(format!("Build failed: {}", build_failed.join(", ")))
-            );
-        }
-        if !install_failed.is_empty() {
-            println!(
-                "  {} {}",
-                error_icon(),
-                highlight_value()
-                    .apply_to(format!("Install failed: {}", install_failed.join(", ")))
-            );
+/// `-S --print`: print the commands `-S` would run as a script, without running any.
+fn handle_print_commands(cfg: &Config, pkgs: &[String]) -> Result<()> {
+    if pkgs.is_empty() {
+        return Err(anyhow!("No packages specified."));
+    }
+    let (repo, aur_requests) = classify_sync_targets(cfg, pkgs, false)?;
+    let noconfirm: Vec<String> = if cfg.noconfirm {
+        vec![String::from("--noconfirm")]
+    } else {
+        vec![]
+    };
+    println!("#!/bin/sh\nset -e");
+    if !repo.is_empty() {
+        let mut args = vec![String::from("-S")];
+        args.extend(noconfirm.iter().cloned());
+        args.extend(repo);
+        println!("{}", pac::sudo_pacman_command_line(&args));
+    }
+    if aur_requests.is_empty() {
+        return Ok(());
+    }
+
+    let client = aur::http_client(cfg)?;
+    let names: Vec<String> = aur_requests.iter().map(|req| req.name.clone()).collect();
+    let order = aur::resolve_build_order(cfg, &client, &names, true)?;
+    let infos = aur::aur_info_batch(cfg, &client, order.clone())?;
+    let mut pkgbases: Vec<String> = vec![];
+    for name in &order {
+        if let Some(info) = infos.get(name) {
+            if !pkgbases.contains(&info.pkgbase) {
+                pkgbases.push(info.pkgbase.clone());
+            }
         }
     }
-    // Clean temp after completion
-    clean_dir_contents(&temp_path)?;
+    let unfound: Vec<&str> = names
+        .iter()
+        .filter(|n| !infos.contains_key(*n))
+        .map(String::as_str)
+        .collect();
+    if !unfound.is_empty() {
+        println!("# not found in the AUR: {}", unfound.join(" "));
+    }
+
+    let temp_path = cfg.temp_dir();
+    let default_source = AurSource::preferred(cfg);
+    for base in &pkgbases {
+        let source = aur_requests
+            .iter()
+            .find(|req| infos.get(&req.name).is_some_and(|i| &i.pkgbase == base))
+            .map(|req| req.source)
+            .unwrap_or(default_source);
+        let spec = AurCloneSpec {
+            pkgbase: base.clone(),
+            source,
+        };
+        println!("{}", clone_command_line(cfg, &spec, &temp_path));
+        println!("{}", makepkg_command_line(cfg, &temp_path.join(base)));
+    }
+
+    let mut targets: Vec<String> = install_targets(&names, &infos, &pkgbases)
+        .into_iter()
+        .collect();
+    targets.sort();
+    let mut args = vec![String::from("-U")];
+    args.extend(noconfirm);
+    let mut line = pac::sudo_pacman_command_line(&args);
+    for name in &targets {
+        let info = &infos[name];
+        let dir = temp_path.join(&info.pkgbase);
+        line.push_str(&format!(
+            " {}/{}-{}-*.pkg.tar.zst",
+            shell_quote(&dir.to_string_lossy()),
+            shell_quote(name),
+            shell_quote(&info.version)
+        ));
+    }
+    println!("{}", line);
+    Ok(())
+}
+
+/// `-Sw`: repo targets go to `pacman -Sw`; AUR targets (and their AUR deps) are
+/// cloned and `makepkg --verifysource` fetches their sources. Nothing is built,
+/// installed or cleaned up afterwards.
+fn handle_download_only(
+    cfg: &Config,
+    pkgs: &[String],
+    arg_matches: &clap::ArgMatches,
+) -> Result<()> {
+    if pkgs.is_empty() {
+        return Err(anyhow!("No packages specified."));
+    }
+    let (repo, aur_requests) =
+        classify_sync_targets(cfg, pkgs, arg_matches.get_flag("choose_repo"))?;
+    if !repo.is_empty() {
+        let mut args = vec![String::from("-w")];
+        args.extend(repo);
+        pac::install_repo_packages(&args, cfg.noconfirm)?;
+    }
+    if aur_requests.is_empty() {
+        return Ok(());
+    }
+
+    let SourceCheck {
+        temp_path,
+        verified: fetched,
+        failed,
+        unfound,
+    } = clone_and_verify(cfg, &aur_requests, true)?;
+
+    println!(
+        "\n{} {}",
+        section_title().apply_to("Downloaded"),
+        aur_badge()
+    );
+    for base in &fetched {
+        println!(
+            "  {} {:<32} {}",
+            bullet(),
+            package_name().apply_to(base),
+            path().apply_to(temp_path.join(base).display())
+        );
+    }
+    if !unfound.is_empty() {
+        println!(
+            "  {} {}",
+            warn_icon(),
+            highlight().apply_to(format!("Unfound: {}", unfound.join(", ")))
+        );
+    }
+    if !failed.is_empty() {
+        println!(
+            "  {} {}",
+            warn_icon(),
+            highlight().apply_to(format!(
+                "Failed: {}",
+                failed
+                    .iter()
+                    .map(|(base, _)| base.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        );
+    }
+    if !fetched.is_empty() {
+        let note = if cfg.per_run_temp {
+            "Build later with turbo --local <dir>; once this run has exited, the next turbo run removes this tree unless reuse_temp is set."
+        } else {
+            "Build later with turbo --local <dir>; the next -S/-Syu wipes this tree unless reuse_temp is set."
+        };
+        println!("{} {}", info_icon(), dim().apply_to(note));
+    }
     Ok(())
 }
 
+/// pacman options whose value is the next argument rather than a target.
+const PACMAN_VALUE_FLAGS: &[&str] = &[
+    "--ignore",
+    "--ignoregroup",
+    "--assume-installed",
+    "--overwrite",
+    "--print-format",
+    "--arch",
+    "--cachedir",
+    "--color",
+    "--config",
+    "--dbpath",
+    "-b",
+    "--gpgdir",
+    "--hookdir",
+    "--logfile",
+    "--root",
+    "-r",
+    "--sysroot",
+];
+
+/// `-S foo foo bar` handles foo once; first-seen order is kept. Flags and the
+/// values of value-taking flags are left as they are (--ignore a --ignore a).
+fn dedup_targets(pkgs: &[String]) -> Vec<String> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut out = vec![];
+    let mut is_value = false;
+    for p in pkgs {
+        if is_value || p.starts_with('-') || seen.insert(p.as_str()) {
+            out.push(p.clone());
+        }
+        is_value = !is_value && PACMAN_VALUE_FLAGS.contains(&p.as_str());
+    }
+    out
+}
+
 fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) -> Result<()> {
     if pkgs.is_empty() {
         return Err(anyhow!("No packages specified. Did you mean -Syu?"));
     }
+    let pkgs = dedup_targets(pkgs);
     // Determine which are repo vs AUR (with optional repo prefixes)
-    let (repo, aur_requests) = classify_sync_targets(cfg, pkgs)?;
+    let (mut repo, mut aur_requests) =
+        classify_sync_targets(cfg, &pkgs, arg_matches.get_flag("choose_repo"))?;
+    // `foo` and `aur/foo` are the same request
+    let mut seen_aur: HashSet<String> = HashSet::new();
+    aur_requests.retain(|req| seen_aur.insert(req.name.clone()));
+    if arg_matches.get_flag("only_repo") && !aur_requests.is_empty() {
+        let skipped: Vec<&str> = aur_requests.iter().map(|r| r.display.as_str()).collect();
+        println!(
+            "{} {} {}",
+            info_icon(),
+            aur_badge(),
+            dim().apply_to(format!(
+                "Skipping AUR targets (--only-repo): {}",
+                skipped.join(", ")
+            ))
+        );
+        aur_requests.clear();
+    }
+    if arg_matches.get_flag("only_aur") {
+        let skipped: Vec<&str> = repo
+            .iter()
+            .filter(|r| !r.starts_with('-'))
+            .map(String::as_str)
+            .collect();
+        if !skipped.is_empty() {
+            println!(
+                "{} {} {}",
+                info_icon(),
+                pacman_badge(),
+                dim().apply_to(format!(
+                    "Skipping repo targets (--only-aur): {}",
+                    skipped.join(", ")
+                ))
+            );
+        }
+        repo.clear();
+    }
     let repo_noconfirm = arg_matches.get_flag("noconfirm");
-    if !repo.is_empty() {
-        pac::install_repo_packages(&repo, repo_noconfirm)?;
+    let deps_only = arg_matches.get_flag("deps_only");
+    if deps_only {
+        let skipped: Vec<&String> = repo.iter().filter(|r| !r.starts_with('-')).collect();
+        if !skipped.is_empty() {
+            eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!(
+                    "--deps-only applies to AUR targets; not installing repo targets: {}",
+                    skipped
+                        .iter()
+                        .map(|s| s.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            );
+        }
+    } else if !repo.is_empty() {
+        let current = pac::up_to_date_repo_targets(&repo).unwrap_or_default();
+        if !current.is_empty() {
+            println!(
+                "{} {} {}",
+                info_icon(),
+                pacman_badge(),
+                dim().apply_to(format!(
+                    "Repo packages already installed and up to date: {}",
+                    current.join(", ")
+                ))
+            );
+        }
+        let to_install: Vec<String> = repo.into_iter().filter(|r| !current.contains(r)).collect();
+        if to_install.iter().any(|r| !r.starts_with('-')) {
+            pac::install_repo_packages(&to_install, repo_noconfirm)?;
+        }
     }
 
     if aur_requests.is_empty() {
         return Ok(());
     }
 
-    let client = Client::builder().user_agent("aurwrap/0.1").build()?;
+    check_sync_db_age(cfg)?;
+
+    let client = aur::http_client(cfg)?;
     let requested_names: Vec<String> = aur_requests.iter().map(|req| req.name.clone()).collect();
-    // Determine AUR availability up-front to report unfound
-    let info_map = aur::aur_info_batch(cfg, &client, requested_names)?;
+    // Determine AUR availability up-front to report unfound. A mirror error is
+    // not proof that a package doesn't exist, so those are reported on their own.
+    let lookup = aur::aur_info_lookup(cfg, &client, requested_names)?;
+    let info_map = lookup.infos;
     let unfound: Vec<String> = aur_requests
         .iter()
-        .filter(|req| !info_map.contains_key(&req.name))
+        .filter(|req| !info_map.contains_key(&req.name) && !lookup.failed.contains_key(&req.name))
+        .map(|req| req.display.clone())
+        .collect();
+    let fetch_failed: Vec<String> = aur_requests
+        .iter()
+        .filter(|req| lookup.failed.contains_key(&req.name))
         .map(|req| req.display.clone())
         .collect();
+    for (name, err) in &lookup.failed {
+        eprintln!(
+            "{} {} {}",
+            warn_icon(),
+            github_aur_mirror_badge(),
+            warning().apply_to(format!(
+                "Could not fetch metadata for {}: {}",
+                package_name().apply_to(name),
+                err
+            ))
+        );
+    }
     let available: Vec<String> = aur_requests
         .iter()
         .filter(|req| info_map.contains_key(&req.name))
         .map(|req| req.name.clone())
         .collect();
+    if available.is_empty() {
+        let mut reasons = vec![];
+        if !unfound.is_empty() {
+            reasons.push(format!("not in the AUR: {}", unfound.join(", ")));
+        }
+        if !fetch_failed.is_empty() {
+            reasons.push(format!(
+                "metadata fetch failed: {}",
+                fetch_failed.join(", ")
+            ));
+        }
+        return Err(anyhow!("Nothing to build ({})", reasons.join("; ")));
+    }
 
-    let build_order = aur::resolve_build_order(cfg, &client, &available)?;
+    let mut build_order = aur::resolve_build_order(cfg, &client, &available, true)?;
+    warn_ignored_pkgs(&build_order);
+    let mut repo_deps: Vec<String> = vec![];
+    if deps_only {
+        // The roots are never built; their repo deps have to be installed here since
+        // makepkg -s only pulls in deps for what it actually builds.
+        repo_deps = available
+            .iter()
+            .filter_map(|name| info_map.get(name))
+            .flat_map(aur::resolve_dep_names)
+            .filter(|dep| !build_order.contains(dep))
+            .collect();
+        repo_deps.sort();
+        repo_deps.dedup();
+        // AUR deps that are already installed were left out of build_order; they
+        // must not be handed to pacman -S either
+        repo_deps = pac::deptest(&repo_deps)?;
+        if !repo_deps.is_empty() {
+            let mut args = vec![String::from("--needed"), String::from("--asdeps")];
+            args.extend(repo_deps.iter().cloned());
+            pac::install_repo_packages(&args, repo_noconfirm)?;
+        }
+        build_order.retain(|name| !available.contains(name));
+        if build_order.is_empty() {
+            report_deps_installed(&repo_deps, &[]);
+            return Ok(());
+        }
+    }
     let temp_path = cfg.temp_dir();
-    clean_dir_contents(&temp_path)?;
+    if !cfg.reuse_temp {
+        clean_dir_contents(&temp_path)?;
+    }
+    let mut temp_guard = TempDirGuard::new(cfg, &temp_path);
     // Track failures by pkgbase
     let mut clone_failed: Vec<String> = vec![];
     let mut build_failed: Vec<String> = vec![];
@@ -598,8 +3265,11 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
     }
 
     // Clone each base, continue on error
-    let default_source = AurSource::from_cfg(cfg);
+    let default_source = AurSource::preferred(cfg);
     for base in &pkgbases {
+        if interrupt::interrupted() {
+            break;
+        }
         let source = pkgbase_sources.get(base).copied().unwrap_or(default_source);
         let spec = AurCloneSpec {
             pkgbase: base.clone(),
@@ -621,26 +3291,40 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
         }
     }
 
+    if interrupt::interrupted() {
+        return stop_interrupted(cfg, &temp_path, &built_ok);
+    }
+
     // Prompt edit
-    let edit = Confirm::new()
-        .with_prompt("Edit PKGBUILDs/source files in file manager before building?")
-        .default(false)
-        .interact()?;
-    if edit {
+    show_pkgbuild_diffs(cfg, &pkgbases, &clone_failed, &temp_path);
+    if should_edit(cfg, false)? {
         open_file_manager(cfg, &temp_path)?;
-        for base in &pkgbases {
-            regen_srcinfo(&temp_path.join(base))?;
-        }
+        let dirs: Vec<PathBuf> = pkgbases.iter().map(|b| temp_path.join(b)).collect();
+        regen_srcinfos_if_edited(cfg, &dirs)?;
     }
 
+    ensure_build_tools(cfg)?;
+    ensure_free_space(cfg, &temp_path)?;
+    let to_build: Vec<String> = pkgbases
+        .iter()
+        .filter(|b| !clone_failed.contains(b))
+        .cloned()
+        .collect();
+    run_pre_build_hook(cfg, &temp_path, &to_build)?;
     // Verify sources then build each in order
     for base in &pkgbases {
+        if interrupt::interrupted() {
+            break;
+        }
         if clone_failed.contains(base) {
             continue;
         }
         let dir = temp_path.join(base);
-        let _ = import_validpgpkeys(&dir);
-        if let Err(e) = verify_sources(&dir) {
+        if let Err(e) = rewrite_sources(cfg, &dir) {
+            eprintln!("{} {}", warn_icon(), warning().apply_to(e.to_string()));
+        }
+        let _ = import_validpgpkeys(cfg, &dir);
+        if let Err(e) = verify_sources(cfg, &dir) {
             let source = pkgbase_sources.get(base).copied().unwrap_or(default_source);
             let badge = match source {
                 AurSource::Github => github_aur_mirror_badge(),
@@ -659,8 +3343,11 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
             build_failed.push(base.clone());
             continue;
         }
-        match makepkg_build(&dir) {
-            Ok(()) => built_ok.push(base.clone()),
+        match build_with_retry(cfg, base, &dir, cfg.noconfirm) {
+            Ok(()) => {
+                let _ = save_built_pkgbuild(cfg, base, &dir);
+                built_ok.push(base.clone())
+            }
             Err(e) => {
                 let source = pkgbase_sources.get(base).copied().unwrap_or(default_source);
                 let badge = match source {
@@ -679,34 +3366,38 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
         }
     }
 
+    if interrupt::interrupted() {
+        return stop_interrupted(cfg, &temp_path, &built_ok);
+    }
+
     // Collect .zst paths
-    let built_ok_bases: HashSet<String> = built_ok.iter().cloned().collect();
-    let desired_pkg_names: HashSet<String> = build_order
-        .iter()
-        .filter_map(|name| {
-            info_for_order.get(name).and_then(|info| {
-                if built_ok_bases.contains(&info.pkgbase) {
-                    Some(name.clone())
-                } else {
-                    None
-                }
-            })
-        })
-        .collect();
-    let zsts = collect_zsts(&temp_path, Some(&desired_pkg_names))?;
+    // Under --deps-only the roots were never built; everything built is a dep
+    let roots = if deps_only { &build_order } else { &available };
+    let desired_pkg_names = install_targets(roots, &info_for_order, &built_ok);
+    let zsts = collect_zsts(&temp_path, Some(&desired_pkg_names), cfg.include_debug)?;
     if zsts.is_empty() {
         return Err(anyhow!("No built *.pkg.tar.zst artifacts found."));
     }
+    let built = read_artifacts(&zsts);
+    report_version_drift(&built, &info_for_order);
 
     // Install built AUR files
     let mut install_failed: Vec<String> = vec![];
-    let install_res = if repo_noconfirm {
+    let install_noconfirm = repo_noconfirm || arg_matches.get_flag("noconfirm_install_only");
+    let install_res = if deps_only {
+        pac::sudo_pacman_U_asdeps(&zsts, install_noconfirm)
+    } else if install_noconfirm {
         pac::sudo_pacman_U_noconfirm(&zsts)
     } else {
         pac::sudo_pacman_U(&zsts)
     };
     if install_res.is_err() {
         install_failed = built_ok.clone();
+    } else {
+        remember_installed(cfg, &zsts, &built);
+        if deps_only {
+            report_deps_installed(&repo_deps, &built);
+        }
     }
     if let Err(e) = install_res {
         eprintln!(
@@ -717,8 +3408,18 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
         );
     }
 
+    if unfound.is_empty()
+        && fetch_failed.is_empty()
+        && clone_failed.is_empty()
+        && build_failed.is_empty()
+        && install_failed.is_empty()
+    {
+        run_post_install_hook(cfg, &installed_names(&built));
+    }
+
     // Summary
     if !unfound.is_empty()
+        || !fetch_failed.is_empty()
         || !clone_failed.is_empty()
         || !build_failed.is_empty()
         || !install_failed.is_empty()
@@ -731,6 +3432,16 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
                 highlight().apply_to(format!("Unfound: {}", unfound.join(", ")))
             );
         }
+        if !fetch_failed.is_empty() {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!(
+                    "Metadata fetch failed (try again): {}",
+                    fetch_failed.join(", ")
+                ))
+            );
+        }
         if !clone_failed.is_empty() {
             println!(
                 "  {} {}",
@@ -745,6 +3456,16 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
                 highlight().apply_to(format!("Build failed: {}", build_failed.join(", ")))
             );
         }
+        if let Some(low) = low_disk_space(cfg, &temp_path, &build_failed) {
+            println!(
+                "  {} {}",
+                warn_icon(),
+                highlight().apply_to(format!(
+                    "Build likely failed due to low disk space: {}",
+                    low
+                ))
+            );
+        }
         if !install_failed.is_empty() {
             println!(
                 "  {} {}",
@@ -754,7 +3475,110 @@ fn handle_sync(cfg: &Config, pkgs: &[String], arg_matches: &clap::ArgMatches) ->
             );
         }
     }
-    // Clean temp after completion
-    clean_dir_contents(&temp_path)?;
-    Ok(())
+    temp_guard.disarm();
+    cleanup_temp(cfg, &temp_path, &build_failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reasons(pairs: &[(&str, bool)]) -> HashMap<String, bool> {
+        pairs.iter().map(|(n, d)| (n.to_string(), *d)).collect()
+    }
+
+    #[test]
+    fn restores_only_flipped_reasons() {
+        let before = reasons(&[
+            ("dep", true),
+            ("app", false),
+            ("lib", true),
+            ("tool", false),
+        ]);
+        // dep turned explicit, app turned into a dep; lib and tool kept theirs
+        let after = reasons(&[
+            ("dep", false),
+            ("app", true),
+            ("lib", true),
+            ("tool", false),
+        ]);
+        assert_eq!(reasons_to_restore(&before, &after, true), ["dep"]);
+        assert_eq!(reasons_to_restore(&before, &after, false), ["app"]);
+    }
+
+    #[test]
+    fn skips_packages_no_longer_installed() {
+        let before = reasons(&[("gone", true)]);
+        assert!(reasons_to_restore(&before, &HashMap::new(), true).is_empty());
+    }
+
+    fn info(name: &str, pkgbase: &str, depends: &[&str]) -> aur::AurInfo {
+        aur::AurInfo {
+            name: name.to_string(),
+            pkgbase: pkgbase.to_string(),
+            version: String::from("1.0-1"),
+            description: None,
+            url: None,
+            depends: Some(depends.iter().map(|d| d.to_string()).collect()),
+            makedepends: None,
+            checkdepends: None,
+            replaces: None,
+            out_of_date: None,
+        }
+    }
+
+    #[test]
+    fn install_targets_of_split_sub_package() {
+        // python-foo is the base; only its python2-foo package was asked for
+        let infos: HashMap<String, aur::AurInfo> = [
+            info("python-foo", "python-foo", &[]),
+            info("python2-foo", "python-foo", &["bar>=1"]),
+            info("bar", "bar", &[]),
+        ]
+        .into_iter()
+        .map(|i| (i.name.clone(), i))
+        .collect();
+        let built_ok = [String::from("python-foo"), String::from("bar")];
+        let targets = install_targets(&[String::from("python2-foo")], &infos, &built_ok);
+        let expected: HashSet<String> = ["python2-foo", "bar"].map(String::from).into();
+        assert_eq!(targets, expected);
+    }
+
+    #[test]
+    fn installs_built_dep_when_an_older_version_is_installed() {
+        let infos: HashMap<String, aur::AurInfo> = [
+            info("foo", "foo", &["bar>=2", "baz"]),
+            info("bar", "bar", &[]),
+            info("baz", "baz", &[]),
+        ]
+        .into_iter()
+        .map(|i| (i.name.clone(), i))
+        .collect();
+        let built_ok = [String::from("bar"), String::from("baz")];
+        // bar 1 and baz are installed: plain `bar` would pass, `bar>=2` does not
+        let deptest = |specs: &[String]| {
+            Ok(specs
+                .iter()
+                .filter(|s| !["bar", "baz"].contains(&s.as_str()))
+                .cloned()
+                .collect())
+        };
+        let missing = aur_deps_to_install("foo", &infos, &built_ok, deptest).unwrap();
+        assert_eq!(missing, HashSet::from([String::from("bar")]));
+    }
+
+    #[test]
+    fn dedup_targets_keeps_first_seen_order_and_flags() {
+        let args = ["foo", "--ignore", "bar", "foo", "bar", "bar"].map(String::from);
+        assert_eq!(dedup_targets(&args), ["foo", "--ignore", "bar", "bar"]);
+    }
+
+    #[test]
+    fn dedup_targets_keeps_repeated_flag_values() {
+        let args = ["--ignore", "a", "--ignore", "a", "a"].map(String::from);
+        assert_eq!(
+            dedup_targets(&args),
+            ["--ignore", "a", "--ignore", "a", "a"]
+        );
+    }
 }
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use reqwest::blocking::Client;
+
+use crate::aur::send;
+use crate::config::Config;
+use crate::state;
+use crate::style::*;
+use crate::ui::confirm;
+
+const NEWS_FEED_URL: &str = "https://archlinux.org/feeds/news/";
+
+#[derive(Debug, Clone)]
+pub struct NewsItem {
+    pub title: String,
+    pub link: String,
+    pub date: String,   // pubDate as published, for display
+    pub published: i64, // unix seconds
+}
+
+/// Items of the Arch Linux news RSS feed, newest first.
+pub fn fetch_news(client: &Client) -> Result<Vec<NewsItem>> {
+    let body = send(client.get(NEWS_FEED_URL))
+        .and_then(|r| r.error_for_status())
+        .context("Failed to fetch the Arch Linux news feed")?
+        .text()?;
+    let doc =
+        roxmltree::Document::parse(&body).context("Failed to parse the Arch Linux news feed")?;
+    let mut items = vec![];
+    for item in doc.descendants().filter(|n| n.has_tag_name("item")) {
+        let field = |tag: &str| {
+            item.children()
+                .find(|c| c.has_tag_name(tag))
+                .and_then(|c| c.text())
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let date = field("pubDate");
+        let Some(published) = parse_rfc2822(&date) else {
+            continue;
+        };
+        items.push(NewsItem {
+            title: field("title"),
+            link: field("link"),
+            date,
+            published,
+        });
+    }
+    items.sort_by_key(|i| std::cmp::Reverse(i.published));
+    Ok(items)
+}
+
+/// Show news published since the last acknowledged item (all of it the first time).
+/// With `ask`, the user has to confirm before the caller proceeds; returns false
+/// if they decline. Items are only marked read once acknowledged, so under
+/// --noconfirm they are shown again on the next interactive run.
+pub fn show_unread_news(cfg: &Config, client: &Client, ask: bool) -> Result<bool> {
+    let mut st = state::load_state(cfg)?;
+    let items = fetch_news(client)?;
+    let unread: Vec<&NewsItem> = items
+        .iter()
+        .filter(|i| st.last_news.is_none_or(|seen| i.published > seen))
+        .collect();
+    if unread.is_empty() {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to("No unread Arch Linux news.")
+        );
+        return Ok(true);
+    }
+
+    println!("\n{}", section_title().apply_to("Arch Linux news"));
+    for item in &unread {
+        println!(
+            "  {} {} {}",
+            bullet(),
+            dim().apply_to(&item.date),
+            highlight().apply_to(&item.title)
+        );
+        println!("    {}", path().apply_to(&item.link));
+    }
+
+    if ask {
+        if cfg.noconfirm {
+            println!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to("Continuing without acknowledging the news (--noconfirm)")
+            );
+            return Ok(true);
+        }
+        let proceed = confirm("Read the news above; continue with the upgrade?", false)?;
+        if !proceed {
+            return Ok(false);
+        }
+    }
+
+    st.last_news = unread.iter().map(|i| i.published).max();
+    state::save_state(cfg, &st)?;
+    Ok(true)
+}
+
+/// Parse an RSS pubDate such as `Wed, 03 Jul 2024 13:57:59 +0000` into unix seconds.
+fn parse_rfc2822(s: &str) -> Option<i64> {
+    let mut parts: Vec<&str> = s.split_whitespace().collect();
+    if parts.first().is_some_and(|p| p.ends_with(',')) {
+        parts.remove(0);
+    }
+    let [day, month, year, time, zone] = parts[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|m| m.eq_ignore_ascii_case(month))? as i64
+        + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|t| t.parse::<i64>());
+    let (h, m, sec) = (
+        hms.next()?.ok()?,
+        hms.next()?.ok()?,
+        hms.next().unwrap_or(Ok(0)).ok()?,
+    );
+    let offset = match zone {
+        "GMT" | "UTC" | "Z" => 0,
+        z if z.len() == 5 && z.is_ascii() => {
+            let sign = if z.starts_with('-') { -1 } else { 1 };
+            let hh: i64 = z[1..3].parse().ok()?;
+            let mm: i64 = z[3..5].parse().ok()?;
+            sign * (hh * 3600 + mm * 60)
+        }
+        _ => return None,
+    };
+    Some(days_from_civil(year, month, day) * 86400 + h * 3600 + m * 60 + sec - offset)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
@@ -2,23 +2,165 @@ use crate::config::Config;
 use crate::style::*;
 use anyhow::{anyhow, Result};
 use duct::cmd;
-use std::collections::HashMap;
-use std::sync::{LazyLock, OnceLock};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 use tokio::task;
 
 static PACMAN: OnceLock<String> = OnceLock::new();
+static SUDO_TOOL: OnceLock<String> = OnceLock::new();
+static SUDO_FLAGS: OnceLock<Vec<String>> = OnceLock::new();
+static VERBOSE: OnceLock<bool> = OnceLock::new();
+static HOST_ARCH: OnceLock<String> = OnceLock::new();
+static SYNC_NAMES: OnceLock<HashSet<String>> = OnceLock::new();
+static VERCMP_CACHE: OnceLock<Mutex<HashMap<(String, String), i32>>> = OnceLock::new();
 
 pub fn get_pacman() -> &'static str {
     PACMAN.get_or_init(|| Config::load().unwrap().pacman)
 }
 
+/// Fix the sudo flags for this run (config plus --sudoflags); later calls are ignored.
+pub fn set_sudo_flags(flags: Vec<String>) {
+    let _ = SUDO_FLAGS.set(flags);
+}
+
+fn sudo_flags() -> &'static [String] {
+    SUDO_FLAGS.get_or_init(|| Config::load().map(|c| c.sudoflags).unwrap_or_default())
+}
+
+/// The `verbose` setting, for diagnostics from functions that take no `Config`.
+pub fn set_verbose(on: bool) {
+    let _ = VERBOSE.set(on);
+}
+
+fn verbose() -> bool {
+    *VERBOSE.get_or_init(|| Config::load().is_ok_and(|c| c.verbose))
+}
+
+/// The escalation command from `sudo_cmd` (e.g. `sudo`, `doas`); later calls are ignored.
+pub fn set_sudo_tool(tool: String) {
+    let _ = SUDO_TOOL.set(tool);
+}
+
+fn sudo_tool() -> &'static str {
+    SUDO_TOOL.get_or_init(|| {
+        Config::load()
+            .map(|c| c.sudo)
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| String::from("sudo"))
+    })
+}
+
+/// `<tool> <sudoflags> <args>`, each word of `tool` and each flag its own argv element.
+fn sudo_argv<I, S>(tool: &str, flags: &[String], args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<OsString>,
+{
+    tool.split_whitespace()
+        .map(OsString::from)
+        .chain(flags.iter().map(OsString::from))
+        .chain(args.into_iter().map(Into::into))
+        .collect()
+}
+
+/// `sudo <sudoflags> <pacman> <args>` as a shell command line (for --print).
+pub fn sudo_pacman_command_line(args: &[String]) -> String {
+    sudo_argv(
+        sudo_tool(),
+        sudo_flags(),
+        std::iter::once(get_pacman()).chain(args.iter().map(String::as_str)),
+    )
+    .iter()
+    .map(|a| crate::build::shell_quote(&a.to_string_lossy()))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// `args` through the configured escalation tool with its sudoflags, or run
+/// directly when turbo already runs as root.
+pub fn sudo<I, S>(args: I) -> duct::Expression
+where
+    I: IntoIterator<Item = S>,
+    S: Into<OsString>,
+{
+    let argv = if crate::build::running_as_root() {
+        sudo_argv("", &[], args)
+    } else {
+        sudo_argv(sudo_tool(), sudo_flags(), args)
+    };
+    cmd(&argv[0], &argv[1..])
+}
+
+/// Ask for the sudo password now, in the foreground. `makepkg -s` runs in its
+/// own process group (see `interrupt::shielded`), where a password prompt from
+/// its `sudo pacman` would stop it; a fresh timestamp lets that call through.
+/// Other tools keep no timestamp, so this only applies to sudo.
+pub fn refresh_sudo_timestamp() {
+    let program = sudo_tool().split_whitespace().next().unwrap_or_default();
+    let is_sudo = Path::new(program)
+        .file_name()
+        .is_some_and(|n| n == "sudo" || n == "sudo-rs");
+    if is_sudo && !crate::build::running_as_root() {
+        let _ = sudo(["-v"]).unchecked().run();
+    }
+}
+
+/// Operations that only read the databases (`-Q*`, `-Ss`, `-Si`, `-Sl`, `-Sg`)
+/// need no root, so they run without the escalation tool.
+pub fn is_read_only_query(args: &[String]) -> bool {
+    let Some(op) = args
+        .iter()
+        .find(|a| a.starts_with('-') && !a.starts_with("--"))
+    else {
+        return false;
+    };
+    if op.starts_with("-Q") {
+        return true;
+    }
+    op.strip_prefix("-S").is_some_and(|flags| {
+        !flags.is_empty()
+            && flags
+                .chars()
+                .all(|c| matches!(c, 's' | 'i' | 'l' | 'g' | 'q'))
+    })
+}
+
+/// Privileged operations run through `sudo_cmd` unless turbo is root. Fail up
+/// front with a clear message when neither is the case, instead of on the first spawn.
+pub fn ensure_privilege_tool() -> Result<()> {
+    let tool = sudo_tool();
+    let program = tool.split_whitespace().next().unwrap_or(tool);
+    if crate::build::running_as_root() || which::which(program).is_ok() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} was not found in PATH; turbo runs pacman and package installs through it. \
+         Install it, set sudo_cmd in the conf to the tool you have (e.g. sudo_cmd=doas), \
+         or run turbo as root with build_user set so makepkg can build as a regular user.",
+        program
+    ))
+}
+
 pub async fn run_pacman(args: &[String]) -> Result<()> {
     let pacman = get_pacman();
     let mut full_args = vec![pacman.to_string()];
     full_args.extend(args.iter().cloned());
-    let status =
-        task::spawn_blocking(move || cmd("sudo", full_args).stderr_to_stdout().unchecked().run())
-            .await??;
+    let read_only = is_read_only_query(args);
+    let status = task::spawn_blocking(move || {
+        let expr = if read_only {
+            cmd(&full_args[0], &full_args[1..])
+        } else {
+            sudo(full_args)
+        };
+        expr.stderr_to_stdout().unchecked().run()
+    })
+    .await??;
     if !status.status.success() {
         let exit_desc = status
             .status
@@ -36,19 +178,14 @@ pub async fn run_pacman(args: &[String]) -> Result<()> {
     Ok(())
 }
 
+#[allow(dead_code)]
 pub fn is_in_repo(name: &str) -> Result<bool> {
     let pacman = get_pacman();
-    let res = cmd(
-        "bash",
-        [
-            "-lc",
-            &format!("sudo {} -Si -- {}", pacman, shell_escape(name)),
-        ],
-    )
-    .stdout_capture()
-    .stderr_null()
-    .unchecked()
-    .run()?;
+    let res = cmd(pacman, ["-Si", "--", name])
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()?;
     let ok = res.status.success() && !String::from_utf8_lossy(&res.stdout).is_empty();
     Ok(ok)
 }
@@ -63,37 +200,356 @@ pub async fn passthrough_to_pacman(args: &[String]) -> Result<bool> {
         "{} {} {}",
         info_icon(),
         pacman_badge(),
-        prompt().apply_to(format!("Running: sudo {} {}", pacman, argstr).as_str())
+        prompt().apply_to(if is_read_only_query(args) {
+            format!("Running: {} {}", pacman, argstr)
+        } else {
+            format!("Running: sudo {} {}", pacman, argstr)
+        })
     );
     let owned = args.to_vec();
     run_pacman(&owned).await?;
     Ok(true)
 }
 
-pub async fn list_foreign_packages() -> Result<HashMap<String, String>> {
-    // pacman -Qm : foreign; we'll get name and version
+/// Installed foreign packages, name -> version. Ordered by name so update
+/// listings and menu numbering stay the same from run to run.
+///
+/// This is a point-in-time snapshot. -Syu picks its updates from one taken at
+/// the start, but anything that acts after a prompt or a build (installing
+/// artifacts, the soname scan, removing orphans) queries pacman again, since
+/// another process may have changed the system in the meantime.
+pub async fn list_foreign_packages() -> Result<BTreeMap<String, String>> {
+    // pacman -Qm : foreign; we'll get name and version. A read-only query, so
+    // no sudo (-P --no-refresh runs from status bars)
+    let pacman = get_pacman();
+    let out =
+        task::spawn_blocking(move || cmd(pacman, ["-Qm"]).stderr_to_stdout().read()).await??;
+    Ok(parse_foreign_list(&out))
+}
+
+/// `pacman -Qm` lines are `name version`. The name is the first word and the
+/// version the rest of the line; a line whose first word is no package name
+/// (e.g. a pacman warning on the merged stderr) is skipped.
+fn parse_foreign_list(out: &str) -> BTreeMap<String, String> {
+    let mut map = BTreeMap::new();
+    for line in out.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let parsed = line
+            .split_once(char::is_whitespace)
+            .map(|(n, v)| (n, v.trim()))
+            .filter(|(n, v)| is_package_name(n) && !v.is_empty());
+        match parsed {
+            Some((n, v)) => {
+                map.insert(n.to_string(), v.to_string());
+            }
+            None if verbose() => eprintln!(
+                "{} {}",
+                warn_icon(),
+                warning().apply_to(format!("Skipping unexpected pacman -Qm line: {}", line))
+            ),
+            None => {}
+        }
+    }
+    map
+}
+
+/// Package names only use alphanumerics and `@._+-`.
+fn is_package_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '_' | '+' | '-'))
+}
+
+/// Read `name version` from built package files (`pacman -Qp`), in input order.
+pub fn query_artifacts(zsts: &[String]) -> Result<Vec<(String, String)>> {
+    if zsts.is_empty() {
+        return Ok(vec![]);
+    }
     let pacman = get_pacman();
-    let out = task::spawn_blocking(move || cmd("sudo", [pacman, "-Qm"]).stderr_to_stdout().read())
-        .await??;
-    let mut map = HashMap::new();
+    let mut args: Vec<&str> = vec!["-Qp", "--"];
+    args.extend(zsts.iter().map(String::as_str));
+    let out = cmd(pacman, args)
+        .stderr_null()
+        .read()
+        .map_err(|e| anyhow!("{} -Qp failed: {}", pacman, e))?;
+    Ok(out
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .collect())
+}
+
+/// name -> sync repo, from `pacman -Sl` (no root needed).
+pub async fn list_sync_repos() -> Result<HashMap<String, String>> {
+    let sources = task::spawn_blocking(list_sync_sources).await??;
+    Ok(sources
+        .into_iter()
+        .filter_map(|(name, repos)| repos.into_iter().next().map(|repo| (name, repo)))
+        .collect())
+}
+
+/// name -> every sync repo that has it, in pacman.conf order (which is the
+/// order `pacman -Sl` lists them in).
+pub fn list_sync_sources() -> Result<HashMap<String, Vec<String>>> {
+    let out = cmd(get_pacman(), ["-Sl"]).stderr_null().read()?;
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
     for line in out.lines() {
-        if let Some((n, v)) = line.split_once(' ') {
-            map.insert(n.to_string(), v.to_string());
+        let mut fields = line.split_whitespace();
+        if let (Some(repo), Some(name)) = (fields.next(), fields.next()) {
+            map.entry(name.to_string())
+                .or_default()
+                .push(repo.to_string());
         }
     }
     Ok(map)
 }
 
-pub async fn vercmp(a: &str, b: &str) -> Result<i32> {
-    // pacman's vercmp prints -1, 0, or 1 on stdout
-    let a = a.to_string();
-    let b = b.to_string();
-    let out = task::spawn_blocking(move || {
-        cmd("vercmp", [a.as_str(), b.as_str()])
-            .stderr_to_stdout()
+/// Whether some sync repo has a package called `name`. The `-Sl` listing is
+/// read once per run; when it fails nothing counts as a repo package.
+pub fn in_sync_repos(name: &str) -> bool {
+    SYNC_NAMES
+        .get_or_init(|| {
+            list_sync_sources()
+                .map(|sources| sources.into_keys().collect())
+                .unwrap_or_default()
+        })
+        .contains(name)
+}
+
+/// `IgnorePkg` patterns from pacman.conf (Includes resolved by `pacman-conf`).
+pub fn ignored_pkg_patterns() -> Vec<String> {
+    conf_values("IgnorePkg")
+}
+
+/// `IgnoreGroup` names from pacman.conf.
+pub fn ignored_group_names() -> Vec<String> {
+    conf_values("IgnoreGroup")
+}
+
+fn conf_values(key: &str) -> Vec<String> {
+    cmd("pacman-conf", [key])
+        .stderr_null()
+        .read()
+        .map(|out| out.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Members of the sync repo `groups` (`pacman -Sg`); unknown groups have none.
+pub fn group_members(groups: &[String]) -> HashSet<String> {
+    if groups.is_empty() {
+        return HashSet::new();
+    }
+    cmd(
+        get_pacman(),
+        ["-Sg", "--"]
+            .into_iter()
+            .chain(groups.iter().map(String::as_str)),
+    )
+    .stderr_null()
+    .unchecked()
+    .read()
+    .map(|out| {
+        out.lines()
+            .filter_map(|l| l.split_whitespace().nth(1))
+            .map(str::to_string)
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The architecture pacman installs for (`Architecture` in pacman.conf, `auto`
+/// resolved by `pacman-conf`), falling back to the one turbo was built for.
+pub fn host_arch() -> &'static str {
+    HOST_ARCH.get_or_init(|| {
+        cmd("pacman-conf", ["Architecture"])
+            .stderr_null()
             .read()
+            .ok()
+            .and_then(|out| out.split_whitespace().next().map(str::to_string))
+            .unwrap_or_else(|| std::env::consts::ARCH.to_string())
+    })
+}
+
+/// Whether `name` matches an `IgnorePkg` pattern; pacman allows `*`, `?` and
+/// `[...]` globs there.
+pub fn matches_ignore_pattern(pattern: &str, name: &str) -> bool {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    let mut in_class = false;
+    while let Some(c) = chars.next() {
+        match c {
+            ']' if in_class => {
+                in_class = false;
+                re.push(c);
+            }
+            // Ranges like a-z pass through as is
+            _ if in_class && !matches!(c, '\\' | '[') => re.push(c),
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                re.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    re.push('^');
+                }
+            }
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Regex::new(&re).is_ok_and(|re| re.is_match(name))
+}
+
+fn db_path() -> PathBuf {
+    cmd("pacman-conf", ["DBPath"])
+        .stderr_null()
+        .read()
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/var/lib/pacman"))
+}
+
+/// Time since the sync databases were last refreshed (newest `sync/*.db`),
+/// or None if there are none to look at.
+pub fn sync_db_age() -> Result<Option<Duration>> {
+    let sync_dir = db_path().join("sync");
+    if !sync_dir.exists() {
+        return Ok(None);
+    }
+    let mut newest: Option<SystemTime> = None;
+    for entry in fs::read_dir(&sync_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("db") {
+            continue;
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        if newest.is_none_or(|n| modified > n) {
+            newest = Some(modified);
+        }
+    }
+    Ok(newest.map(|t| t.elapsed().unwrap_or_default()))
+}
+
+pub async fn list_orphans() -> Result<Vec<String>> {
+    // pacman -Qtdq: installed as deps, no longer required; exits 1 when there are none
+    let pacman = get_pacman();
+    let out = task::spawn_blocking(move || {
+        cmd(pacman, ["-Qtdq"])
+            .stdout_capture()
+            .stderr_null()
+            .unchecked()
+            .run()
+    })
+    .await??;
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    Ok(stdout
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+/// Explicitly installed packages (`pacman -Qe`), name -> version.
+pub async fn list_explicit_packages() -> Result<BTreeMap<String, String>> {
+    let pacman = get_pacman();
+    let out = task::spawn_blocking(move || {
+        cmd(pacman, ["-Qe"])
+            .stdout_capture()
+            .stderr_null()
+            .unchecked()
+            .run()
     })
     .await??;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .collect())
+}
+
+/// The dependency strings (version constraints allowed) that nothing installed
+/// satisfies, by way of `pacman -T` (which also honours provides).
+pub async fn unsatisfied_deps(deps: Vec<String>) -> Result<Vec<String>> {
+    task::spawn_blocking(move || deptest(&deps)).await?
+}
+
+/// What `pacman -S targets` would install, dependencies included, per
+/// `pacman -Sp`. Targets that no sync repo has (nor provides) are returned
+/// separately instead of failing the whole query.
+pub fn sync_closure(targets: &[String]) -> Result<(Vec<String>, Vec<String>)> {
+    let mut targets = targets.to_vec();
+    let mut unknown = vec![];
+    while !targets.is_empty() {
+        let out = cmd(
+            get_pacman(),
+            ["-Sp", "--noconfirm", "--print-format", "%n", "--"]
+                .into_iter()
+                .chain(targets.iter().map(String::as_str)),
+        )
+        .env("LC_ALL", "C")
+        .stdin_null()
+        .stdout_capture()
+        .stderr_capture()
+        .unchecked()
+        .run()?;
+        if out.status.success() {
+            let names = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(str::to_string)
+                .collect();
+            return Ok((names, unknown));
+        }
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let missing: Vec<String> = stderr
+            .lines()
+            .filter_map(|l| l.strip_prefix("error: target not found: "))
+            .map(|t| t.trim().to_string())
+            .collect();
+        if missing.is_empty() {
+            return Err(anyhow!("{} -Sp failed: {}", get_pacman(), stderr.trim()));
+        }
+        targets.retain(|t| !missing.contains(t));
+        unknown.extend(missing);
+    }
+    Ok((vec![], unknown))
+}
+
+/// Blocking `unsatisfied_deps`.
+pub fn deptest(deps: &[String]) -> Result<Vec<String>> {
+    if deps.is_empty() {
+        return Ok(vec![]);
+    }
+    // -T exits 127 when something is missing; that's the interesting case here
+    let out = cmd(
+        get_pacman(),
+        ["-T", "--"]
+            .into_iter()
+            .chain(deps.iter().map(String::as_str)),
+    )
+    .stdout_capture()
+    .stderr_null()
+    .unchecked()
+    .run()?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+pub async fn vercmp(a: &str, b: &str) -> Result<i32> {
+    let a = a.to_string();
+    let b = b.to_string();
+    task::spawn_blocking(move || vercmp_blocking(&a, &b)).await?
+}
+
+/// Blocking `vercmp`.
+pub fn vercmp_blocking(a: &str, b: &str) -> Result<i32> {
+    // pacman's vercmp prints -1, 0, or 1 on stdout
+    let out = cmd("vercmp", [a, b]).stderr_to_stdout().read()?;
     let trimmed = out.trim();
     let v: i32 = trimmed
         .parse()
@@ -101,23 +557,33 @@ pub async fn vercmp(a: &str, b: &str) -> Result<i32> {
     Ok(v)
 }
 
+/// Memoized `vercmp`: the same version pairs get compared by both -P and -Syu,
+/// so avoid spawning a subprocess for a pair we've already seen this run.
+pub async fn vercmp_cached(a: &str, b: &str) -> Result<i32> {
+    let cache = VERCMP_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    let key = (a.to_string(), b.to_string());
+    if let Some(v) = cache.lock().unwrap().get(&key) {
+        return Ok(*v);
+    }
+    let v = vercmp(a, b).await?;
+    let mut guard = cache.lock().unwrap();
+    guard.insert(key, v);
+    // vercmp is antisymmetric, so the swapped pair is known too
+    guard.insert((b.to_string(), a.to_string()), -v);
+    Ok(v)
+}
+
 pub fn split_repo_vs_aur(pkgs: &[String]) -> Result<(Vec<String>, Vec<String>)> {
     let pacman = get_pacman();
     let mut repo = vec![];
     let mut aur = vec![];
     for p in pkgs {
         // If pacman -Si finds it in a repo, treat as repo; else assume AUR
-        let res = cmd(
-            "bash",
-            [
-                "-lc",
-                &format!("sudo {} -Si -- {}", pacman, shell_escape(p)),
-            ],
-        )
-        .stdout_capture()
-        .stderr_null()
-        .unchecked()
-        .run()?;
+        let res = cmd(pacman, ["-Si", "--", p.as_str()])
+            .stdout_capture()
+            .stderr_null()
+            .unchecked()
+            .run()?;
         let ok = res.status.success() && !String::from_utf8_lossy(&res.stdout).is_empty();
         if ok {
             repo.push(p.clone());
@@ -128,26 +594,31 @@ pub fn split_repo_vs_aur(pkgs: &[String]) -> Result<(Vec<String>, Vec<String>)>
     Ok((repo, aur))
 }
 
-fn shell_escape(s: &str) -> String {
-    let mut out = String::from("'");
-    out.push_str(&s.replace('\'', "'\\''"));
-    out.push('\'');
-    out
-}
-
+#[allow(non_snake_case)]
 pub fn sudo_pacman_U(zsts: &[String]) -> Result<()> {
-    sudo_pacman_U_inner(zsts, false)
+    sudo_pacman_U_inner(zsts, false, false)
 }
 
+#[allow(non_snake_case)]
 pub fn sudo_pacman_U_noconfirm(zsts: &[String]) -> Result<()> {
-    sudo_pacman_U_inner(zsts, true)
+    sudo_pacman_U_inner(zsts, true, false)
+}
+
+/// `pacman -U --asdeps`, for packages built only as dependencies (--deps-only).
+#[allow(non_snake_case)]
+pub fn sudo_pacman_U_asdeps(zsts: &[String], noconfirm: bool) -> Result<()> {
+    sudo_pacman_U_inner(zsts, noconfirm, true)
 }
 
-fn sudo_pacman_U_inner(zsts: &[String], noconfirm: bool) -> Result<()> {
+#[allow(non_snake_case)]
+fn sudo_pacman_U_inner(zsts: &[String], noconfirm: bool, asdeps: bool) -> Result<()> {
     let mut args = vec!["-U"];
     if noconfirm {
         args.push("--noconfirm");
     }
+    if asdeps {
+        args.push("--asdeps");
+    }
     for z in zsts {
         args.push(z.as_str());
     }
@@ -160,8 +631,7 @@ fn sudo_pacman_U_inner(zsts: &[String], noconfirm: bool) -> Result<()> {
         pacman_badge(),
         prompt().apply_to(command_str.as_str())
     );
-    let status = cmd(
-        "sudo",
+    let status = sudo(
         [pacman]
             .into_iter()
             .chain(args.iter().copied())
@@ -195,8 +665,7 @@ pub fn install_repo_packages(repo: &[String], noconfirm: bool) -> Result<()> {
         pacman_badge(),
         prompt().apply_to(command_str.as_str())
     );
-    let status = cmd(
-        "sudo",
+    let status = sudo(
         [pacman]
             .into_iter()
             .chain(args.iter().copied())
@@ -210,36 +679,214 @@ pub fn install_repo_packages(repo: &[String], noconfirm: bool) -> Result<()> {
     Ok(())
 }
 
+/// Repo targets (as given, possibly `repo/name`) whose installed version already
+/// matches the sync DB. Groups, providers and unknown names are never reported,
+/// so pacman still gets to handle them.
+pub fn up_to_date_repo_targets(targets: &[String]) -> Result<Vec<String>> {
+    let pacman = get_pacman();
+    let names: Vec<&str> = targets
+        .iter()
+        .filter(|t| !t.starts_with('-'))
+        .map(|t| t.as_str())
+        .collect();
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let sync_out = cmd(
+        pacman,
+        ["-Sp", "--print-format", "%n %v", "--"]
+            .into_iter()
+            .chain(names.iter().copied()),
+    )
+    .stdout_capture()
+    .stderr_null()
+    .unchecked()
+    .run()?;
+    if !sync_out.status.success() {
+        return Ok(vec![]);
+    }
+    let sync: HashMap<String, String> =
+        parse_name_version(&String::from_utf8_lossy(&sync_out.stdout));
+
+    let bare: Vec<&str> = names
+        .iter()
+        .map(|t| t.rsplit('/').next().unwrap_or(t))
+        .collect();
+    // -Q exits non-zero if any name isn't installed but still prints the rest
+    let local_out = cmd(pacman, ["-Q", "--"].into_iter().chain(bare.iter().copied()))
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()?;
+    let local = parse_name_version(&String::from_utf8_lossy(&local_out.stdout));
+
+    Ok(names
+        .iter()
+        .zip(&bare)
+        .filter(|(_, name)| matches!((local.get(**name), sync.get(**name)), (Some(l), Some(s)) if l == s))
+        .map(|(target, _)| target.to_string())
+        .collect())
+}
+
+fn parse_name_version(out: &str) -> HashMap<String, String> {
+    out.lines()
+        .filter_map(|line| {
+            let (name, version) = line.trim().split_once(' ')?;
+            Some((name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
 pub fn sudo_pacman_scc() -> Result<()> {
     let pacman = get_pacman();
-    let status = cmd("sudo", [pacman, "-Scc"]).stderr_to_stdout().run()?;
+    let status = sudo([pacman, "-Scc"]).stderr_to_stdout().run()?;
     if !status.status.success() {
         return Err(anyhow!("sudo {} -Scc failed", pacman));
     }
     Ok(())
 }
 
+/// Installed packages that require `name` (`pacman -Qi`'s Required By), or None
+/// if `name` is not installed.
+pub fn required_by(name: &str) -> Result<Option<Vec<String>>> {
+    let out = cmd(get_pacman(), ["-Qi", "--", name])
+        .env("LC_ALL", "C")
+        .stderr_null()
+        .unchecked()
+        .stdout_capture()
+        .run()?;
+    if !out.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(parse_required_by(&String::from_utf8_lossy(
+        &out.stdout,
+    ))))
+}
+
+fn parse_required_by(qi: &str) -> Vec<String> {
+    let mut value = String::new();
+    let mut in_field = false;
+    for line in qi.lines() {
+        match line.split_once(':') {
+            // Long lists wrap onto indented lines without a key
+            _ if in_field && line.starts_with(' ') => value.push_str(line),
+            Some((key, rest)) if key.trim() == "Required By" => {
+                in_field = true;
+                value.push_str(rest);
+            }
+            _ => in_field = false,
+        }
+    }
+    value
+        .split_whitespace()
+        .filter(|n| *n != "None")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether each installed package of `names` was installed as a dependency
+/// (`pacman -Qi`'s Install Reason). Names that are not installed are left out.
+pub fn install_reasons(names: &[String]) -> Result<HashMap<String, bool>> {
+    if names.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let out = cmd(
+        get_pacman(),
+        ["-Qi", "--"]
+            .into_iter()
+            .chain(names.iter().map(String::as_str)),
+    )
+    .env("LC_ALL", "C")
+    .stderr_null()
+    .unchecked()
+    .read()?;
+    Ok(parse_install_reasons(&out))
+}
+
+fn parse_install_reasons(qi: &str) -> HashMap<String, bool> {
+    let mut reasons = HashMap::new();
+    let mut name: Option<&str> = None;
+    for line in qi.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim() {
+            "Name" => name = Some(value.trim()),
+            "Install Reason" => {
+                if let Some(n) = name.take() {
+                    reasons.insert(n.to_string(), value.contains("dependency"));
+                }
+            }
+            _ => {}
+        }
+    }
+    reasons
+}
+
+/// `sudo pacman -D --asdeps|--asexplicit <names>`.
+pub fn set_install_reason(names: &[String], asdeps: bool) -> Result<()> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let pacman = get_pacman();
+    let flag = if asdeps { "--asdeps" } else { "--asexplicit" };
+    let status = sudo(
+        [pacman, "-D", flag]
+            .into_iter()
+            .chain(names.iter().map(String::as_str))
+            .collect::<Vec<_>>(),
+    )
+    .stdout_null()
+    .unchecked()
+    .run()?;
+    if !status.status.success() {
+        return Err(anyhow!("sudo {} -D {} failed", pacman, flag));
+    }
+    Ok(())
+}
+
+/// `sudo pacman-key <args>`; true when it succeeded. Output goes straight to the terminal.
+pub fn sudo_pacman_key(args: &[&str]) -> Result<bool> {
+    println!(
+        "{} {} {}",
+        info_icon(),
+        pacman_badge(),
+        prompt().apply_to(format!("Running: sudo pacman-key {}", args.join(" ")))
+    );
+    let status = sudo(["pacman-key"].iter().chain(args))
+        .stderr_to_stdout()
+        .unchecked()
+        .run()?;
+    Ok(status.status.success())
+}
+
 pub async fn list_outdated_pacman_packages(
+    refresh: bool,
     forcerefresh: bool,
 ) -> Result<Vec<(String, String, String)>> {
     // pacman -Qu outputs: "package_name old_version -> new_version"
     // We need to get both old (installed) and new (available) versions
     //
     let pacman = get_pacman();
-    let mut refresh_arg = String::from("-Sy");
-    if forcerefresh {
-        refresh_arg = String::from("-Syy")
-    }
-    let refresh_args = vec![refresh_arg];
-    if !passthrough_to_pacman(&refresh_args).await? {
-        return Ok(vec![]);
+    if refresh {
+        let mut refresh_arg = String::from("-Sy");
+        if forcerefresh {
+            refresh_arg = String::from("-Syy")
+        }
+        let refresh_args = vec![refresh_arg];
+        if !passthrough_to_pacman(&refresh_args).await? {
+            return Ok(vec![]);
+        }
     }
+    // Without a refresh this is a read-only query against the synced DBs, so
+    // it runs unprivileged (e.g. from a status bar)
     let out = task::spawn_blocking(move || {
-        cmd("sudo", [pacman, "-Qu"])
-            .stdout_capture()
-            .stderr_null()
-            .unchecked()
-            .run()
+        let query = if refresh {
+            sudo([pacman, "-Qu"])
+        } else {
+            cmd(pacman, ["-Qu"])
+        };
+        query.stdout_capture().stderr_null().unchecked().run()
     })
     .await??;
 
@@ -248,26 +895,113 @@ pub async fn list_outdated_pacman_packages(
         return Ok(vec![]);
     }
 
-    let stdout = String::from_utf8_lossy(&out.stdout);
-    let mut packages = vec![];
-
-    for line in stdout.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
+    Ok(parse_outdated(&String::from_utf8_lossy(&out.stdout)))
+}
 
-        // Format is: "package_name old_version -> new_version"
+/// `pacman -Qu` lines are `name old -> new`, with ` [ignored]` appended for
+/// IgnorePkg/IgnoreGroup members.
+fn parse_outdated(out: &str) -> Vec<(String, String, String)> {
+    let mut packages = vec![];
+    for line in out.lines().map(str::trim).filter(|l| !l.is_empty()) {
         if let Some((name_old, new_ver)) = line.split_once(" -> ") {
             if let Some((name, old_ver)) = name_old.split_once(' ') {
-                packages.push((
-                    name.to_string(),
-                    old_ver.to_string(),
-                    new_ver.trim().to_string(),
-                ));
+                let new_ver = new_ver.trim().trim_end_matches("[ignored]").trim_end();
+                packages.push((name.to_string(), old_ver.to_string(), new_ver.to_string()));
             }
         }
     }
+    packages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn foreign_list_tolerates_trailing_space() {
+        let map = parse_foreign_list("foo 1.0-1 \nbar 2.3-2\n");
+        assert_eq!(map.get("foo").map(String::as_str), Some("1.0-1"));
+        assert_eq!(map.get("bar").map(String::as_str), Some("2.3-2"));
+    }
 
-    Ok(packages)
+    #[test]
+    fn foreign_list_skips_warnings() {
+        let out = "warning: database file for 'chaotic' does not exist\nfoo 1.0-1\n";
+        let map = parse_foreign_list(out);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("foo").map(String::as_str), Some("1.0-1"));
+    }
+
+    #[test]
+    fn foreign_list_keeps_epoch() {
+        let map = parse_foreign_list("foo-git 2:1.0.r12.gabc-1\n");
+        assert_eq!(
+            map.get("foo-git").map(String::as_str),
+            Some("2:1.0.r12.gabc-1")
+        );
+    }
+
+    #[test]
+    fn foreign_list_keeps_version_with_space() {
+        let map = parse_foreign_list("foo 1.0 beta-1\n");
+        assert_eq!(map.get("foo").map(String::as_str), Some("1.0 beta-1"));
+    }
+
+    #[test]
+    fn outdated_drops_ignored_marker() {
+        let out = "linux 6.1.1-1 -> 6.1.2-1\nfoo 1.0-1 -> 1.1-1 [ignored]\n";
+        assert_eq!(
+            parse_outdated(out),
+            [
+                (
+                    String::from("linux"),
+                    String::from("6.1.1-1"),
+                    String::from("6.1.2-1")
+                ),
+                (
+                    String::from("foo"),
+                    String::from("1.0-1"),
+                    String::from("1.1-1")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn install_reasons_of_several_packages() {
+        let qi = "Name            : foo
+Version         : 1.0-1
+Install Reason  : Explicitly installed
+Install Script  : No
+
+Name            : bar
+Version         : 2.0-1
+Install Reason  : Installed as a dependency for another package
+Install Script  : No
+";
+        let reasons = parse_install_reasons(qi);
+        assert_eq!(reasons.len(), 2);
+        assert_eq!(reasons.get("foo"), Some(&false));
+        assert_eq!(reasons.get("bar"), Some(&true));
+    }
+
+    #[test]
+    fn sudo_argv_puts_flags_between_tool_and_pacman() {
+        let flags = [String::from("-A"), String::from("--preserve-env=PATH")];
+        let argv = sudo_argv("sudo", &flags, ["pacman", "-U", "foo.pkg.tar.zst"]);
+        assert_eq!(
+            argv,
+            [
+                "sudo",
+                "-A",
+                "--preserve-env=PATH",
+                "pacman",
+                "-U",
+                "foo.pkg.tar.zst"
+            ]
+        );
+        // A multi-word tool is split into its own argv elements
+        let argv = sudo_argv("doas -n", &[], ["pacman", "-Syu"]);
+        assert_eq!(argv, ["doas", "-n", "pacman", "-Syu"]);
+    }
 }
@@ -3,20 +3,24 @@ use std::path::Path;
 use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
-use dialoguer::Confirm;
 use duct::cmd;
 use reqwest::blocking::Client;
 use semver::Version;
 use serde::Deserialize;
 
+use crate::aur::send;
 use crate::build::{clean_dir_contents, collect_zsts};
 use crate::config::Config;
 use crate::pac;
+use crate::state;
 use crate::style::*;
+use crate::ui::confirm;
 
 const REPO_URL: &str = "https://github.com/splizer101/turbo.git";
 const RELEASES_API: &str = "https://api.github.com/repos/splizer101/turbo/releases/latest";
 const DEFAULT_BRANCH: &str = "main";
+/// Release checks are skipped when the last one is more recent than this.
+const CHECK_INTERVAL_SECS: i64 = 24 * 3600;
 
 #[derive(Debug, Deserialize)]
 struct ReleaseResponse {
@@ -25,10 +29,25 @@ struct ReleaseResponse {
     prerelease: bool,
 }
 
+/// Hours since the last release check when it falls inside the throttle window.
+pub fn checked_recently(cfg: &Config) -> Option<i64> {
+    let last = state::load_state(cfg).ok()?.last_self_update_check?;
+    let age = state::unix_now() - last;
+    (0..CHECK_INTERVAL_SECS)
+        .contains(&age)
+        .then_some(age / 3600)
+}
+
+fn record_check(cfg: &Config) -> Result<()> {
+    let mut st = state::load_state(cfg)?;
+    st.last_self_update_check = Some(state::unix_now());
+    state::save_state(cfg, &st)
+}
+
 pub fn ensure_latest_release_installed(cfg: &Config) -> Result<()> {
     let client = Client::builder()
         .user_agent("turbo-self-update/0.1")
-        .timeout(Duration::from_secs(20))
+        .timeout(Duration::from_secs(cfg.timeout_secs.unwrap_or(20)))
         .build()?;
 
     let release = match fetch_latest_release(&client) {
@@ -43,6 +62,10 @@ pub fn ensure_latest_release_installed(cfg: &Config) -> Result<()> {
         }
     };
 
+    if let Err(e) = record_check(cfg) {
+        eprintln!("{} {}", warn_icon(), warning().apply_to(format!("{:#}", e)));
+    }
+
     let latest_version = normalize_tag(release.tag_name.trim());
     let current_version = env!("CARGO_PKG_VERSION");
 
@@ -52,6 +75,11 @@ pub fn ensure_latest_release_installed(cfg: &Config) -> Result<()> {
         Version::parse(current_version).context("Parsing current Turbo version")?;
 
     if latest_semver <= current_semver {
+        println!(
+            "{} {}",
+            info_icon(),
+            dim().apply_to(format!("Turbo {} is up to date.", current_version))
+        );
         return Ok(());
     }
 
@@ -73,9 +101,7 @@ pub fn ensure_latest_release_installed(cfg: &Config) -> Result<()> {
 }
 
 fn fetch_latest_release(client: &Client) -> Result<ReleaseResponse> {
-    let resp = client
-        .get(RELEASES_API)
-        .send()
+    let resp = send(client.get(RELEASES_API))
         .context("GitHub release request failed")?
         .error_for_status()
         .context("GitHub release API returned an error status")?;
@@ -104,10 +130,7 @@ fn confirm_self_update(cfg: &Config, latest_version: &str) -> Result<bool> {
         prompt().apply_to("?")
     );
 
-    let confirmed = Confirm::new()
-        .with_prompt(prompt_text)
-        .default(true)
-        .interact()?;
+    let confirmed = confirm(prompt_text, true)?;
     if !confirmed {
         println!(
             "{} {}",
@@ -140,7 +163,7 @@ fn install_latest_from_branch(cfg: &Config, branch: &str) -> Result<()> {
     );
     run_makepkg(&checkout_dir)?;
 
-    let artifacts = collect_zsts(&checkout_dir, None)?;
+    let artifacts = collect_zsts(&checkout_dir, None, false)?;
     if artifacts.is_empty() {
         return Err(anyhow!(
             "Self-update build produced no *.pkg.tar.zst artifacts"
@@ -0,0 +1,53 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use duct::cmd;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+
+/// Whether `path` is an ELF object, judged by its magic bytes.
+fn is_elf(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok_and(|_| magic == *b"\x7fELF")
+}
+
+/// Whether `ldd` reports a shared library of `path` as `not found`.
+fn links_missing_lib(path: &str) -> bool {
+    // Static binaries and plain objects make ldd exit non-zero; nothing is missing then
+    cmd("ldd", [path])
+        .stderr_null()
+        .unchecked()
+        .read()
+        .is_ok_and(|out| out.lines().any(|l| l.trim_end().ends_with("=> not found")))
+}
+
+/// Foreign packages with files linked against shared libraries that are no longer
+/// installed, typically after a repo library bumped its soname. These need a
+/// rebuild even though their AUR version did not change.
+pub fn find_broken_foreign(cfg: &Config) -> Result<Vec<String>> {
+    // pacman exits 1 when there are no foreign packages at all
+    let out = cmd(&cfg.pacman, ["-Qlm"])
+        .stderr_null()
+        .unchecked()
+        .read()
+        .context("Failed to list the files of foreign packages")?;
+    let mut files: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for line in out.lines() {
+        let Some((pkg, path)) = line.split_once(' ') else {
+            continue;
+        };
+        if path.ends_with('/') || path.starts_with("/usr/lib/debug/") {
+            continue;
+        }
+        files.entry(pkg).or_default().push(path);
+    }
+    let broken: Vec<String> = files
+        .par_iter()
+        .filter(|(_, paths)| paths.iter().any(|p| is_elf(p) && links_missing_lib(p)))
+        .map(|(pkg, _)| pkg.to_string())
+        .collect();
+    Ok(broken)
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::pac;
+use crate::style::*;
+
+/// Persistent per-package record of what turbo last installed successfully,
+/// stored at `cache_dir()/state.json`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InstallState {
+    #[serde(default)]
+    pub installed: BTreeMap<String, String>, // name -> version we installed
+    #[serde(default)]
+    pub last_news: Option<i64>, // publish time (unix) of the newest acknowledged Arch news item
+    #[serde(default)]
+    pub last_install: Option<i64>, // unix time of the last successful install
+    #[serde(default)]
+    pub last_self_update_check: Option<i64>, // unix time turbo last asked GitHub for a release
+    #[serde(default)]
+    pub builds: Vec<BuildRecord>, // recent makepkg runs, oldest first (--stats)
+    #[serde(default)]
+    pub held: BTreeMap<String, String>, // name -> version -Syu must not upgrade (--downgrade)
+}
+
+/// One makepkg run of a package base.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub pkgbase: String,
+    pub finished: i64, // unix seconds
+    pub secs: u64,
+    pub success: bool,
+}
+
+/// Older build records are dropped beyond this many.
+const BUILD_HISTORY_LIMIT: usize = 1000;
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn state_path(cfg: &Config) -> PathBuf {
+    cfg.cache_dir().join("state.json")
+}
+
+pub fn load_state(cfg: &Config) -> Result<InstallState> {
+    let path = state_path(cfg);
+    if !path.exists() {
+        return Ok(InstallState::default());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let state = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(state)
+}
+
+pub fn save_state(cfg: &Config, state: &InstallState) -> Result<()> {
+    let path = state_path(cfg);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(state)?)?;
+    Ok(())
+}
+
+/// Record the name/version of every artifact that was just installed. Versions
+/// come from the built packages themselves, so VCS packages are recorded with
+/// the pkgver makepkg actually produced rather than the one in .SRCINFO.
+pub fn record_installed(cfg: &Config, built: &[(String, String)]) -> Result<()> {
+    let mut state = load_state(cfg)?;
+    for (name, version) in built {
+        state.installed.insert(name.clone(), version.clone());
+    }
+    state.last_install = Some(unix_now());
+    save_state(cfg, &state)
+}
+
+/// Keep -Syu from upgrading `name` past `version` (`None` lifts the hold). Returns
+/// whether anything changed.
+pub fn set_held(cfg: &Config, name: &str, version: Option<&str>) -> Result<bool> {
+    let mut state = load_state(cfg)?;
+    let changed = match version {
+        Some(v) => {
+            state
+                .held
+                .insert(name.to_string(), v.to_string())
+                .as_deref()
+                != Some(v)
+        }
+        None => state.held.remove(name).is_some(),
+    };
+    save_state(cfg, &state)?;
+    Ok(changed)
+}
+
+/// Append a makepkg run to the build history.
+pub fn record_build(cfg: &Config, pkgbase: &str, secs: u64, success: bool) -> Result<()> {
+    let mut state = load_state(cfg)?;
+    state.builds.push(BuildRecord {
+        pkgbase: pkgbase.to_string(),
+        finished: unix_now(),
+        secs,
+        success,
+    });
+    let excess = state.builds.len().saturating_sub(BUILD_HISTORY_LIMIT);
+    state.builds.drain(..excess);
+    save_state(cfg, &state)
+}
+
+/// Warn about foreign packages now installed at a lower version than the one we
+/// last installed (e.g. a VCS pkgver regression or a manual downgrade).
+pub async fn warn_on_downgrades(cfg: &Config, foreign: &BTreeMap<String, String>) -> Result<()> {
+    let state = load_state(cfg)?;
+    for (name, recorded) in &state.installed {
+        let Some(current) = foreign.get(name) else {
+            continue;
+        };
+        if let Ok(ord) = pac::vercmp_cached(current, recorded).await {
+            if ord < 0 {
+                eprintln!(
+                    "{} {} {}",
+                    warn_icon(),
+                    aur_badge(),
+                    warning().apply_to(format!(
+                        "{} is installed at {} but turbo last installed {} (unexpected downgrade)",
+                        package_name().apply_to(name),
+                        current_version().apply_to(current),
+                        new_version().apply_to(recorded)
+                    ))
+                );
+            }
+        }
+    }
+    Ok(())
+}
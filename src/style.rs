@@ -1,20 +1,194 @@
-use console::Style;
+use console::{Color, Style};
+use std::sync::OnceLock;
+
+use crate::config::ColorTheme;
+
+/// A role's foreground color; `None` leaves only the attributes (bold, italic).
+#[derive(Debug, Clone, Copy)]
+struct Tint {
+    color: Option<Color>,
+    bright: bool,
+}
+
+const fn fixed(n: u8) -> Tint {
+    Tint {
+        color: Some(Color::Color256(n)),
+        bright: false,
+    }
+}
+
+const fn basic(color: Color) -> Tint {
+    Tint {
+        color: Some(color),
+        bright: false,
+    }
+}
+
+const fn bright(color: Color) -> Tint {
+    Tint {
+        color: Some(color),
+        bright: true,
+    }
+}
+
+const PLAIN: Tint = Tint {
+    color: None,
+    bright: false,
+};
+
+/// Colors for every styled role, picked by `color_theme`.
+#[derive(Debug, Clone, Copy)]
+struct Palette {
+    success: Tint,
+    error: Tint,
+    warning: Tint,
+    info: Tint,
+    section_title: Tint,
+    prompt: Tint,
+    highlight: Tint,
+    highlight_value: Tint,
+    aur: Tint,
+    github: Tint,
+    pacman: Tint,
+    current_version: Tint,
+    new_version: Tint,
+    package_name: Tint,
+    command: Tint,
+    path: Tint,
+    number: Tint,
+}
+
+impl Palette {
+    const DEFAULT: Palette = Palette {
+        success: bright(Color::Green),
+        error: bright(Color::Red),
+        warning: basic(Color::Yellow),
+        info: bright(Color::Cyan),
+        section_title: fixed(44),
+        prompt: fixed(208),
+        highlight: fixed(214),
+        highlight_value: fixed(208),
+        aur: basic(Color::Magenta),
+        github: fixed(177),
+        pacman: fixed(81),
+        current_version: fixed(196),
+        new_version: fixed(82),
+        package_name: fixed(45),
+        command: fixed(33),
+        path: fixed(213),
+        number: fixed(39),
+    };
+
+    /// Darker shades that stay readable on a white background.
+    const LIGHT: Palette = Palette {
+        success: fixed(28),
+        error: fixed(160),
+        warning: fixed(130),
+        info: fixed(31),
+        section_title: fixed(25),
+        prompt: fixed(166),
+        highlight: fixed(130),
+        highlight_value: fixed(166),
+        aur: fixed(90),
+        github: fixed(97),
+        pacman: fixed(24),
+        current_version: fixed(160),
+        new_version: fixed(28),
+        package_name: fixed(25),
+        command: fixed(19),
+        path: fixed(127),
+        number: fixed(24),
+    };
+
+    const MONOCHROME: Palette = Palette {
+        success: PLAIN,
+        error: PLAIN,
+        warning: PLAIN,
+        info: PLAIN,
+        section_title: PLAIN,
+        prompt: PLAIN,
+        highlight: PLAIN,
+        highlight_value: PLAIN,
+        aur: PLAIN,
+        github: PLAIN,
+        pacman: PLAIN,
+        current_version: PLAIN,
+        new_version: PLAIN,
+        package_name: PLAIN,
+        command: PLAIN,
+        path: PLAIN,
+        number: PLAIN,
+    };
+
+    /// Only the bright base colors, which terminal themes keep far apart.
+    const HIGH_CONTRAST: Palette = Palette {
+        success: bright(Color::Green),
+        error: bright(Color::Red),
+        warning: bright(Color::Yellow),
+        info: bright(Color::Cyan),
+        section_title: bright(Color::White),
+        prompt: bright(Color::Yellow),
+        highlight: bright(Color::Yellow),
+        highlight_value: bright(Color::Yellow),
+        aur: bright(Color::Magenta),
+        github: bright(Color::Magenta),
+        pacman: bright(Color::Cyan),
+        current_version: bright(Color::Red),
+        new_version: bright(Color::Green),
+        package_name: bright(Color::White),
+        command: bright(Color::Blue),
+        path: bright(Color::Magenta),
+        number: bright(Color::Cyan),
+    };
+
+    fn for_theme(theme: ColorTheme) -> &'static Palette {
+        match theme {
+            ColorTheme::Default => &Palette::DEFAULT,
+            ColorTheme::Light => &Palette::LIGHT,
+            ColorTheme::Monochrome => &Palette::MONOCHROME,
+            ColorTheme::HighContrast => &Palette::HIGH_CONTRAST,
+        }
+    }
+}
+
+static PALETTE: OnceLock<&'static Palette> = OnceLock::new();
+
+/// Pick the palette once the config is loaded; output before that uses the default.
+pub fn set_theme(theme: ColorTheme) {
+    let _ = PALETTE.set(Palette::for_theme(theme));
+}
+
+fn palette() -> &'static Palette {
+    PALETTE.get().copied().unwrap_or(&Palette::DEFAULT)
+}
+
+fn paint(style: Style, tint: Tint) -> Style {
+    let style = match tint.color {
+        Some(color) => style.fg(color),
+        None => style,
+    };
+    if tint.bright {
+        style.bright()
+    } else {
+        style
+    }
+}
 
 // Core status styles
 pub fn success() -> Style {
-    Style::new().green().bright().bold()
+    paint(Style::new().bold(), palette().success)
 }
 
 pub fn error() -> Style {
-    Style::new().red().bright().bold()
+    paint(Style::new().bold(), palette().error)
 }
 
 pub fn warning() -> Style {
-    Style::new().yellow().bold()
+    paint(Style::new().bold(), palette().warning)
 }
 
 pub fn info() -> Style {
-    Style::new().cyan().bright()
+    paint(Style::new(), palette().info)
 }
 
 // Styled icons
@@ -40,19 +214,19 @@ pub fn bullet() -> String {
 
 // UI element styles
 pub fn section_title() -> Style {
-    Style::new().bold().color256(44)
+    paint(Style::new().bold(), palette().section_title)
 }
 
 pub fn prompt() -> Style {
-    Style::new().bold().color256(208)
+    paint(Style::new().bold(), palette().prompt)
 }
 
 pub fn highlight() -> Style {
-    Style::new().bold().color256(214)
+    paint(Style::new().bold(), palette().highlight)
 }
 
 pub fn highlight_value() -> Style {
-    Style::new().bold().color256(208)
+    paint(Style::new().bold(), palette().highlight_value)
 }
 
 pub fn dim() -> Style {
@@ -61,15 +235,15 @@ pub fn dim() -> Style {
 
 // Accent helpers
 pub fn aur_accent() -> Style {
-    Style::new().bold().magenta()
+    paint(Style::new().bold(), palette().aur)
 }
 
 pub fn github_accent() -> Style {
-    Style::new().bold().color256(177)
+    paint(Style::new().bold(), palette().github)
 }
 
 pub fn pacman_accent() -> Style {
-    Style::new().bold().color256(81)
+    paint(Style::new().bold(), palette().pacman)
 }
 
 pub fn badge(label: &str, style: Style) -> String {
@@ -95,29 +269,29 @@ pub fn pacman_badge() -> String {
 
 // Package version styles
 pub fn current_version() -> Style {
-    Style::new().color256(196).bold()
+    paint(Style::new().bold(), palette().current_version)
 }
 
 pub fn new_version() -> Style {
-    Style::new().color256(82).bold()
+    paint(Style::new().bold(), palette().new_version)
 }
 
 // Package name style
 pub fn package_name() -> Style {
-    Style::new().bold().color256(45)
+    paint(Style::new().bold(), palette().package_name)
 }
 
 // Command style
 pub fn command() -> Style {
-    Style::new().bold().color256(33)
+    paint(Style::new().bold(), palette().command)
 }
 
 // Path style
 pub fn path() -> Style {
-    Style::new().italic().color256(213)
+    paint(Style::new().italic(), palette().path)
 }
 
 // Number style
 pub fn number() -> Style {
-    Style::new().bold().color256(39)
+    paint(Style::new().bold(), palette().number)
 }
@@ -1,16 +1,228 @@
 use anyhow::Result;
-use dialoguer::MultiSelect;
+use dialoguer::{Confirm, MultiSelect};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::sync::OnceLock;
+use std::time::Duration;
 
+use crate::config::DefaultSelection;
 use crate::style::*;
 
+static CONFIRM_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// --confirm-timeout: prompts take their default answer after this long.
+pub fn set_confirm_timeout(secs: u64) {
+    let _ = CONFIRM_TIMEOUT.set(Duration::from_secs(secs));
+}
+
+/// Whether stdin has input within `timeout`, by way of poll(2). Nothing is read,
+/// so stdin stays free for the file manager, makepkg and pacman between prompts.
+fn stdin_ready(timeout: Duration) -> Result<bool> {
+    let mut fds = libc::pollfd {
+        fd: io::stdin().as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ms = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+    loop {
+        // SAFETY: `fds` is a single valid pollfd that outlives the call
+        match unsafe { libc::poll(&mut fds, 1, ms) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error().into()),
+            n => return Ok(n > 0),
+        }
+    }
+}
+
+/// One line of stdin, or an empty line (the default answer) once `timeout` runs out.
+fn read_line(timeout: Option<Duration>) -> Result<String> {
+    io::stdout().flush()?;
+    if let Some(timeout) = timeout {
+        if !stdin_ready(timeout)? {
+            println!();
+            println!(
+                "{} {}",
+                info_icon(),
+                dim().apply_to(format!(
+                    "No answer after {}s, using the default.",
+                    timeout.as_secs()
+                ))
+            );
+            return Ok(String::new());
+        }
+    }
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line)
+}
+
+/// The answer to a prompt; with --confirm-timeout, empty when nobody answers in time.
+fn read_answer() -> Result<String> {
+    read_line(CONFIRM_TIMEOUT.get().copied())
+}
+
+/// "Press Enter" prompt. Never times out, since it waits for the user to finish
+/// something (like editing) rather than for a decision with a safe default.
+pub fn wait_for_enter(prompt_text: &str) -> Result<()> {
+    print!("{} {} ", info_icon(), prompt().apply_to(prompt_text));
+    read_line(None)?;
+    Ok(())
+}
+
+/// Yes/no prompt. Without --confirm-timeout this is dialoguer's `Confirm`;
+/// with it, a plain line prompt that takes `default` when nobody answers.
+pub fn confirm(prompt_text: impl Into<String>, default: bool) -> Result<bool> {
+    let prompt_text = prompt_text.into();
+    if CONFIRM_TIMEOUT.get().is_none() {
+        return Ok(Confirm::new()
+            .with_prompt(prompt_text)
+            .default(default)
+            .interact()?);
+    }
+    loop {
+        print!(
+            "{} {} ",
+            prompt_text,
+            dim().apply_to(if default { "[Y/n]" } else { "[y/N]" })
+        );
+        match read_answer()?.trim().to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => continue,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Pickable {
     pub name: String,
     pub current: String,
     pub latest: String,
+    pub from_repo: bool, // a sync repo update (--unified), not an AUR one
+}
+
+/// Ordering for update listings (`--sort`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    VersionDelta,
+    Repo,
+}
+
+impl SortKey {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(SortKey::Name),
+            "version-delta" => Some(SortKey::VersionDelta),
+            "repo" => Some(SortKey::Repo),
+            _ => None,
+        }
+    }
+}
+
+/// Leading numeric components of a version: "1:2.10.3-1" -> [1, 2, 10, 3, 1].
+fn numeric_parts(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().unwrap_or(u64::MAX))
+        .collect()
+}
+
+/// How big a jump old -> new is: the earlier the first differing component
+/// (epoch/major before minor before patch) and the larger its difference, the bigger.
+fn version_delta(old: &str, new: &str) -> (usize, u64) {
+    let (a, b) = (numeric_parts(old), numeric_parts(new));
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (
+            a.get(i).copied().unwrap_or(0),
+            b.get(i).copied().unwrap_or(0),
+        );
+        if x != y {
+            return (i, x.abs_diff(y));
+        }
+    }
+    (usize::MAX, 0)
+}
+
+/// VCS packages whose version only moves when they are rebuilt.
+const DEVEL_SUFFIXES: &[&str] = &["-git", "-svn", "-hg", "-bzr", "-cvs", "-darcs"];
+
+/// Whether old -> new changes the epoch or the first pkgver component.
+fn is_major_bump(old: &str, new: &str) -> bool {
+    let split = |v: &str| match v.split_once(':') {
+        Some((epoch, rest)) => (epoch.to_string(), numeric_parts(rest)),
+        None => (String::from("0"), numeric_parts(v)),
+    };
+    let ((old_epoch, old_parts), (new_epoch, new_parts)) = (split(old), split(new));
+    old_epoch != new_epoch || old_parts.first() != new_parts.first()
+}
+
+/// One-line risk read of an update list, e.g.
+/// "12 AUR packages, 3 major version bumps, 2 flagged out-of-date, 1 devel".
+pub fn summarize_updates(items: &[Pickable], out_of_date: &HashSet<&str>) -> String {
+    fn count(n: usize, one: &str, many: &str) -> String {
+        format!("{} {}", n, if n == 1 { one } else { many })
+    }
+    let major = items
+        .iter()
+        .filter(|p| is_major_bump(&p.current, &p.latest))
+        .count();
+    let flagged = items
+        .iter()
+        .filter(|p| out_of_date.contains(p.name.as_str()))
+        .count();
+    let devel = items
+        .iter()
+        .filter(|p| DEVEL_SUFFIXES.iter().any(|s| p.name.ends_with(s)))
+        .count();
+    let mut parts = vec![count(items.len(), "AUR package", "AUR packages")];
+    if major > 0 {
+        parts.push(count(major, "major version bump", "major version bumps"));
+    }
+    if flagged > 0 {
+        parts.push(format!("{} flagged out-of-date", flagged));
+    }
+    if devel > 0 {
+        parts.push(format!("{} devel", devel));
+    }
+    parts.join(", ")
 }
 
-pub fn pick_updates(items: &[Pickable]) -> Result<Vec<String>> {
+/// Sort update rows by `key`, always falling back to the package name so the
+/// order is stable. `parts` extracts (name, old version, new version) from a row;
+/// `repo_of` maps names to their sync repo for `SortKey::Repo`.
+pub fn sort_updates<T>(
+    items: &mut [T],
+    key: SortKey,
+    repo_of: &HashMap<String, String>,
+    parts: impl Fn(&T) -> (&str, &str, &str),
+) {
+    items.sort_by(|x, y| {
+        let (xn, xo, xl) = parts(x);
+        let (yn, yo, yl) = parts(y);
+        let primary = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::VersionDelta => {
+                let (xi, xd) = version_delta(xo, xl);
+                let (yi, yd) = version_delta(yo, yl);
+                xi.cmp(&yi).then(yd.cmp(&xd))
+            }
+            SortKey::Repo => repo_of
+                .get(xn)
+                .map(String::as_str)
+                .unwrap_or("")
+                .cmp(repo_of.get(yn).map(String::as_str).unwrap_or("")),
+        };
+        primary.then_with(|| xn.cmp(yn))
+    });
+}
+
+#[allow(dead_code)]
+pub fn pick_updates(items: &[Pickable], default: DefaultSelection) -> Result<Vec<String>> {
     let items_disp: Vec<String> = items
         .iter()
         .map(|p| {
@@ -38,7 +250,7 @@ pub fn pick_updates(items: &[Pickable]) -> Result<Vec<String>> {
     let selected = MultiSelect::new()
         .with_prompt(prompt_label)
         .items(&items_disp)
-        .defaults(&vec![true; items.len()])
+        .defaults(&vec![default == DefaultSelection::All; items.len()])
         .report(true)
         .interact()?;
 
@@ -49,7 +261,16 @@ pub fn pick_updates(items: &[Pickable]) -> Result<Vec<String>> {
     Ok(out)
 }
 
-pub fn pick_updates_numeric(items: &[Pickable]) -> Result<Vec<String>> {
+/// Numbered update menu. With `select_all` the list is only printed and every
+/// item is returned without reading stdin (for --noconfirm / --upgrades-only).
+/// `default` decides what an empty answer means.
+pub fn pick_updates_numeric(
+    items: &[Pickable],
+    select_all: bool,
+    default: DefaultSelection,
+) -> Result<Vec<String>> {
+    // Badge each line only when repo and AUR updates share the menu
+    let mixed = items.iter().any(|p| p.from_repo);
     // Print numbered list
     for (i, p) in items.iter().enumerate() {
         let num = number().apply_to(format!("{:>2})", i + 1));
@@ -58,27 +279,41 @@ pub fn pick_updates_numeric(items: &[Pickable]) -> Result<Vec<String>> {
         let arrow = dim().apply_to("→");
         let latest = new_version().apply_to(&p.latest);
 
+        let badge = match (mixed, p.from_repo) {
+            (false, _) => String::new(),
+            (true, true) => format!(" {}", pacman_badge()),
+            (true, false) => format!(" {}", aur_badge()),
+        };
         println!(
-            "{} {} {:<32} {:>12}  {}  {:<12}",
+            "{} {} {:<32} {:>12}  {}  {:<12}{}",
             bullet(),
             num,
             name,
             current,
             arrow,
-            latest
+            latest,
+            badge
         );
     }
-    let prompt_text = format!(
-        "Enter numbers to update (e.g., 1 3 5). Press Enter for all, 0 or >{} to skip:",
-        items.len()
-    );
+    if select_all {
+        return Ok(items.iter().map(|p| p.name.clone()).collect());
+    }
+    let prompt_text = match default {
+        DefaultSelection::All => format!(
+            "Enter numbers to update (e.g., 1 3 5). Press Enter for all, 0 or >{} to skip:",
+            items.len()
+        ),
+        DefaultSelection::None => {
+            String::from("Enter numbers to update (e.g., 1 3 5). Press Enter to skip:")
+        }
+    };
     print!("{} {} ", info_icon(), prompt().apply_to(&prompt_text));
-    use std::io::{self, Write};
-    io::stdout().flush()?;
-    let mut line = String::new();
-    io::stdin().read_line(&mut line)?;
+    let line = read_answer()?;
     if line.trim().is_empty() {
-        return Ok(items.iter().map(|p| p.name.clone()).collect());
+        return Ok(match default {
+            DefaultSelection::All => items.iter().map(|p| p.name.clone()).collect(),
+            DefaultSelection::None => vec![],
+        });
     }
     let mut selections: Vec<usize> = vec![];
     for t in line
@@ -100,3 +335,92 @@ pub fn pick_updates_numeric(items: &[Pickable]) -> Result<Vec<String>> {
     }
     Ok(out)
 }
+
+/// Ask which sync repo to install `name` from; Enter keeps pacman's first match.
+pub fn pick_repo(name: &str, repos: &[String]) -> Result<String> {
+    println!(
+        "{} {} is available from several repos:",
+        info_icon(),
+        package_name().apply_to(name)
+    );
+    for (i, repo) in repos.iter().enumerate() {
+        let num = number().apply_to(format!("{:>2})", i + 1));
+        println!("{} {} {}", bullet(), num, repo);
+    }
+    let prompt_text = format!("Repo to install from [1-{}] (Enter for 1):", repos.len());
+    print!("{} {} ", info_icon(), prompt().apply_to(&prompt_text));
+    let line = read_answer()?;
+    let choice = line
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=repos.len()).contains(n))
+        .unwrap_or(1);
+    Ok(repos[choice - 1].clone())
+}
+
+/// Ask which cached version of `name` to install; Enter cancels.
+pub fn pick_version(name: &str, versions: &[String]) -> Result<Option<usize>> {
+    println!(
+        "{} Cached versions of {}:",
+        info_icon(),
+        package_name().apply_to(name)
+    );
+    for (i, version) in versions.iter().enumerate() {
+        let num = number().apply_to(format!("{:>2})", i + 1));
+        println!("{} {} {}", bullet(), num, version);
+    }
+    let prompt_text = format!(
+        "Version to install [1-{}] (Enter to cancel):",
+        versions.len()
+    );
+    print!("{} {} ", info_icon(), prompt().apply_to(&prompt_text));
+    let line = read_answer()?;
+    Ok(line
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=versions.len()).contains(n))
+        .map(|n| n - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(key: SortKey, repo_of: &HashMap<String, String>) -> Vec<&'static str> {
+        let mut rows = vec![
+            ("zlib-ng", "2.1.0-1", "2.2.0-1"),
+            ("bar", "1.0-1", "2.0-1"),
+            ("foo", "1.0-1", "1.0-2"),
+            ("abc", "1.0-1", "2.0-1"),
+        ];
+        sort_updates(&mut rows, key, repo_of, |r| (r.0, r.1, r.2));
+        rows.into_iter().map(|r| r.0).collect()
+    }
+
+    #[test]
+    fn sort_by_name() {
+        assert_eq!(
+            sorted(SortKey::Name, &HashMap::new()),
+            ["abc", "bar", "foo", "zlib-ng"]
+        );
+    }
+
+    #[test]
+    fn ties_fall_back_to_name() {
+        // abc and bar have the same major bump, zlib-ng a minor one, foo a pkgrel one
+        assert_eq!(
+            sorted(SortKey::VersionDelta, &HashMap::new()),
+            ["abc", "bar", "zlib-ng", "foo"]
+        );
+        let repo_of: HashMap<String, String> = [("zlib-ng", "core"), ("foo", "core")]
+            .map(|(n, r)| (n.to_string(), r.to_string()))
+            .into();
+        // AUR rows (no repo) first, each group by name
+        assert_eq!(
+            sorted(SortKey::Repo, &repo_of),
+            ["abc", "bar", "foo", "zlib-ng"]
+        );
+    }
+}